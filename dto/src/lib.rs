@@ -1,13 +1,37 @@
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
 
 pub mod carriers {
     // GMTP: Gajelas (or Goblok) Manga Transfer Protocol
-    // Version 0.0.0
+    // Version 0.4.0
     //
     // A dumb, whacky, freaky, skibidy attempt to make my own transfer protocol for transferring
     // manga data between my applications. (i know, it's dumb)
     // Please don't use it anywhere.
     //
+    // Framing:
+    // Every message (request or response) is a JSON payload prefixed by its length as a
+    // 4 byte unsigned integer. A client may keep the connection open and send as many
+    // requests as it wants on it (a session). Each request carries an `id` chosen by the
+    // client, and the response to it carries the same `id`. Responses may arrive in any
+    // order, so clients must match them by `id` instead of by position.
+    //
+    // Startup:
+    // A source that is spawned by a client prints a single line on its stdout once it
+    // accepts connections: `GMTP-READY tcp <address>:<port>` or `GMTP-READY unix <path>`.
+    // The client should wait for that line and connect to the address in it, instead of
+    // guessing when the source is up. Sources listening on TCP bind a free port unless
+    // they are told otherwise, so the line is the only way to know where they are.
+    //
+    // Handshake:
+    // The first thing a client should do is to send a PING. The source answers it with a
+    // `SourceInfo` telling its name, the GMTP versions it speaks and what it supports.
+    // Two versions are compatible when their major version is the same (or, while the
+    // major version is still 0, when their minor version is the same too). A source
+    // answers PING whatever the version of the request is, but it refuses every other
+    // command coming from an incompatible client.
+    //
     // Request:
     // `
     // GMTP-Version CRLF
@@ -27,23 +51,62 @@ pub mod carriers {
     // - SEARCH -> This command will ask server to search a manga (params: ["Manga Keyword"],[Filter])
     // - CHAPTERS -> This command will ask server to fetch a manga's chapter list (params: ["Manga's URL"],[Filter])
     // - PAGES -> This command will ask server to fetch a chapter's URL for its pages (params: ["Chapter's URL"])
+    // - BROWSE -> This command will ask server for a list of manga without a keyword, like the latest
+    //   updates (params: [BrowseMode],[Page],[Filter]). Only the modes the source advertises work.
+    // - LIST TAGS -> This command will ask server to list the tags manga can be filtered by, grouped
+    //   the way the site groups them (no params). Only sources that advertise it support it.
     //
-    // STATUS code meaning:
-    // - 200 -> This means "Ok"
-    // - 400 -> This means "The format is wrong, WTF is wrong with you!?"
-    // - 404 -> This means "No data"
-    // - 444 -> This means "Network problem"
+    // STATUS meaning:
+    // - Ok -> This means "Ok"
+    // - Error(ErrorCode) -> Something went wrong, the code tells what and `reason` tells
+    //   the details in a human readable way. See `ErrorCode` for the available codes.
 
     use super::*;
 
+    /// The GMTP version spoken by this crate
+    pub const VERSION: &str = "0.4.0";
+
+    /// Starts the line a source prints on stdout once it is ready to accept connections
+    pub const READY: &str = "GMTP-READY";
+
+    /// Checks whether two GMTP versions can talk with each other
+    pub fn is_compatible(ours: &str, theirs: &str) -> bool {
+        let parse = |v: &str| -> Option<(u32, u32)> {
+            let mut parts = v.split('.').map(|p| p.parse::<u32>().ok());
+            Some((parts.next()??, parts.next()??))
+        };
+
+        match (parse(ours), parse(theirs)) {
+            (Some((0, our_minor)), Some((0, their_minor))) => our_minor == their_minor,
+            (Some((our_major, _)), Some((their_major, _))) => our_major == their_major,
+            _ => false,
+        }
+    }
+
     #[derive(Serialize, Deserialize, Debug, Clone)]
     pub struct Request {
+        // Old clients don't send an id, they will always get 0 back
+        #[serde(default)]
+        pub id: u64,
         pub command: Command,
         pub version: String,
     }
 
+    impl Request {
+        pub fn new(id: u64, command: Command) -> Self {
+            Self {
+                id,
+                command,
+                version: VERSION.to_string(),
+            }
+        }
+    }
+
     #[derive(Serialize, Deserialize, Debug, Clone)]
     pub struct Response<T> {
+        // The id of the request this response answers to
+        #[serde(default)]
+        pub id: u64,
         pub status: Status,
         pub reason: String,
         pub source_name: String,
@@ -70,37 +133,383 @@ pub mod carriers {
         FetchChapter {
             chapter_identifier: String,
         },
+        ListTags,
+        Browse {
+            mode: BrowseMode,
+            page: u32,
+            filter: Filter,
+        },
+    }
+
+    impl Command {
+        pub fn kind(&self) -> CommandKind {
+            match self {
+                Command::Ping => CommandKind::Ping,
+                Command::Search { .. } => CommandKind::Search,
+                Command::FetchChapterList { .. } => CommandKind::FetchChapterList,
+                Command::FetchManga { .. } => CommandKind::FetchManga,
+                Command::FetchChapter { .. } => CommandKind::FetchChapter,
+                Command::ListTags => CommandKind::ListTags,
+                Command::Browse { .. } => CommandKind::Browse,
+            }
+        }
+    }
+
+    /// The name of a command, without its arguments
+    #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum CommandKind {
+        Ping,
+        Search,
+        FetchChapterList,
+        FetchManga,
+        FetchChapter,
+        ListTags,
+        Browse,
     }
 
+    /// The content of a PING response
     #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct SourceInfo {
+        pub name: String,
+        // Every GMTP version the source is able to speak
+        pub versions: Vec<String>,
+        pub commands: Vec<CommandKind>,
+        // Languages the source can serve manga in
+        pub languages: Vec<String>,
+        // The `Filter` fields the source takes into account, the rest is ignored
+        pub filters: Vec<String>,
+        // Filters only this source has, their values are sent in `Filter::fields`
+        #[serde(default)]
+        pub filter_fields: Vec<FilterField>,
+        // What can be browsed with BROWSE
+        #[serde(default)]
+        pub browse_modes: Vec<BrowseMode>,
+        // What manga can be sorted by
+        #[serde(default)]
+        pub sort_keys: Vec<SortKey>,
+    }
+
+    impl SourceInfo {
+        /// Returns the first version spoken by the source that is compatible with ours
+        pub fn compatible_version(&self) -> Option<&str> {
+            self.versions
+                .iter()
+                .find(|v| is_compatible(VERSION, v))
+                .map(|v| v.as_str())
+        }
+
+        pub fn supports(&self, command: CommandKind) -> bool {
+            self.commands.contains(&command)
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
     pub enum Status {
         Ok,
-        Error,
+        Error(ErrorCode),
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ErrorCode {
+        // The manga or chapter doesn't exist
+        NotFound,
+        // The format is wrong, WTF is wrong with you!?
+        BadRequest,
+        // The site answered with an unexpected HTTP status
+        Upstream(u16),
+        // The site is throttling us, try again after the specified seconds (if known)
+        RateLimited(Option<u64>),
+        // The site can't be reached
+        Network,
+        // The source doesn't support the command or the GMTP version
+        Unsupported,
+        // The source itself is broken
+        Internal,
+    }
+
+    impl std::fmt::Display for ErrorCode {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                ErrorCode::NotFound => write!(f, "Not found"),
+                ErrorCode::BadRequest => write!(f, "Bad request"),
+                ErrorCode::Upstream(status) => write!(f, "Site responded with HTTP {status}"),
+                ErrorCode::RateLimited(Some(secs)) => {
+                    write!(f, "Rate limited, retry in {secs} seconds")
+                }
+                ErrorCode::RateLimited(None) => write!(f, "Rate limited"),
+                ErrorCode::Network => write!(f, "Network problem"),
+                ErrorCode::Unsupported => write!(f, "Unsupported"),
+                ErrorCode::Internal => write!(f, "Internal source error"),
+            }
+        }
     }
 }
 
+/// What searches and chapter lists are narrowed down with. Every source understands these
+/// fields the same way, and ignores the ones it can't do anything with. Empty lists don't
+/// narrow anything down, the source picks its own defaults then.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Filter {
     pub language: String,
-    pub sort: Order,
+    pub sort: Sort,
+    /// Tags, like genres or themes, the manga must have. Tags are named the way the source
+    /// names them.
+    #[serde(default)]
+    pub included_tags: Vec<String>,
+    /// Whether the manga must have every included tag or only one of them
+    #[serde(default)]
+    pub tag_mode: TagMode,
+    /// Tags the manga must not have
+    #[serde(default)]
+    pub excluded_tags: Vec<String>,
+    #[serde(default)]
+    pub statuses: Vec<PublicationStatus>,
+    #[serde(default)]
+    pub content_ratings: Vec<ContentRating>,
+    #[serde(default)]
+    pub demographics: Vec<Demographic>,
+    /// The year the manga came out
+    #[serde(default)]
+    pub year: Option<u32>,
+    /// Languages the manga was first published in
+    #[serde(default)]
+    pub original_languages: Vec<String>,
+    /// Identifiers of people who wrote or drew the manga, in the source
+    #[serde(default)]
+    pub authors: Vec<String>,
+    /// Values of the filters the source described in its `SourceInfo`, by field id. Fields
+    /// without a value are left to the source.
+    #[serde(default)]
+    pub fields: HashMap<String, FieldValue>,
 }
 
 impl Default for Filter {
     fn default() -> Self {
         Self {
             language: String::from("en"),
-            sort: Order::Descending,
+            sort: Sort::new(Order::Descending),
+            included_tags: Vec::new(),
+            tag_mode: TagMode::default(),
+            excluded_tags: Vec::new(),
+            statuses: Vec::new(),
+            content_ratings: Vec::new(),
+            demographics: Vec::new(),
+            year: None,
+            original_languages: Vec::new(),
+            authors: Vec::new(),
+            fields: HashMap::new(),
         }
     }
 }
 
+/// Lists of manga that are browsed without a keyword
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrowseMode {
+    /// Manga with the newest chapters first
+    Latest,
+    /// Manga followed by the most people first
+    Popular,
+    TopRated,
+    /// Manga that were added to the source last first
+    RecentlyAdded,
+}
+
+impl BrowseMode {
+    pub const ALL: [Self; 4] = [
+        Self::Latest,
+        Self::Popular,
+        Self::TopRated,
+        Self::RecentlyAdded,
+    ];
+}
+
+/// A filter only some sources have. Sources describe them so clients can show them
+/// without knowing the source.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FilterField {
+    /// The key of the value in `Filter::fields`
+    pub id: String,
+    pub label: String,
+    pub kind: FieldKind,
+}
+
+/// How a filter field is chosen, and the value it takes
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum FieldKind {
+    /// One of the options, takes a `FieldValue::Select`
+    Select {
+        options: Vec<FieldOption>,
+        /// The option the source picks when there is no value
+        #[serde(default)]
+        default: Option<String>,
+    },
+    /// Any number of the options, takes a `FieldValue::MultiSelect`
+    MultiSelect { options: Vec<FieldOption> },
+    /// Options that are included, excluded or ignored, takes a `FieldValue::TriState`
+    TriState { options: Vec<FieldOption> },
+    /// Free text, takes a `FieldValue::Text`
+    Text,
+    /// Numbers between two bounds, takes a `FieldValue::Range`
+    Range {
+        #[serde(default)]
+        min: Option<f64>,
+        #[serde(default)]
+        max: Option<f64>,
+    },
+    /// One of the options to sort by and a direction, takes a `FieldValue::Sort`
+    Sort { options: Vec<FieldOption> },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FieldOption {
+    pub id: String,
+    pub label: String,
+}
+
+/// The value chosen for a filter field. Options are referred to by their id.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", content = "value")]
+pub enum FieldValue {
+    Select(String),
+    MultiSelect(Vec<String>),
+    TriState {
+        included: Vec<String>,
+        excluded: Vec<String>,
+    },
+    Text(String),
+    /// A missing bound doesn't limit the range on that side
+    Range {
+        min: Option<f64>,
+        max: Option<f64>,
+    },
+    Sort {
+        option: String,
+        order: Order,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TagMode {
+    /// The manga has every tag
+    #[default]
+    All,
+    /// The manga has at least one of the tags
+    Any,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentRating {
+    Safe,
+    Suggestive,
+    Erotica,
+    Pornographic,
+}
+
+impl ContentRating {
+    pub const ALL: [Self; 4] = [
+        Self::Safe,
+        Self::Suggestive,
+        Self::Erotica,
+        Self::Pornographic,
+    ];
+}
+
+/// The readers a manga is published for
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Demographic {
+    Shounen,
+    Shoujo,
+    Seinen,
+    Josei,
+    /// Manga that aren't published for any demographic in particular
+    None,
+}
+
+impl Demographic {
+    pub const ALL: [Self; 5] = [
+        Self::Shounen,
+        Self::Shoujo,
+        Self::Seinen,
+        Self::Josei,
+        Self::None,
+    ];
+}
+
+/// What results are sorted by, and in which direction
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(from = "SortRepr")]
+pub struct Sort {
+    /// What manga are sorted by, `None` leaves it to the source. Chapter lists are always
+    /// sorted by chapter.
+    pub key: Option<SortKey>,
+    pub order: Order,
+}
+
+impl Sort {
+    /// Sorts in that direction by whatever the source sorts by
+    pub fn new(order: Order) -> Self {
+        Self { key: None, order }
+    }
+}
+
+// Clients from before sort keys only send the direction
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SortRepr {
+    Order(Order),
+    Sort {
+        #[serde(default)]
+        key: Option<SortKey>,
+        order: Order,
+    },
+}
+
+impl From<SortRepr> for Sort {
+    fn from(value: SortRepr) -> Self {
+        match value {
+            SortRepr::Order(order) => Sort::new(order),
+            SortRepr::Sort { key, order } => Sort { key, order },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    /// How well the manga matches the keyword
+    Relevance,
+    Title,
+    Year,
+    /// When the manga was added to the source
+    CreatedAt,
+    UpdatedAt,
+    /// When the last chapter of the manga came out
+    LatestChapter,
+    /// How many people follow the manga
+    Follows,
+    Rating,
+}
+
+impl SortKey {
+    pub const ALL: [Self; 8] = [
+        Self::Relevance,
+        Self::Title,
+        Self::Year,
+        Self::CreatedAt,
+        Self::UpdatedAt,
+        Self::LatestChapter,
+        Self::Follows,
+        Self::Rating,
+    ];
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Order {
     Ascending,
     Descending,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PublicationStatus {
     Ongoing,
     Completed,
@@ -109,6 +518,39 @@ pub enum PublicationStatus {
     Unknown,
 }
 
+impl PublicationStatus {
+    /// The statuses manga can be filtered by
+    pub const KNOWN: [Self; 4] = [
+        Self::Ongoing,
+        Self::Completed,
+        Self::Hiatus,
+        Self::Cancelled,
+    ];
+}
+
+/// Tags of a source that belong together, like genres or themes
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TagGroup {
+    pub name: String,
+    pub tags: Vec<Tag>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Tag {
+    /// The name filters take in `included_tags` and `excluded_tags`
+    pub name: String,
+    /// The name of the tag in other languages, by language code
+    #[serde(default)]
+    pub localized_names: HashMap<String, String>,
+}
+
+impl Tag {
+    /// The name of the tag in the language, or its usual name if there is none
+    pub fn name_in(&self, language: &str) -> &str {
+        self.localized_names.get(language).unwrap_or(&self.name)
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Author {
     pub name: String,
@@ -156,6 +598,12 @@ pub struct ChapterListEntry {
     pub identifier: String,
     pub title: String,
     pub number: String,
+    // Not every source knows which volume a chapter belongs to
+    #[serde(default)]
+    pub volume: Option<String>,
+    /// The group that translated the chapter
+    #[serde(default)]
+    pub group: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -167,6 +615,38 @@ pub struct Chapter {
     pub manga_identifier: String,
     pub title: String,
     pub number: String,
+    #[serde(default)]
+    pub volume: Option<String>,
+    /// The group that translated the chapter
+    #[serde(default)]
+    pub group: Option<String>,
     pub language: String,
     pub page_urls: Vec<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::carriers::is_compatible;
+
+    #[test]
+    fn version_compatibility() {
+        assert!(is_compatible("0.2.0", "0.2.5"));
+        assert!(!is_compatible("0.2.0", "0.1.0"));
+        assert!(is_compatible("1.0.0", "1.4.2"));
+        assert!(!is_compatible("1.0.0", "2.0.0"));
+        assert!(!is_compatible("0.2.0", "garbage"));
+    }
+
+    #[test]
+    fn filters_from_older_clients() {
+        // Clients that only know the language and the sort order narrow nothing else down
+        let filter: super::Filter =
+            serde_json::from_str(r#"{"language": "fr", "sort": "Ascending"}"#).unwrap();
+
+        assert_eq!(filter.language, "fr");
+        assert_eq!(filter.sort, super::Sort::new(super::Order::Ascending));
+        assert!(filter.included_tags.is_empty() && filter.content_ratings.is_empty());
+        assert_eq!(filter.tag_mode, super::TagMode::All);
+        assert!(filter.fields.is_empty());
+    }
+}
@@ -0,0 +1,284 @@
+//! The settings of mager, shared by `mager-tui` and the `mager` CLI.
+//!
+//! Settings are read from `$XDG_CONFIG_HOME/mager/config.toml`, or
+//! `~/.config/mager/config.toml` when the variable isn't set. Settings left out of the file
+//! keep their default value, and every setting can be overridden with an environment
+//! variable named after it, e.g. `MAGER_DOWNLOAD_DIR` for `download_dir`.
+
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+use color_eyre::eyre::{eyre, Context, Result};
+use dto::Filter;
+use serde::{Deserialize, Serialize};
+
+use crate::template::{PathTemplate, DEFAULT_TEMPLATE};
+
+/// Every setting, in the order they are listed
+pub const KEYS: [&str; 6] = [
+    "download_dir",
+    "path_template",
+    "language",
+    "sources_dir",
+    "user_agent",
+    "image_protocol",
+];
+
+/// How the reader of `mager-tui` can show pages, `auto` picks one for the terminal
+pub const IMAGE_PROTOCOLS: [&str; 5] = ["auto", "kitty", "iterm2", "sixel", "halfblocks"];
+
+const DEFAULT_USER_AGENT: &str =
+    "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Where downloaded chapters go
+    pub download_dir: PathBuf,
+    /// How downloads are laid out in `download_dir`, see [`crate::template`]
+    pub path_template: String,
+    /// The language manga and chapters are requested in
+    pub language: String,
+    /// Where installed sources are
+    pub sources_dir: PathBuf,
+    /// The user agent pages are downloaded with
+    pub user_agent: String,
+    /// How the reader shows pages, one of [`IMAGE_PROTOCOLS`]
+    pub image_protocol: String,
+    /// Sources pick a free port of their own now. Still read, so that older config files
+    /// load.
+    #[serde(skip_serializing)]
+    port: Option<u16>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let home = home();
+
+        Self {
+            download_dir: home.join("Downloads/mager"),
+            path_template: DEFAULT_TEMPLATE.to_string(),
+            language: String::from("en"),
+            sources_dir: home.join(".local/mager/sources"),
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            image_protocol: String::from("auto"),
+            port: None,
+        }
+    }
+}
+
+impl Config {
+    /// Where the config file is
+    pub fn path() -> PathBuf {
+        let config_home = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .filter(|p| p.is_absolute())
+            .unwrap_or_else(|| home().join(".config"));
+
+        config_home.join("mager/config.toml")
+    }
+
+    /// Reads the config file, applies the environment overrides and checks the result
+    pub fn load() -> Result<Self> {
+        let mut config = Self::load_file(&Self::path())?;
+        config.apply_overrides(|var| env::var(var).ok())?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Reads the settings written in the config file, without the environment overrides.
+    /// A missing file is the same as an empty one.
+    pub fn load_file(path: &Path) -> Result<Self> {
+        let raw = match fs::read_to_string(path) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).wrap_err(format!("Can't read {}", path.display())),
+        };
+
+        let mut config: Self =
+            toml::from_str(&raw).wrap_err(format!("{} is not valid", path.display()))?;
+        config.download_dir = expand_home(&config.download_dir);
+        config.sources_dir = expand_home(&config.sources_dir);
+
+        Ok(config)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, toml::to_string_pretty(self)?)?;
+
+        Ok(())
+    }
+
+    /// The environment variable that overrides the setting
+    pub fn env_var(key: &str) -> String {
+        format!("MAGER_{}", key.to_uppercase())
+    }
+
+    /// Overrides settings with the values `lookup` finds for their environment variable
+    pub fn apply_overrides(&mut self, lookup: impl Fn(&str) -> Option<String>) -> Result<()> {
+        for key in KEYS {
+            let var = Self::env_var(key);
+            if let Some(value) = lookup(&var) {
+                self.set(key, &value)
+                    .wrap_err(format!("{var} is not valid"))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn get(&self, key: &str) -> Result<String> {
+        let value = match key {
+            "download_dir" => self.download_dir.display().to_string(),
+            "path_template" => self.path_template.clone(),
+            "language" => self.language.clone(),
+            "sources_dir" => self.sources_dir.display().to_string(),
+            "user_agent" => self.user_agent.clone(),
+            "image_protocol" => self.image_protocol.clone(),
+            _ => return Err(unknown_key(key)),
+        };
+
+        Ok(value)
+    }
+
+    /// Changes a setting, `value` being written the way `get` shows it. Paths may start
+    /// with `~`. The whole config should be validated afterwards.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "download_dir" => self.download_dir = expand_home(Path::new(value)),
+            "path_template" => self.path_template = value.to_string(),
+            "language" => self.language = value.to_string(),
+            "sources_dir" => self.sources_dir = expand_home(Path::new(value)),
+            "user_agent" => self.user_agent = value.to_string(),
+            "image_protocol" => self.image_protocol = value.to_string(),
+            _ => return Err(unknown_key(key)),
+        }
+
+        Ok(())
+    }
+
+    /// Checks that every setting makes sense
+    pub fn validate(&self) -> Result<()> {
+        if !self.download_dir.is_absolute() {
+            return Err(eyre!("download_dir has to be an absolute path"));
+        }
+        if !self.sources_dir.is_absolute() {
+            return Err(eyre!("sources_dir has to be an absolute path"));
+        }
+        PathTemplate::parse(&self.path_template).wrap_err("path_template is not valid")?;
+
+        let is_language_code = |c: char| c.is_ascii_alphanumeric() || c == '-';
+        if self.language.is_empty() || !self.language.chars().all(is_language_code) {
+            return Err(eyre!(
+                "language has to be a language code such as en or pt-br, not {:?}",
+                self.language
+            ));
+        }
+        if self.user_agent.trim().is_empty() {
+            return Err(eyre!("user_agent can't be empty"));
+        }
+        if !IMAGE_PROTOCOLS.contains(&self.image_protocol.as_str()) {
+            return Err(eyre!(
+                "image_protocol has to be one of {}",
+                IMAGE_PROTOCOLS.join(", ")
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// The filter requests start with, before the user changes anything
+    pub fn filter(&self) -> Filter {
+        Filter {
+            language: self.language.clone(),
+            ..Filter::default()
+        }
+    }
+}
+
+fn unknown_key(key: &str) -> color_eyre::eyre::Report {
+    eyre!("{key} is not a setting, try one of {}", KEYS.join(", "))
+}
+
+/// Where mager keeps its data, `$XDG_DATA_HOME/mager` or `~/.local/mager` when it isn't set
+pub fn data_dir() -> PathBuf {
+    env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .map(|p| p.join("mager"))
+        .unwrap_or_else(|| home().join(".local/mager"))
+}
+
+pub(crate) fn home() -> PathBuf {
+    env::var_os("HOME").map(PathBuf::from).unwrap_or_default()
+}
+
+fn expand_home(path: &Path) -> PathBuf {
+    match path.strip_prefix("~") {
+        Ok(rest) => home().join(rest),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn defaults_are_valid() {
+        let config = Config::default();
+        config.validate().unwrap();
+        assert_eq!(config.filter().language, "en");
+    }
+
+    #[test]
+    fn partial_config_file() {
+        let path = env::temp_dir().join(format!("mager-config-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            "download_dir = \"~/Comics\"\nlanguage = \"fr\"\nport = 7878\n",
+        )
+        .unwrap();
+
+        let config = Config::load_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.download_dir, home().join("Comics"));
+        assert_eq!(config.language, "fr");
+        assert_eq!(config.user_agent, Config::default().user_agent);
+    }
+
+    #[test]
+    fn get_and_set() {
+        let mut config = Config::default();
+
+        config.set("language", "fr").unwrap();
+        assert_eq!(config.get("language").unwrap(), "fr");
+        assert!(config.set("port", "9000").is_err());
+        assert!(config.set("colour", "blue").is_err());
+        assert!(config.get("colour").is_err());
+
+        config.set("path_template", "{manga}").unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn environment_overrides() {
+        let mut config = Config::default();
+        config
+            .apply_overrides(|var| (var == "MAGER_LANGUAGE").then(|| String::from("es-la")))
+            .unwrap();
+        assert_eq!(config.language, "es-la");
+
+        // There is no port setting anymore
+        let mut config = Config::default();
+        config
+            .apply_overrides(|var| (var == "MAGER_PORT").then(String::new))
+            .unwrap();
+        assert_eq!(config, Config::default());
+    }
+}
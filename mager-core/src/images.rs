@@ -0,0 +1,152 @@
+//! Downloading page images and recognizing their format
+
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{eyre, Result};
+use futures::StreamExt;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::ClientBuilder;
+use tokio::fs::{create_dir_all, rename, File};
+use tokio::io::AsyncWriteExt;
+use tracing::{event, info, warn, Level};
+
+use crate::template::add_extension;
+
+/// Guesses the extension of an image from its first bytes
+pub fn image_extension(bytes: &[u8]) -> Option<&'static str> {
+    match bytes {
+        [0xFF, 0xD8, 0xFF, ..] => Some("jpg"),
+        [0x89, b'P', b'N', b'G', ..] => Some("png"),
+        [b'G', b'I', b'F', b'8', ..] => Some("gif"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("webp"),
+        _ => None,
+    }
+}
+
+/// The extension of images served with this Content-Type
+pub fn media_type_extension(content_type: &str) -> Option<&'static str> {
+    // Parameters such as the charset don't matter here
+    let media_type = content_type.split(';').next()?.trim().to_ascii_lowercase();
+
+    match media_type.as_str() {
+        "image/jpeg" | "image/jpg" | "image/pjpeg" => Some("jpg"),
+        "image/png" => Some("png"),
+        "image/gif" => Some("gif"),
+        "image/webp" => Some("webp"),
+        _ => None,
+    }
+}
+
+/// How many bytes of an image it takes to tell its format
+const MAGIC_LENGTH: usize = 12;
+
+/// Downloads the image at `url` to `file_stem`, with the extension that matches the image
+/// format. The format is sniffed from the first bytes of the file, or from the Content-Type
+/// when the bytes don't tell. The file is written under a temporary name and renamed once
+/// complete, so a file that exists is always a complete one. The progress, from 0 to 1,
+/// is reported if the server tells the size of the file.
+///
+/// Returns where the image was saved.
+pub async fn download_image(
+    url: &str,
+    user_agent: &str,
+    file_stem: impl AsRef<Path>,
+    mut on_progress: impl FnMut(f32),
+) -> Result<PathBuf> {
+    let file_stem = file_stem.as_ref();
+    info!("Downloading image to {}", file_stem.display());
+    let client = ClientBuilder::new().user_agent(user_agent).build()?;
+
+    let response = client.get(url).send().await?.error_for_status()?;
+
+    let mut downloaded = 0 as f32;
+    let total_size = response.content_length().map(|l| l as f32);
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(media_type_extension);
+    // The first bytes of the image, enough to recognize every format mager knows
+    let mut magic = Vec::with_capacity(MAGIC_LENGTH);
+
+    if let Some(parent) = file_stem.parent() {
+        create_dir_all(parent).await?
+    }
+    let part_name = add_extension(file_stem, "part");
+    let mut file = File::create(&part_name).await?;
+
+    let mut stream = response.bytes_stream();
+    let mut attempt_counter = 1;
+    while let Some(chunk) = stream.next().await {
+        let Ok(chunk) = chunk else {
+            // this code will try to reestablish the connection if interrupted
+            warn!("Network interrrupted, retrying");
+            attempt_counter += 1;
+
+            if attempt_counter >= 20 {
+                event!(Level::ERROR, "Maximum retries reached");
+                return Err(eyre!("Maximum retries reached"));
+            }
+
+            let range_header = format!("bytes={}", downloaded as u64);
+            let response = client.get(url).header("Range", range_header).send().await?;
+            stream = response.bytes_stream();
+            continue;
+        };
+        attempt_counter = 1;
+        downloaded += chunk.len() as f32;
+
+        let missing = MAGIC_LENGTH.saturating_sub(magic.len());
+        magic.extend(chunk.iter().take(missing));
+
+        file.write_all(&chunk).await?;
+
+        if let Some(total_size) = total_size {
+            let progress = downloaded / total_size;
+            event!(Level::DEBUG, "downloading progress: {progress}");
+            on_progress(progress);
+        }
+    }
+    file.flush().await?;
+
+    let file_name = match image_extension(&magic).or(content_type) {
+        Some(extension) => add_extension(file_stem, extension),
+        None => {
+            warn!("{url} is not an image mager knows, it is saved without an extension");
+            file_stem.to_path_buf()
+        }
+    };
+    rename(&part_name, &file_name).await?;
+    info!("Download complete");
+
+    Ok(file_name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sniff_images() {
+        assert_eq!(
+            image_extension(&[0xFF, 0xD8, 0xFF, 0xE0, 0, 0]),
+            Some("jpg")
+        );
+        assert_eq!(image_extension(b"\x89PNG\r\n\x1a\n"), Some("png"));
+        assert_eq!(image_extension(b"GIF89a"), Some("gif"));
+        assert_eq!(image_extension(b"RIFF\x10\0\0\0WEBPVP8 "), Some("webp"));
+        assert_eq!(image_extension(b"RIFF\x10\0\0\0WAVE"), None);
+        assert_eq!(image_extension(b"<html>"), None);
+    }
+
+    #[test]
+    fn image_content_types() {
+        assert_eq!(media_type_extension("image/jpeg"), Some("jpg"));
+        assert_eq!(
+            media_type_extension("Image/PNG; charset=binary"),
+            Some("png")
+        );
+        assert_eq!(media_type_extension("image/webp"), Some("webp"));
+        assert_eq!(media_type_extension("text/html; charset=utf-8"), None);
+    }
+}
@@ -0,0 +1,9 @@
+//! The parts of mager that are shared by its clients, `mager-tui` and the `mager` CLI, so
+//! they behave the same way.
+
+pub mod config;
+pub mod images;
+pub mod library;
+pub mod range;
+pub mod template;
+pub mod update;
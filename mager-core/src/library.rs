@@ -0,0 +1,563 @@
+//! The manga the user follows, kept in a SQLite database.
+//!
+//! A manga is identified by its source and its identifier in that source. The details the
+//! source gave last time are cached, so the library can be shown without starting any
+//! source. The library also remembers which chapters of a followed manga are known, read
+//! and downloaded.
+//!
+//! Reading progress is kept for every manga that is read, followed or not: the last page
+//! read of each chapter, and the last chapter read of each manga.
+
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::Result;
+use dto::{Chapter, ChapterListEntry, Manga};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::config::data_dir;
+
+/// Every change made to the schema, in order. The database remembers how many of them it
+/// went through in its `user_version`.
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE manga (
+        source TEXT NOT NULL,
+        identifier TEXT NOT NULL,
+        title TEXT NOT NULL,
+        details TEXT NOT NULL,
+        added_at INTEGER NOT NULL DEFAULT (unixepoch()),
+        PRIMARY KEY (source, identifier)
+    );
+    CREATE TABLE chapters (
+        source TEXT NOT NULL,
+        manga_identifier TEXT NOT NULL,
+        identifier TEXT NOT NULL,
+        number TEXT NOT NULL,
+        title TEXT NOT NULL,
+        volume TEXT,
+        read INTEGER NOT NULL DEFAULT 0,
+        downloaded INTEGER NOT NULL DEFAULT 0,
+        PRIMARY KEY (source, identifier),
+        FOREIGN KEY (source, manga_identifier)
+            REFERENCES manga (source, identifier) ON DELETE CASCADE
+    );
+",
+    "
+    CREATE TABLE page_progress (
+        source TEXT NOT NULL,
+        chapter_identifier TEXT NOT NULL,
+        manga_identifier TEXT NOT NULL,
+        page INTEGER NOT NULL,
+        pages INTEGER NOT NULL,
+        PRIMARY KEY (source, chapter_identifier)
+    );
+    CREATE INDEX page_progress_manga ON page_progress (source, manga_identifier);
+    CREATE TABLE last_read (
+        source TEXT NOT NULL,
+        manga_identifier TEXT NOT NULL,
+        chapter_identifier TEXT NOT NULL,
+        read_at INTEGER NOT NULL DEFAULT (unixepoch()),
+        PRIMARY KEY (source, manga_identifier)
+    );
+",
+    "ALTER TABLE manga ADD COLUMN checked INTEGER NOT NULL DEFAULT 0;",
+];
+
+/// A manga in the library
+#[derive(Clone, Debug)]
+pub struct LibraryEntry {
+    pub source: String,
+    /// The details the source gave the last time they were fetched
+    pub manga: Manga,
+    /// How many chapters are known
+    pub chapters: usize,
+    pub unread: usize,
+    pub downloaded: usize,
+    /// Whether an update check took in the whole chapter list. Until then, the known
+    /// chapters are only the ones that were seen.
+    pub checked: bool,
+}
+
+/// A chapter of a manga in the library
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LibraryChapter {
+    pub identifier: String,
+    pub number: String,
+    pub title: String,
+    pub volume: Option<String>,
+    pub read: bool,
+    pub downloaded: bool,
+}
+
+/// How far a chapter was read
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReadingProgress {
+    pub chapter_identifier: String,
+    /// The last page read, starting from 0
+    pub page: usize,
+    /// How many pages the chapter has
+    pub pages: usize,
+}
+
+impl ReadingProgress {
+    /// Whether the last page of the chapter was reached
+    pub fn finished(&self) -> bool {
+        self.page + 1 >= self.pages
+    }
+}
+
+pub struct Library {
+    connection: Connection,
+}
+
+impl Library {
+    /// Where the library is kept
+    pub fn path() -> PathBuf {
+        data_dir().join("library.db")
+    }
+
+    /// Opens the library, creating it if it doesn't exist yet
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        Self::setup(Connection::open(path)?)
+    }
+
+    /// A library that only lives as long as it is open
+    pub fn open_in_memory() -> Result<Self> {
+        Self::setup(Connection::open_in_memory()?)
+    }
+
+    fn setup(connection: Connection) -> Result<Self> {
+        connection.pragma_update(None, "foreign_keys", true)?;
+
+        let version: usize = connection.pragma_query_value(None, "user_version", |r| r.get(0))?;
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            connection.execute_batch(&format!(
+                "BEGIN; {migration} PRAGMA user_version = {}; COMMIT;",
+                i + 1
+            ))?;
+        }
+
+        Ok(Self { connection })
+    }
+
+    /// Adds the manga to the library, or refreshes its cached details if it is already
+    /// there
+    pub fn add(&self, source: &str, manga: &Manga) -> Result<()> {
+        self.connection.execute(
+            "INSERT INTO manga (source, identifier, title, details) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (source, identifier)
+             DO UPDATE SET title = excluded.title, details = excluded.details",
+            params![
+                source,
+                manga.identifier,
+                manga.title,
+                serde_json::to_string(manga)?
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Removes the manga from the library, along with everything known about its chapters
+    pub fn remove(&self, source: &str, identifier: &str) -> Result<()> {
+        self.connection.execute(
+            "DELETE FROM manga WHERE source = ?1 AND identifier = ?2",
+            params![source, identifier],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn contains(&self, source: &str, identifier: &str) -> Result<bool> {
+        let found = self
+            .connection
+            .query_row(
+                "SELECT 1 FROM manga WHERE source = ?1 AND identifier = ?2",
+                params![source, identifier],
+                |_| Ok(()),
+            )
+            .optional()?;
+
+        Ok(found.is_some())
+    }
+
+    /// Every manga in the library, sorted by title
+    pub fn entries(&self) -> Result<Vec<LibraryEntry>> {
+        let mut statement = self.connection.prepare(
+            "SELECT m.source, m.details, count(c.identifier),
+                    count(c.identifier) - coalesce(sum(c.read), 0),
+                    coalesce(sum(c.downloaded), 0), m.checked
+             FROM manga m
+             LEFT JOIN chapters c
+                 ON c.source = m.source AND c.manga_identifier = m.identifier
+             GROUP BY m.source, m.identifier
+             ORDER BY m.title COLLATE NOCASE, m.source",
+        )?;
+
+        let rows = statement.query_map([], |r| {
+            Ok((
+                r.get::<_, String>(0)?,
+                r.get::<_, String>(1)?,
+                r.get::<_, usize>(2)?,
+                r.get::<_, usize>(3)?,
+                r.get::<_, usize>(4)?,
+                r.get::<_, bool>(5)?,
+            ))
+        })?;
+
+        let mut entries = Vec::new();
+        for row in rows {
+            let (source, details, chapters, unread, downloaded, checked) = row?;
+            entries.push(LibraryEntry {
+                source,
+                manga: serde_json::from_str(&details)?,
+                chapters,
+                unread,
+                downloaded,
+                checked,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    /// Remembers the chapters of a manga in the library, and refreshes the ones that are
+    /// already known. Chapters of a manga that isn't in the library are ignored.
+    ///
+    /// Returns how many chapters weren't known before.
+    pub fn add_chapters(
+        &mut self,
+        source: &str,
+        manga_identifier: &str,
+        chapters: &[ChapterListEntry],
+    ) -> Result<usize> {
+        if !self.contains(source, manga_identifier)? {
+            return Ok(0);
+        }
+
+        let transaction = self.connection.transaction()?;
+        let mut added = 0;
+        {
+            let mut statement = transaction.prepare(
+                "INSERT INTO chapters (source, manga_identifier, identifier, number, title, volume)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                 ON CONFLICT (source, identifier) DO UPDATE
+                 SET number = excluded.number, title = excluded.title, volume = excluded.volume",
+            )?;
+            let mut known = transaction
+                .prepare("SELECT 1 FROM chapters WHERE source = ?1 AND identifier = ?2")?;
+
+            for chapter in chapters {
+                let is_known = known.exists(params![source, chapter.identifier])?;
+                statement.execute(params![
+                    source,
+                    manga_identifier,
+                    chapter.identifier,
+                    chapter.number,
+                    chapter.title,
+                    chapter.volume
+                ])?;
+                if !is_known {
+                    added += 1;
+                }
+            }
+        }
+        transaction.commit()?;
+
+        Ok(added)
+    }
+
+    /// Remembers that an update check took in the whole chapter list of the manga
+    pub fn set_checked(&self, source: &str, identifier: &str) -> Result<()> {
+        self.connection.execute(
+            "UPDATE manga SET checked = 1 WHERE source = ?1 AND identifier = ?2",
+            params![source, identifier],
+        )?;
+
+        Ok(())
+    }
+
+    /// The known chapters of the manga, in no particular order
+    pub fn chapters(&self, source: &str, manga_identifier: &str) -> Result<Vec<LibraryChapter>> {
+        let mut statement = self.connection.prepare(
+            "SELECT identifier, number, title, volume, read, downloaded FROM chapters
+             WHERE source = ?1 AND manga_identifier = ?2",
+        )?;
+
+        let chapters = statement
+            .query_map(params![source, manga_identifier], |r| {
+                Ok(LibraryChapter {
+                    identifier: r.get(0)?,
+                    number: r.get(1)?,
+                    title: r.get(2)?,
+                    volume: r.get(3)?,
+                    read: r.get(4)?,
+                    downloaded: r.get(5)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(chapters)
+    }
+
+    /// Marks a known chapter as read or unread. A chapter marked as unread is read again
+    /// from its first page.
+    pub fn set_read(&self, source: &str, chapter_identifier: &str, read: bool) -> Result<()> {
+        self.connection.execute(
+            "UPDATE chapters SET read = ?3 WHERE source = ?1 AND identifier = ?2",
+            params![source, chapter_identifier, read],
+        )?;
+
+        if !read {
+            self.connection.execute(
+                "DELETE FROM page_progress WHERE source = ?1 AND chapter_identifier = ?2",
+                params![source, chapter_identifier],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Remembers that `page` of the chapter was just read, which makes it the last chapter
+    /// read of its manga. Reaching the last page marks the chapter as read.
+    pub fn save_progress(&self, source: &str, chapter: &Chapter, page: usize) -> Result<()> {
+        let pages = chapter.page_urls.len();
+        self.connection.execute(
+            "INSERT INTO page_progress (source, chapter_identifier, manga_identifier, page, pages)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (source, chapter_identifier)
+             DO UPDATE SET page = excluded.page, pages = excluded.pages",
+            params![
+                source,
+                chapter.identifier,
+                chapter.manga_identifier,
+                page,
+                pages
+            ],
+        )?;
+        self.connection.execute(
+            "INSERT INTO last_read (source, manga_identifier, chapter_identifier)
+             VALUES (?1, ?2, ?3)
+             ON CONFLICT (source, manga_identifier)
+             DO UPDATE SET chapter_identifier = excluded.chapter_identifier,
+                           read_at = excluded.read_at",
+            params![source, chapter.manga_identifier, chapter.identifier],
+        )?;
+
+        if page + 1 >= pages {
+            self.set_read(source, &chapter.identifier, true)?;
+        }
+
+        Ok(())
+    }
+
+    /// How far every chapter of the manga that was opened was read, in no particular order
+    pub fn progress(&self, source: &str, manga_identifier: &str) -> Result<Vec<ReadingProgress>> {
+        let mut statement = self.connection.prepare(
+            "SELECT chapter_identifier, page, pages FROM page_progress
+             WHERE source = ?1 AND manga_identifier = ?2",
+        )?;
+
+        let progress = statement
+            .query_map(params![source, manga_identifier], |r| {
+                Ok(ReadingProgress {
+                    chapter_identifier: r.get(0)?,
+                    page: r.get(1)?,
+                    pages: r.get(2)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(progress)
+    }
+
+    /// The identifier of the chapter of the manga that was read last
+    pub fn last_read(&self, source: &str, manga_identifier: &str) -> Result<Option<String>> {
+        let chapter = self
+            .connection
+            .query_row(
+                "SELECT chapter_identifier FROM last_read
+                 WHERE source = ?1 AND manga_identifier = ?2",
+                params![source, manga_identifier],
+                |r| r.get(0),
+            )
+            .optional()?;
+
+        Ok(chapter)
+    }
+
+    /// Marks the chapter as downloaded, if its manga is in the library
+    pub fn mark_downloaded(&mut self, source: &str, chapter: &Chapter) -> Result<()> {
+        let entry = ChapterListEntry {
+            identifier: chapter.identifier.clone(),
+            title: chapter.title.clone(),
+            number: chapter.number.clone(),
+            volume: chapter.volume.clone(),
+            group: chapter.group.clone(),
+        };
+        self.add_chapters(source, &chapter.manga_identifier, &[entry])?;
+
+        self.connection.execute(
+            "UPDATE chapters SET downloaded = 1 WHERE source = ?1 AND identifier = ?2",
+            params![source, chapter.identifier],
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use dto::PublicationStatus;
+
+    fn manga(identifier: &str, title: &str) -> Manga {
+        Manga {
+            identifier: identifier.to_string(),
+            title: title.to_string(),
+            authors: Vec::new(),
+            original_language: String::from("ja"),
+            language: String::from("en"),
+            description: String::new(),
+            status: PublicationStatus::Ongoing,
+        }
+    }
+
+    fn chapter(identifier: &str, number: &str) -> ChapterListEntry {
+        ChapterListEntry {
+            identifier: identifier.to_string(),
+            title: String::new(),
+            number: number.to_string(),
+            volume: None,
+            group: None,
+        }
+    }
+
+    fn chapter_detail(identifier: &str, pages: usize) -> Chapter {
+        Chapter {
+            identifier: identifier.to_string(),
+            manga_identifier: String::from("a"),
+            title: String::new(),
+            number: String::from("1"),
+            volume: None,
+            group: None,
+            language: String::from("en"),
+            page_urls: vec![String::new(); pages],
+        }
+    }
+
+    #[test]
+    fn add_and_remove_manga() {
+        let library = Library::open_in_memory().unwrap();
+
+        library.add("mangadex", &manga("b", "Blame!")).unwrap();
+        library.add("mangadex", &manga("a", "Akira")).unwrap();
+        library.add("mangadex", &manga("a", "AKIRA")).unwrap();
+        assert!(library.contains("mangadex", "a").unwrap());
+        assert!(!library.contains("other", "a").unwrap());
+
+        let titles: Vec<String> = library
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.manga.title)
+            .collect();
+        assert_eq!(titles, ["AKIRA", "Blame!"]);
+
+        library.remove("mangadex", "a").unwrap();
+        assert_eq!(library.entries().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn chapter_states() {
+        let mut library = Library::open_in_memory().unwrap();
+        let chapters = [chapter("c1", "1"), chapter("c2", "2")];
+
+        // Only chapters of followed manga are kept
+        assert_eq!(library.add_chapters("mangadex", "a", &chapters).unwrap(), 0);
+
+        library.add("mangadex", &manga("a", "Akira")).unwrap();
+        assert_eq!(library.add_chapters("mangadex", "a", &chapters).unwrap(), 2);
+        assert_eq!(library.add_chapters("mangadex", "a", &chapters).unwrap(), 0);
+
+        library.set_read("mangadex", "c1", true).unwrap();
+        let downloaded = Chapter {
+            identifier: String::from("c3"),
+            manga_identifier: String::from("a"),
+            title: String::new(),
+            number: String::from("3"),
+            volume: None,
+            group: None,
+            language: String::from("en"),
+            page_urls: Vec::new(),
+        };
+        library.mark_downloaded("mangadex", &downloaded).unwrap();
+
+        let entry = &library.entries().unwrap()[0];
+        assert_eq!((entry.chapters, entry.unread, entry.downloaded), (3, 2, 1));
+        assert!(!entry.checked);
+        library.set_checked("mangadex", "a").unwrap();
+        assert!(library.entries().unwrap()[0].checked);
+
+        let c1 = library
+            .chapters("mangadex", "a")
+            .unwrap()
+            .into_iter()
+            .find(|c| c.identifier == "c1")
+            .unwrap();
+        assert!(c1.read && !c1.downloaded);
+
+        // Forgetting the manga forgets its chapters
+        library.remove("mangadex", "a").unwrap();
+        library.add("mangadex", &manga("a", "Akira")).unwrap();
+        assert!(library.chapters("mangadex", "a").unwrap().is_empty());
+    }
+
+    #[test]
+    fn reading_progress() {
+        let mut library = Library::open_in_memory().unwrap();
+        library.add("mangadex", &manga("a", "Akira")).unwrap();
+        library
+            .add_chapters("mangadex", "a", &[chapter("c1", "1"), chapter("c2", "2")])
+            .unwrap();
+
+        // Progress is kept whether the manga is followed or not
+        library
+            .save_progress("mangadex", &chapter_detail("c1", 10), 9)
+            .unwrap();
+        library
+            .save_progress("mangadex", &chapter_detail("c2", 10), 3)
+            .unwrap();
+        library
+            .save_progress("other", &chapter_detail("x", 5), 1)
+            .unwrap();
+
+        let mut progress = library.progress("mangadex", "a").unwrap();
+        progress.sort_by(|a, b| a.chapter_identifier.cmp(&b.chapter_identifier));
+        assert_eq!(progress.len(), 2);
+        assert!(progress[0].finished() && !progress[1].finished());
+        assert_eq!(progress[1].page, 3);
+        assert_eq!(
+            library.last_read("mangadex", "a").unwrap().as_deref(),
+            Some("c2")
+        );
+        assert_eq!(
+            library.last_read("other", "a").unwrap().as_deref(),
+            Some("x")
+        );
+
+        // Reaching the last page marks the chapter as read, unmarking it starts it over
+        let read = |library: &Library| {
+            let chapters = library.chapters("mangadex", "a").unwrap();
+            chapters.iter().filter(|c| c.read).count()
+        };
+        assert_eq!(read(&library), 1);
+        library.set_read("mangadex", "c1", false).unwrap();
+        assert_eq!(read(&library), 0);
+        assert_eq!(library.progress("mangadex", "a").unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,115 @@
+//! Chapter ranges given on the command line, like `10-20`, `5`, `12-` or `-3`.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Chapter numbers between two bounds, both included. A missing bound doesn't limit the
+/// range on that side.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ChapterRange {
+    pub start: Option<f32>,
+    pub end: Option<f32>,
+}
+
+impl ChapterRange {
+    /// Whether the chapter number is in the range. Chapters without a number, like
+    /// oneshots, are only in a range without bounds.
+    pub fn contains(&self, number: &str) -> bool {
+        let Ok(number) = number.trim().parse::<f32>() else {
+            return self.start.is_none() && self.end.is_none();
+        };
+
+        self.start.is_none_or(|start| number >= start) && self.end.is_none_or(|end| number <= end)
+    }
+}
+
+impl FromStr for ChapterRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bound = |b: &str| -> Result<Option<f32>, String> {
+            match b.trim() {
+                "" => Ok(None),
+                b => b
+                    .parse::<f32>()
+                    .map(Some)
+                    .map_err(|_| format!("{b} is not a chapter number")),
+            }
+        };
+
+        let range = match s.split_once('-') {
+            Some((start, end)) => Self {
+                start: bound(start)?,
+                end: bound(end)?,
+            },
+            None => {
+                let number = bound(s)?.ok_or("The range is empty")?;
+                Self {
+                    start: Some(number),
+                    end: Some(number),
+                }
+            }
+        };
+
+        if let (Some(start), Some(end)) = (range.start, range.end) {
+            if start > end {
+                return Err(format!("The range {s} ends before it starts"));
+            }
+        }
+
+        Ok(range)
+    }
+}
+
+impl fmt::Display for ChapterRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.start, self.end) {
+            (Some(start), Some(end)) if start == end => write!(f, "{start}"),
+            (start, end) => {
+                if let Some(start) = start {
+                    write!(f, "{start}")?;
+                }
+                write!(f, "-")?;
+                if let Some(end) = end {
+                    write!(f, "{end}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_ranges() {
+        let range = |s: &str| s.parse::<ChapterRange>();
+
+        assert_eq!(
+            range("10-20"),
+            Ok(ChapterRange {
+                start: Some(10.0),
+                end: Some(20.0)
+            })
+        );
+        assert_eq!(range("12-").unwrap().end, None);
+        assert_eq!(range("-3").unwrap().start, None);
+        assert_eq!(range("5").unwrap().to_string(), "5");
+        assert_eq!(range("1.5-3").unwrap().to_string(), "1.5-3");
+        assert!(range("20-10").is_err());
+        assert!(range("a-b").is_err());
+        assert!(range("").is_err());
+    }
+
+    #[test]
+    fn contains_chapters() {
+        let range: ChapterRange = "10-20".parse().unwrap();
+
+        assert!(range.contains("10") && range.contains("15.5") && range.contains("20"));
+        assert!(!range.contains("9.9") && !range.contains("21"));
+        assert!(!range.contains("Oneshot"));
+        assert!(ChapterRange::default().contains("Oneshot"));
+    }
+}
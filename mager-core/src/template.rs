@@ -0,0 +1,517 @@
+//! Where downloaded chapters go.
+//!
+//! The layout of the download directory is described by a path template such as
+//! `{source}/{manga}/Volume {volume}/#{chapter} - {chapter_title}/{page}`. Every component
+//! of the template is rendered and sanitised on its own, so a title containing `/` or `:`
+//! stays in a single, valid file name. Components whose placeholders are all empty, like
+//! `Volume {volume}` for a chapter without volume, are left out.
+
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use color_eyre::eyre::{eyre, Report, Result};
+use dto::{Chapter, Manga};
+
+use crate::config::Config;
+
+/// The layout mager has always used
+pub const DEFAULT_TEMPLATE: &str = "{manga}/#{chapter} - {chapter_title}/{page}";
+
+/// Name of the file that tells which chapter a folder belongs to
+const OWNER_FILE: &str = ".mager-chapter";
+
+/// Longest file name mager writes, in bytes. Most filesystems allow 255, some room is
+/// left for extensions and collision suffixes.
+const MAX_NAME_LENGTH: usize = 200;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    Source,
+    Manga,
+    Volume,
+    Chapter,
+    ChapterTitle,
+    Lang,
+    Group,
+    Page,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Self> {
+        let field = match name {
+            "source" => Self::Source,
+            "manga" => Self::Manga,
+            "volume" => Self::Volume,
+            "chapter" => Self::Chapter,
+            "chapter_title" => Self::ChapterTitle,
+            "lang" => Self::Lang,
+            "group" => Self::Group,
+            "page" => Self::Page,
+            _ => return None,
+        };
+        Some(field)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Field(Field),
+}
+
+/// What the placeholders of a template are replaced with
+#[derive(Clone, Debug, Default)]
+pub struct PathValues {
+    pub source: String,
+    pub manga: String,
+    pub volume: Option<String>,
+    pub chapter: String,
+    pub chapter_title: String,
+    pub lang: String,
+    pub group: Option<String>,
+}
+
+impl PathValues {
+    pub fn new(source: &str, manga: &Manga, chapter: &Chapter) -> Self {
+        Self {
+            source: source.to_string(),
+            manga: manga.title.clone(),
+            volume: chapter.volume.clone(),
+            chapter: chapter.number.clone(),
+            chapter_title: chapter.title.clone(),
+            lang: chapter.language.clone(),
+            group: chapter.group.clone(),
+        }
+    }
+
+    fn get(&self, field: Field) -> &str {
+        match field {
+            Field::Source => &self.source,
+            Field::Manga => &self.manga,
+            Field::Volume => self.volume.as_deref().unwrap_or_default(),
+            Field::Chapter => &self.chapter,
+            Field::ChapterTitle => &self.chapter_title,
+            Field::Lang => &self.lang,
+            Field::Group => self.group.as_deref().unwrap_or_default(),
+            // Pages are rendered on their own
+            Field::Page => "",
+        }
+    }
+}
+
+/// A parsed path template. Placeholders are written between braces, `{{` and `}}` are
+/// literal braces. The last component names the page files and has to end with `{page}`,
+/// which is the page number padded with zeros. The extension is added to it once the
+/// image format is known.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PathTemplate {
+    raw: String,
+    components: Vec<Vec<Part>>,
+}
+
+impl PathTemplate {
+    pub fn parse(raw: &str) -> Result<Self> {
+        if raw.starts_with('/') {
+            return Err(eyre!(
+                "The path template is relative to the download directory, it can't start with /"
+            ));
+        }
+
+        let components = raw
+            .split('/')
+            .map(parse_component)
+            .collect::<Result<Vec<_>>>()?;
+
+        let Some((page, folders)) = components.split_last() else {
+            return Err(eyre!("The path template is empty"));
+        };
+        if folders.is_empty() {
+            return Err(eyre!(
+                "The path template needs at least one folder before the page name"
+            ));
+        }
+        if page.last() != Some(&Part::Field(Field::Page)) {
+            return Err(eyre!("The path template has to end with {{page}}"));
+        }
+        if page
+            .iter()
+            .filter(|p| **p == Part::Field(Field::Page))
+            .count()
+            > 1
+            || folders
+                .iter()
+                .flatten()
+                .any(|p| *p == Part::Field(Field::Page))
+        {
+            return Err(eyre!(
+                "{{page}} can only be used once, at the end of the template"
+            ));
+        }
+        for component in components.iter() {
+            if let [Part::Text(text)] = component.as_slice() {
+                if text == "." || text == ".." {
+                    return Err(eyre!("The path template can't contain . or .. folders"));
+                }
+            }
+        }
+
+        Ok(Self {
+            raw: raw.to_string(),
+            components,
+        })
+    }
+
+    /// The folder the pages of the chapter go in, relative to the download directory
+    pub fn chapter_folder(&self, values: &PathValues) -> PathBuf {
+        let (_, folders) = self.components.split_last().unwrap();
+
+        folders
+            .iter()
+            .filter_map(|component| render(component, values))
+            .collect()
+    }
+
+    /// The name of a page file, without its extension, which [`add_extension`] adds.
+    /// `page` is counted from 1.
+    pub fn page_name(&self, values: &PathValues, page: usize, page_count: usize) -> String {
+        let width = page_count.to_string().len().max(3);
+        let (component, _) = self.components.split_last().unwrap();
+
+        let mut name = String::new();
+        for part in component[..component.len() - 1].iter() {
+            match part {
+                Part::Text(text) => name.push_str(text),
+                Part::Field(field) => name.push_str(values.get(*field)),
+            }
+        }
+        sanitize(&format!("{name}{page:0width$}"))
+    }
+}
+
+impl Default for PathTemplate {
+    fn default() -> Self {
+        Self::parse(DEFAULT_TEMPLATE).unwrap()
+    }
+}
+
+impl FromStr for PathTemplate {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s)
+    }
+}
+
+impl Display for PathTemplate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.raw)
+    }
+}
+
+/// Where downloads go on disk: the download directory, laid out by a path template
+#[derive(Clone, Debug)]
+pub struct DownloadLayout {
+    pub directory: PathBuf,
+    pub template: PathTemplate,
+}
+
+impl DownloadLayout {
+    pub fn from_config(config: &Config) -> Result<Self> {
+        Ok(Self {
+            directory: config.download_dir.clone(),
+            template: PathTemplate::parse(&config.path_template)?,
+        })
+    }
+
+    /// Creates the folder the pages of the chapter go in. `owner` identifies the chapter,
+    /// e.g. its source and identifier. When another chapter already uses the folder the
+    /// template points to, a suffix is added instead of mixing their pages. The folder of
+    /// an interrupted download is reused.
+    pub fn chapter_folder(&self, values: &PathValues, owner: &str) -> Result<PathBuf> {
+        let path = self.directory.join(self.template.chapter_folder(values));
+        let folder = unique_path(&path, None, |candidate| {
+            match fs::read_to_string(candidate.join(OWNER_FILE)) {
+                Ok(o) => o == owner,
+                Err(_) => !candidate.exists(),
+            }
+        });
+
+        fs::create_dir_all(&folder)?;
+        fs::write(folder.join(OWNER_FILE), owner)?;
+        Ok(folder)
+    }
+
+    /// Where to export a chapter downloaded in `folder`, next to it. Files that are
+    /// already there are never overwritten.
+    pub fn chapter_file(&self, folder: &Path, extension: &str) -> PathBuf {
+        unique_path(folder, Some(extension), |p| !p.exists())
+    }
+
+    /// Where to export a whole volume whose chapters are downloaded in `folder`. The file
+    /// goes in the folder that holds the chapter folders.
+    pub fn volume_file(&self, folder: &Path, volume: &str, extension: &str) -> PathBuf {
+        let parent = folder.parent().unwrap_or(&self.directory);
+        let path = parent.join(sanitize(&format!("Volume {volume}")));
+        unique_path(&path, Some(extension), |p| !p.exists())
+    }
+}
+
+fn parse_component(raw: &str) -> Result<Vec<Part>> {
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut chars = raw.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            }
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => return Err(eyre!("{{{name} is never closed in the path template")),
+                    }
+                }
+                let field = Field::parse(&name)
+                    .ok_or(eyre!("{{{name}}} is not a placeholder mager knows"))?;
+
+                if !text.is_empty() {
+                    parts.push(Part::Text(std::mem::take(&mut text)));
+                }
+                parts.push(Part::Field(field));
+            }
+            '}' => return Err(eyre!("Unmatched }} in the path template, use }}}} instead")),
+            c => text.push(c),
+        }
+    }
+
+    if !text.is_empty() {
+        parts.push(Part::Text(text));
+    }
+    if parts.is_empty() {
+        return Err(eyre!("The path template has an empty folder name"));
+    }
+    Ok(parts)
+}
+
+/// Renders a folder name, `None` if it should be left out
+fn render(parts: &[Part], values: &PathValues) -> Option<String> {
+    let mut name = String::new();
+    let mut has_fields = false;
+    let mut has_values = false;
+
+    for part in parts {
+        match part {
+            Part::Text(text) => name.push_str(text),
+            Part::Field(field) => {
+                let value = values.get(*field);
+                has_fields = true;
+                has_values |= !value.is_empty();
+                name.push_str(value);
+            }
+        }
+    }
+
+    if has_fields && !has_values {
+        return None;
+    }
+    Some(sanitize(&name)).filter(|n| !n.is_empty())
+}
+
+/// Turns text into a name that is valid as a single file name on Linux, macOS and Windows.
+/// Separators and characters Windows reserves are replaced with `_`, and leading dots are
+/// replaced so the file isn't hidden. The name is empty if nothing is left of the text.
+pub fn sanitize(text: &str) -> String {
+    let replaced: String = text
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+
+    let mut name = trim(&replaced);
+    if name.len() > MAX_NAME_LENGTH {
+        let mut end = MAX_NAME_LENGTH;
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        name = trim(&name[..end]);
+    }
+
+    if let Some(rest) = name.strip_prefix('.') {
+        name = format!("_{rest}");
+    }
+    name
+}
+
+/// Windows doesn't allow names ending with dots or spaces
+fn trim(name: &str) -> String {
+    name.trim().trim_end_matches(['.', ' ']).to_string()
+}
+
+/// `path` with the extension added. Unlike [`Path::with_extension`], what follows a dot
+/// in the name, as in chapter `1.5`, is kept.
+pub fn add_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(format!(".{extension}"));
+    PathBuf::from(path)
+}
+
+/// Finds where to put something that would go at `path`, when something else might be
+/// there already. `path`, `path (2)`, `path (3)`… are tried in order and the first one that
+/// `is_free` accepts is returned. The extension, if any, is added after the suffix.
+pub fn unique_path(
+    path: &Path,
+    extension: Option<&str>,
+    is_free: impl Fn(&Path) -> bool,
+) -> PathBuf {
+    let extension = extension.map(|e| format!(".{e}")).unwrap_or_default();
+
+    for n in 1.. {
+        let suffix = if n == 1 {
+            String::new()
+        } else {
+            format!(" ({n})")
+        };
+
+        let mut candidate = path.as_os_str().to_owned();
+        candidate.push(format!("{suffix}{extension}"));
+        let candidate = PathBuf::from(candidate);
+
+        if is_free(&candidate) {
+            return candidate;
+        }
+    }
+
+    unreachable!()
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use super::*;
+
+    fn values() -> PathValues {
+        PathValues {
+            source: String::from("MangaDex"),
+            manga: String::from("Re:Zero / Arc 1"),
+            volume: None,
+            chapter: String::from("1.5"),
+            chapter_title: String::from("The End?"),
+            lang: String::from("en"),
+            group: Some(String::from("Scans")),
+        }
+    }
+
+    #[test]
+    fn default_layout() {
+        let template = PathTemplate::default();
+        assert_eq!(
+            template.chapter_folder(&values()),
+            PathBuf::from("Re_Zero _ Arc 1/#1.5 - The End_")
+        );
+        assert_eq!(template.page_name(&values(), 7, 20), "007");
+    }
+
+    #[test]
+    fn every_placeholder() {
+        let template = PathTemplate::parse(
+            "{source}/{manga}/Volume {volume}/{lang}/[{group}] {chapter} {chapter_title}/p{{{page}",
+        )
+        .unwrap();
+
+        // The volume folder is left out, as the chapter has no volume
+        assert_eq!(
+            template.chapter_folder(&values()),
+            PathBuf::from("MangaDex/Re_Zero _ Arc 1/en/[Scans] 1.5 The End_")
+        );
+        assert_eq!(template.page_name(&values(), 12, 1000), "p{0012");
+    }
+
+    #[test]
+    fn dotted_chapter_pages() {
+        let template = PathTemplate::parse("{manga}/{chapter}-{page}").unwrap();
+        let folder = Path::new("/downloads/Re_Zero");
+
+        let pages: Vec<PathBuf> = (1..=2)
+            .map(|page| folder.join(template.page_name(&values(), page, 2)))
+            .map(|stem| add_extension(&stem, "jpg"))
+            .collect();
+        assert_eq!(pages[0], folder.join("1.5-001.jpg"));
+        assert_eq!(pages[1], folder.join("1.5-002.jpg"));
+    }
+
+    #[test]
+    fn invalid_templates() {
+        assert!(PathTemplate::parse("/{manga}/{page}").is_err());
+        assert!(PathTemplate::parse("{page}").is_err());
+        assert!(PathTemplate::parse("{manga}/{chapter}").is_err());
+        assert!(PathTemplate::parse("{manga}/{page} {chapter}").is_err());
+        assert!(PathTemplate::parse("{manga}/{title}/{page}").is_err());
+        assert!(PathTemplate::parse("{manga}//{page}").is_err());
+        assert!(PathTemplate::parse("{manga}/../{page}").is_err());
+        assert!(PathTemplate::parse("{manga}}/{page}").is_err());
+        assert!(PathTemplate::parse("{manga/{page}").is_err());
+        assert!(PathTemplate::parse("{manga}/{page").is_err());
+    }
+
+    #[test]
+    fn sanitize_names() {
+        assert_eq!(sanitize("a/b\\c:d*e?f\"g<h>i|j"), "a_b_c_d_e_f_g_h_i_j");
+        assert_eq!(sanitize("  Vol. 3 ... "), "Vol. 3");
+        assert_eq!(sanitize(".hidden"), "_hidden");
+        assert_eq!(sanitize(".."), "");
+        assert_eq!(sanitize(&"é".repeat(150)).len(), MAX_NAME_LENGTH);
+    }
+
+    #[test]
+    fn collisions() {
+        let taken = [PathBuf::from("a/b.cbz"), PathBuf::from("a/b (2).cbz")];
+        let path = unique_path(Path::new("a/b"), Some("cbz"), |p| {
+            !taken.iter().any(|t| t == p)
+        });
+        assert_eq!(path, PathBuf::from("a/b (3).cbz"));
+
+        let path = unique_path(Path::new("a/#1.5"), None, |_| true);
+        assert_eq!(path, PathBuf::from("a/#1.5"));
+    }
+
+    #[test]
+    fn chapters_sharing_a_folder() {
+        let directory = env::temp_dir().join(format!("mager-layout-{}", std::process::id()));
+        let layout = DownloadLayout {
+            directory: directory.clone(),
+            template: PathTemplate::default(),
+        };
+
+        let first = layout.chapter_folder(&values(), "MangaDex/a").unwrap();
+        let second = layout.chapter_folder(&values(), "MangaDex/b").unwrap();
+        let first_again = layout.chapter_folder(&values(), "MangaDex/a").unwrap();
+
+        assert_eq!(first, directory.join("Re_Zero _ Arc 1/#1.5 - The End_"));
+        assert_eq!(
+            second,
+            directory.join("Re_Zero _ Arc 1/#1.5 - The End_ (2)")
+        );
+        assert_eq!(first_again, first);
+        assert_eq!(
+            layout.volume_file(&first, "3", "epub"),
+            directory.join("Re_Zero _ Arc 1/Volume 3.epub")
+        );
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,147 @@
+//! Looking for the chapters of followed manga that came out since they were last checked.
+//!
+//! The first check of a manga takes in its whole chapter list, so that the library knows
+//! every chapter from then on. Later checks request chapter lists newest first, page after
+//! page, until a page only holds chapters the library already knows.
+
+use std::collections::HashSet;
+
+use dto::{ChapterList, ChapterListEntry, Filter, Order, Sort};
+
+use crate::config::Config;
+
+/// The chapters of a manga that the library doesn't know yet
+pub struct ChapterScan {
+    known: HashSet<String>,
+    /// The first time a manga is checked, only the chapters that were seen are known, so
+    /// every other one would look new
+    first_scan: bool,
+    /// The unknown chapters found so far, newest first
+    pub chapters: Vec<ChapterListEntry>,
+}
+
+impl ChapterScan {
+    /// A scan that skips the chapters with these identifiers. `checked` tells whether a
+    /// scan already took in the whole chapter list, see [`LibraryEntry::checked`].
+    ///
+    /// [`LibraryEntry::checked`]: crate::library::LibraryEntry::checked
+    pub fn new(known: impl IntoIterator<Item = String>, checked: bool) -> Self {
+        Self {
+            first_scan: !checked,
+            known: known.into_iter().collect(),
+            chapters: Vec::new(),
+        }
+    }
+
+    /// The filter chapter lists have to be requested with
+    pub fn filter(config: &Config) -> Filter {
+        Filter {
+            sort: Sort::new(Order::Descending),
+            ..config.filter()
+        }
+    }
+
+    /// Sorts out a page of the chapter list. Returns the page to request next, if the
+    /// scan isn't over.
+    pub fn add_page(&mut self, list: ChapterList) -> Option<u32> {
+        let mut found_unknown = false;
+        for chapter in list.data {
+            if self.known.insert(chapter.identifier.clone()) {
+                self.chapters.push(chapter);
+                found_unknown = true;
+            }
+        }
+
+        // The first scan goes through every page, whatever was seen before
+        let done = !self.first_scan && !found_unknown;
+        if done || list.page >= list.total_page {
+            return None;
+        }
+
+        Some(list.page + 1)
+    }
+
+    /// The chapters that came out since the last check. Nothing is new the first time a
+    /// manga is checked, its chapters are only taken in.
+    pub fn new_chapters(&self) -> &[ChapterListEntry] {
+        match self.first_scan {
+            true => &[],
+            false => &self.chapters,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn page(page: u32, total_page: u32, identifiers: &[&str]) -> ChapterList {
+        let data = identifiers
+            .iter()
+            .map(|id| ChapterListEntry {
+                identifier: id.to_string(),
+                title: String::new(),
+                number: id.to_string(),
+                volume: None,
+                group: None,
+            })
+            .collect();
+
+        ChapterList {
+            page,
+            total_page,
+            data,
+        }
+    }
+
+    #[test]
+    fn stops_at_known_chapters() {
+        let mut scan = ChapterScan::new(["3", "2", "1"].map(String::from), true);
+
+        assert_eq!(scan.add_page(page(1, 3, &["5", "4"])), Some(2));
+        assert_eq!(scan.add_page(page(2, 3, &["3", "2"])), None);
+        let new: Vec<&str> = scan
+            .new_chapters()
+            .iter()
+            .map(|c| c.identifier.as_str())
+            .collect();
+        assert_eq!(new, ["5", "4"]);
+
+        let mut scan = ChapterScan::new(["1"].map(String::from), true);
+        assert_eq!(scan.add_page(page(1, 1, &["2"])), None);
+        assert_eq!(scan.new_chapters().len(), 1);
+    }
+
+    #[test]
+    fn first_scan_reports_nothing() {
+        let mut scan = ChapterScan::new(Vec::new(), false);
+
+        assert_eq!(scan.add_page(page(1, 2, &["9", "8"])), Some(2));
+        assert_eq!(scan.add_page(page(2, 2, &["7", "6"])), None);
+        assert!(scan.new_chapters().is_empty());
+        assert_eq!(scan.chapters.len(), 4);
+    }
+
+    #[test]
+    fn chapters_seen_before_the_first_scan() {
+        // Only the second page was opened, oldest first, before the manga was checked
+        let mut scan = ChapterScan::new(["3", "4"].map(String::from), false);
+
+        assert_eq!(scan.add_page(page(1, 3, &["6", "5"])), Some(2));
+        assert_eq!(scan.add_page(page(2, 3, &["4", "3"])), Some(3));
+        assert_eq!(scan.add_page(page(3, 3, &["2", "1"])), None);
+        assert!(scan.new_chapters().is_empty());
+
+        // Every chapter is known after that, so only the ones that come out are new
+        let known = ["6", "5", "4", "3", "2", "1"].map(String::from);
+        let mut scan = ChapterScan::new(known, true);
+        assert_eq!(scan.add_page(page(1, 4, &["7", "6"])), Some(2));
+        assert_eq!(scan.add_page(page(2, 4, &["5", "4"])), None);
+        let new: Vec<&str> = scan
+            .new_chapters()
+            .iter()
+            .map(|c| c.identifier.as_str())
+            .collect();
+        assert_eq!(new, ["7"]);
+    }
+}
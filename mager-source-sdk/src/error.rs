@@ -0,0 +1,29 @@
+use std::fmt;
+
+use dto::carriers::ErrorCode;
+
+/// An error with a GMTP error code attached. Return it (as a `Report`) from a `Source`
+/// method to tell the client what kind of failure happened, every other error is sent as
+/// `ErrorCode::Internal`.
+#[derive(Debug, Clone)]
+pub struct SourceError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl SourceError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for SourceError {}
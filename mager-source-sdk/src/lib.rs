@@ -0,0 +1,112 @@
+//! Everything needed to write a mager source.
+//!
+//! A source is a small program that speaks GMTP with mager. This crate takes care of the
+//! boring parts (listening, framing, dispatching commands, turning errors into responses
+//! and logging), so a new source only has to implement the [`Source`] trait with its
+//! scraping logic and hand it over to [`serve`].
+//!
+//! ```ignore
+//! #[tokio::main]
+//! async fn main() -> color_eyre::Result<()> {
+//!     color_eyre::install()?;
+//!     mager_source_sdk::serve(MySource::new()).await
+//! }
+//! ```
+
+mod error;
+mod server;
+mod stream;
+
+use std::future::Future;
+
+use color_eyre::Result;
+use dto::carriers::ErrorCode;
+use dto::{
+    BrowseMode, Chapter, ChapterList, Filter, FilterField, Manga, MangaList, SortKey, TagGroup,
+};
+
+pub use dto;
+pub use error::SourceError;
+pub use server::{serve, serve_with, Options};
+
+/// The scraping logic of a source. Every method answers one GMTP command, returning an
+/// error will send an error response back to the client with the error as its reason.
+/// Use [`SourceError`] to tell the client what kind of error it is.
+pub trait Source: Send + Sync + 'static {
+    /// The name that is sent back to clients in every response
+    fn name(&self) -> &str;
+
+    /// Checks whether the source is able to serve requests
+    fn ping(&self) -> impl Future<Output = Result<()>> + Send {
+        async { Ok(()) }
+    }
+
+    /// Languages the source can serve manga in
+    fn languages(&self) -> Vec<String> {
+        vec![String::from("en")]
+    }
+
+    /// The `Filter` fields the source takes into account
+    fn filters(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Filters only this source has. Their values come in `Filter::fields`, by field id.
+    fn filter_fields(&self) -> Vec<FilterField> {
+        Vec::new()
+    }
+
+    /// What manga can be sorted by in `Filter::sort`
+    fn sort_keys(&self) -> Vec<SortKey> {
+        Vec::new()
+    }
+
+    /// The lists of manga the source can be browsed by
+    fn browse_modes(&self) -> Vec<BrowseMode> {
+        Vec::new()
+    }
+
+    /// Searches manga by keyword. `page` starts from 1.
+    fn search(
+        &self,
+        keyword: String,
+        page: u32,
+        filter: Filter,
+    ) -> impl Future<Output = Result<MangaList>> + Send;
+
+    /// Fetches a page of one of the lists in [`Source::browse_modes`]. `page` starts from 1.
+    fn browse(
+        &self,
+        mode: BrowseMode,
+        _page: u32,
+        _filter: Filter,
+    ) -> impl Future<Output = Result<MangaList>> + Send {
+        async move {
+            let message = format!("This source can't be browsed by {mode:?}");
+            Err(SourceError::new(ErrorCode::Unsupported, message).into())
+        }
+    }
+
+    /// Fetches the details of a manga
+    fn manga(&self, identifier: String) -> impl Future<Output = Result<Manga>> + Send;
+
+    /// Fetches a page of a manga's chapter list. `page` starts from 1.
+    fn chapter_list(
+        &self,
+        identifier: String,
+        page: u32,
+        filter: Filter,
+    ) -> impl Future<Output = Result<ChapterList>> + Send;
+
+    /// Fetches the details of a chapter, including its page URLs
+    fn chapter(&self, identifier: String) -> impl Future<Output = Result<Chapter>> + Send;
+
+    /// Lists the tags manga can be filtered by. Sources that take `included_tags` or
+    /// `excluded_tags` into account have to list them, the other ones don't.
+    fn tags(&self) -> impl Future<Output = Result<Vec<TagGroup>>> + Send {
+        async {
+            let error = SourceError::new(ErrorCode::Unsupported, "This source has no tags");
+            Err(error.into())
+        }
+    }
+}
@@ -0,0 +1,442 @@
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use clap::Parser;
+use color_eyre::eyre::{eyre, Report};
+use color_eyre::Result;
+use dto::carriers::{self, Command, CommandKind, ErrorCode, Request, Response, SourceInfo, Status};
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, ToSocketAddrs, UnixListener};
+use tokio::sync::{mpsc, Semaphore};
+use tracing::{debug, error, info, warn};
+
+use crate::stream::{read_from_stream, write_to_stream};
+use crate::{Source, SourceError};
+
+#[derive(Parser)]
+#[command(about = "A mager source, speaking GMTP")]
+struct Args {
+    /// Port to listen on. A free port is picked when it is not given.
+    #[arg(conflicts_with = "socket")]
+    port: Option<u16>,
+
+    /// Address to listen on when serving over TCP. Use 0.0.0.0 to serve remote clients.
+    #[arg(long, default_value = "127.0.0.1", conflicts_with = "socket")]
+    host: String,
+
+    /// Serve over a Unix domain socket created at this path instead of TCP
+    #[arg(long, value_name = "PATH")]
+    socket: Option<PathBuf>,
+
+    /// Maximum number of requests that are handled at the same time. Sources also use it
+    /// to cap the calls they make upstream.
+    #[arg(long, value_name = "N", default_value_t = 8)]
+    max_in_flight: usize,
+}
+
+/// The command line settings a source can be built with, see [`serve_with`]
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// How many requests are handled at the same time, the most calls a source should make
+    /// upstream at the same time too
+    pub max_in_flight: usize,
+}
+
+/// Parses the command line arguments, then serves GMTP requests with the source until the
+/// process is killed. Requests are served over a Unix domain socket when `--socket` is
+/// given, or over TCP otherwise. Every connection is handled concurrently, and so are the requests
+/// sent through it.
+pub async fn serve<S: Source>(source: S) -> Result<()> {
+    serve_with(|_| source).await
+}
+
+/// Same as [`serve`], for sources that are built with the command line [`Options`]
+pub async fn serve_with<S: Source>(build: impl FnOnce(&Options) -> S) -> Result<()> {
+    let args = Args::parse();
+    // Logs go to stderr, stdout belongs to the client that spawned us
+    let _ = tracing_subscriber::fmt().with_writer(io::stderr).try_init();
+
+    let options = Options {
+        max_in_flight: args.max_in_flight,
+    };
+    let source = Arc::new(build(&options));
+    let limiter = Arc::new(Semaphore::new(args.max_in_flight));
+
+    match args.socket {
+        Some(path) => serve_unix(&path, source, limiter).await,
+        None => {
+            let port = args.port.unwrap_or(0);
+            serve_tcp((args.host.as_str(), port), source, limiter).await
+        }
+    }
+}
+
+async fn serve_tcp<S: Source>(
+    address: impl ToSocketAddrs,
+    source: Arc<S>,
+    limiter: Arc<Semaphore>,
+) -> Result<()> {
+    let listener = TcpListener::bind(address).await?;
+    let address = listener.local_addr()?;
+    info!("{} is listening on {address}", source.name());
+    announce_ready(&format!("tcp {address}"))?;
+
+    loop {
+        let (stream, address) = listener.accept().await?;
+        debug!("Accepted connection from {address}");
+        spawn_connection(stream, source.clone(), limiter.clone());
+    }
+}
+
+/// Serves over a Unix domain socket that only the current user can connect to
+async fn serve_unix<S: Source>(path: &Path, source: Arc<S>, limiter: Arc<Semaphore>) -> Result<()> {
+    // A socket left behind by a previous run would make binding fail. Anything else at
+    // that path isn't ours to remove.
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path)?,
+        Ok(_) => return Err(eyre!("{} exists and isn't a socket", path.display())),
+        Err(_) => {}
+    }
+
+    // The socket is created without access for other users, they can't connect before
+    // its permissions would be changed
+    // SAFETY: umask has no preconditions and can't fail
+    let umask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(path);
+    unsafe { libc::umask(umask) };
+    let listener = listener?;
+    info!("{} is listening on {}", source.name(), path.display());
+    announce_ready(&format!("unix {}", path.display()))?;
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        debug!("Accepted connection on {}", path.display());
+        spawn_connection(stream, source.clone(), limiter.clone());
+    }
+}
+
+/// Tells the client that spawned us where to connect, see the GMTP startup section
+fn announce_ready(endpoint: &str) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    writeln!(stdout, "{} {endpoint}", carriers::READY)?;
+    stdout.flush()?;
+
+    Ok(())
+}
+
+fn spawn_connection<S, T>(stream: T, source: Arc<S>, limiter: Arc<Semaphore>)
+where
+    S: Source,
+    T: AsyncRead + AsyncWrite + Send + 'static,
+{
+    tokio::spawn(async move {
+        if let Err(e) = handle_connection(stream, source, limiter).await {
+            error!("Connection closed with error: {e}");
+        }
+    });
+}
+
+/// Serves every request sent through one connection. Responses are written back as soon
+/// as they are ready, so a slow request doesn't block the fast ones.
+async fn handle_connection<S, T>(stream: T, source: Arc<S>, limiter: Arc<Semaphore>) -> Result<()>
+where
+    S: Source,
+    T: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    let (response_tx, mut response_rx) = mpsc::unbounded_channel::<String>();
+
+    let writer_task = tokio::spawn(async move {
+        while let Some(response) = response_rx.recv().await {
+            write_to_stream(&mut writer, &response).await?;
+        }
+        Ok::<(), Report>(())
+    });
+
+    while let Some(request) = read_from_stream(&mut reader).await? {
+        let request: Request = match serde_json::from_slice(&request) {
+            Ok(r) => r,
+            Err(e) => {
+                warn!("Received a malformed request: {e}");
+                let response = malformed_request_response(&request, e, source.as_ref())?;
+                let _ = response_tx.send(response);
+                continue;
+            }
+        };
+
+        let response_tx = response_tx.clone();
+        let source = source.clone();
+        let limiter = limiter.clone();
+        tokio::spawn(async move {
+            let _permit = limiter.acquire().await;
+            match handle_request(request, source.as_ref()).await {
+                Ok(response) => {
+                    let _ = response_tx.send(response);
+                }
+                Err(e) => error!("Failed to handle request: {e}"),
+            }
+        });
+    }
+
+    // The writer stops once every pending response has been sent
+    drop(response_tx);
+    writer_task.await?
+}
+
+/// Runs the command on the source and returns the serialized response
+async fn handle_request<S: Source>(request: Request, source: &S) -> Result<String> {
+    let id = request.id;
+    debug!("Request {id}: {:?}", request.command);
+
+    // Ping is always answered, so the client can find out which versions we speak
+    let is_ping = matches!(request.command, Command::Ping);
+    if !is_ping && !carriers::is_compatible(carriers::VERSION, &request.version) {
+        let error = SourceError::new(
+            ErrorCode::Unsupported,
+            format!(
+                "Unsupported GMTP version {}, this source speaks {}",
+                request.version,
+                carriers::VERSION
+            ),
+        );
+        return respond::<_, ()>(id, source, Err(error.into()));
+    }
+
+    match request.command {
+        Command::Ping => {
+            let info = source.ping().await.map(|_| source_info(source));
+            respond(id, source, info)
+        }
+        Command::Search {
+            keyword,
+            page,
+            filter,
+        } => respond(id, source, source.search(keyword, page, filter).await),
+        Command::FetchManga { manga_identifier } => {
+            respond(id, source, source.manga(manga_identifier).await)
+        }
+        Command::FetchChapterList {
+            identifier,
+            page,
+            filter,
+        } => respond(
+            id,
+            source,
+            source.chapter_list(identifier, page, filter).await,
+        ),
+        Command::FetchChapter { chapter_identifier } => {
+            respond(id, source, source.chapter(chapter_identifier).await)
+        }
+        Command::ListTags => respond(id, source, source.tags().await),
+        Command::Browse { mode, page, filter } => {
+            respond(id, source, source.browse(mode, page, filter).await)
+        }
+    }
+}
+
+/// Builds a BadRequest response for a request that can't be parsed. The id is salvaged if
+/// possible, so the client isn't left waiting forever.
+fn malformed_request_response<S: Source>(
+    raw_request: &[u8],
+    error: serde_json::Error,
+    source: &S,
+) -> Result<String> {
+    let id = serde_json::from_slice::<serde_json::Value>(raw_request)
+        .ok()
+        .and_then(|r| r["id"].as_u64())
+        .unwrap_or(0);
+
+    let error = SourceError::new(ErrorCode::BadRequest, format!("Malformed request: {error}"));
+    respond::<_, ()>(id, source, Err(error.into()))
+}
+
+fn source_info<S: Source>(source: &S) -> SourceInfo {
+    let filters = source.filters();
+    let browse_modes = source.browse_modes();
+
+    // Every command is dispatched to the source, tags are only listed by sources that
+    // filter by them and only sources with browse modes can be browsed
+    let mut commands = vec![
+        CommandKind::Ping,
+        CommandKind::Search,
+        CommandKind::FetchChapterList,
+        CommandKind::FetchManga,
+        CommandKind::FetchChapter,
+    ];
+    if filters
+        .iter()
+        .any(|f| f == "included_tags" || f == "excluded_tags")
+    {
+        commands.push(CommandKind::ListTags);
+    }
+    if !browse_modes.is_empty() {
+        commands.push(CommandKind::Browse);
+    }
+
+    SourceInfo {
+        name: source.name().to_string(),
+        versions: vec![carriers::VERSION.to_string()],
+        commands,
+        languages: source.languages(),
+        filters,
+        filter_fields: source.filter_fields(),
+        browse_modes,
+        sort_keys: source.sort_keys(),
+    }
+}
+
+fn respond<S, T>(id: u64, source: &S, result: Result<T>) -> Result<String>
+where
+    S: Source,
+    T: Serialize,
+{
+    let response = match result {
+        Ok(content) => Response {
+            id,
+            status: Status::Ok,
+            reason: String::from("All good"),
+            source_name: source.name().to_string(),
+            content: Some(content),
+        },
+        Err(report) => {
+            warn!("Request {id} failed: {report}");
+            let code = report
+                .downcast_ref::<SourceError>()
+                .map(|e| e.code)
+                .unwrap_or(ErrorCode::Internal);
+
+            Response {
+                id,
+                status: Status::Error(code),
+                reason: report.to_string(),
+                source_name: source.name().to_string(),
+                content: None,
+            }
+        }
+    };
+
+    Ok(serde_json::to_string(&response)?)
+}
+
+#[cfg(test)]
+mod test {
+    use color_eyre::eyre::eyre;
+    use dto::{Chapter, ChapterList, Filter, Manga, MangaList};
+
+    use super::*;
+
+    struct EmptySource;
+
+    impl Source for EmptySource {
+        fn name(&self) -> &str {
+            "Empty"
+        }
+
+        async fn search(&self, _: String, page: u32, _: Filter) -> Result<MangaList> {
+            Ok(MangaList {
+                page,
+                ..Default::default()
+            })
+        }
+
+        async fn manga(&self, identifier: String) -> Result<Manga> {
+            let message = format!("{identifier} doesn't exist");
+            Err(SourceError::new(ErrorCode::NotFound, message).into())
+        }
+
+        async fn chapter_list(&self, _: String, page: u32, _: Filter) -> Result<ChapterList> {
+            Ok(ChapterList {
+                page,
+                ..Default::default()
+            })
+        }
+
+        async fn chapter(&self, identifier: String) -> Result<Chapter> {
+            Err(eyre!("{identifier} doesn't exist"))
+        }
+    }
+
+    async fn send<T>(request: Request, stream: &mut T) -> serde_json::Value
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
+        let request = serde_json::to_string(&request).unwrap();
+        write_to_stream(stream, &request).await.unwrap();
+
+        let response = read_from_stream(stream).await.unwrap().unwrap();
+        serde_json::from_slice(&response).unwrap()
+    }
+
+    #[tokio::test]
+    async fn errors_become_responses() {
+        let (mut client, server) = tokio::io::duplex(1024);
+        let limiter = Arc::new(Semaphore::new(1));
+        tokio::spawn(handle_connection(server, Arc::new(EmptySource), limiter));
+
+        let search = Command::Search {
+            keyword: String::from("anything"),
+            page: 3,
+            filter: Filter::default(),
+        };
+        let response = send(Request::new(7, search), &mut client).await;
+        assert_eq!(response["id"], 7);
+        assert_eq!(response["status"], "Ok");
+        assert_eq!(response["content"]["page"], 3);
+
+        let fetch = Command::FetchManga {
+            manga_identifier: String::from("missing"),
+        };
+        let response = send(Request::new(8, fetch), &mut client).await;
+        assert_eq!(response["id"], 8);
+        assert_eq!(response["status"]["Error"], "NotFound");
+        assert_eq!(response["reason"], "missing doesn't exist");
+        assert_eq!(response["source_name"], "Empty");
+
+        let fetch = Command::FetchChapter {
+            chapter_identifier: String::from("missing"),
+        };
+        let response = send(Request::new(9, fetch), &mut client).await;
+        assert_eq!(response["status"]["Error"], "Internal");
+
+        // Sources without tag filters have no tags to list
+        let response = send(Request::new(11, Command::ListTags), &mut client).await;
+        assert_eq!(response["status"]["Error"], "Unsupported");
+
+        // Garbage still gets an answer, with the id if it can be found
+        write_to_stream(&mut client, r#"{"id":10,"command":"Nope"}"#)
+            .await
+            .unwrap();
+        let response = read_from_stream(&mut client).await.unwrap().unwrap();
+        let response: serde_json::Value = serde_json::from_slice(&response).unwrap();
+        assert_eq!(response["id"], 10);
+        assert_eq!(response["status"]["Error"], "BadRequest");
+    }
+
+    #[tokio::test]
+    async fn version_negotiation() {
+        let (mut client, server) = tokio::io::duplex(1024);
+        let limiter = Arc::new(Semaphore::new(1));
+        tokio::spawn(handle_connection(server, Arc::new(EmptySource), limiter));
+
+        let old_request = |command| Request {
+            id: 1,
+            command,
+            version: String::from("0.0.0"),
+        };
+
+        let response = send(old_request(Command::Ping), &mut client).await;
+        assert_eq!(response["status"], "Ok");
+        assert_eq!(response["content"]["name"], "Empty");
+        assert_eq!(response["content"]["versions"][0], carriers::VERSION);
+
+        let fetch = Command::FetchChapter {
+            chapter_identifier: String::from("1"),
+        };
+        let response = send(old_request(fetch), &mut client).await;
+        assert_eq!(response["status"]["Error"], "Unsupported");
+    }
+}
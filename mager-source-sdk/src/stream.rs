@@ -0,0 +1,35 @@
+use std::io::ErrorKind;
+
+use color_eyre::Result;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Reads one length-prefixed message from the stream. Returns `None` when the client has
+/// closed the connection.
+pub(crate) async fn read_from_stream<R>(stream: &mut R) -> Result<Option<Vec<u8>>>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut length: [u8; 4] = [0; 4];
+    match stream.read_exact(&mut length).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    let mut payload = vec![0; u32::from_ne_bytes(length) as usize];
+    stream.read_exact(&mut payload).await?;
+
+    Ok(Some(payload))
+}
+
+pub(crate) async fn write_to_stream<W>(stream: &mut W, payload: &str) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let size = payload.len() as u32;
+    stream.write_all(&size.to_ne_bytes()).await?;
+    stream.write_all(payload.as_bytes()).await?;
+    stream.flush().await?;
+
+    Ok(())
+}
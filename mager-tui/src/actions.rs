@@ -1,7 +1,11 @@
-use dto::carriers::Response;
-use dto::{Chapter, ChapterList, Filter, Manga, MangaList};
+use dto::{BrowseMode, Chapter, ChapterList, ChapterListEntry, Filter, Manga, MangaList, TagGroup};
+use image::DynamicImage;
+use mager_core::library::{LibraryChapter, LibraryEntry, ReadingProgress};
+use std::sync::Arc;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
+use crate::download_manager::DownloadJob;
+use crate::export::ExportFormat;
 use crate::source::Source;
 
 pub type ActionTx = UnboundedSender<Action>;
@@ -15,17 +19,102 @@ pub enum Action {
     PrevPage,
     Quit,
     FetchSources,
-    RunCommand(Command),
-    DownloadChapter(String),
+    // Every action below that carries a source name is about the source with that name
+    RunCommand(String, Command),
+    DownloadChapter(String, String, ExportFormat),
+    DownloadVolume(String, String, ExportFormat),
+    PauseDownload(u64),
+    ResumeDownload(u64),
+    CancelDownload(u64),
+    DisplayDownloads(Vec<DownloadJob>),
+    /// Every page of the chapter is on disk
+    ChapterDownloaded(String, Chapter),
+    FetchLibrary,
+    DisplayLibrary(Vec<LibraryEntry>),
+    AddToLibrary(String, Manga),
+    /// Removes the manga with this identifier from the library
+    RemoveFromLibrary(String, String),
+    /// Remembers the chapters of the manga with this identifier, if it is in the library
+    TrackChapters(String, String, Vec<ChapterListEntry>),
+    /// Remembers the chapters an update check found for the manga with this identifier, and
+    /// that its whole chapter list was taken in
+    ChaptersChecked(String, String, Vec<ChapterListEntry>),
+    /// The chapters the library knows of the manga with this identifier, or `None` when the
+    /// manga isn't in the library
+    DisplayLibraryChapters(String, String, Option<Vec<LibraryChapter>>),
+    /// Marks the chapter with this identifier as read or unread
+    SetChapterRead(String, String, bool),
+    /// Remembers that this page of the chapter was just read, starting from 0
+    SaveProgress(String, Chapter, usize),
+    /// Looks up how far the manga with this identifier was read
+    FetchProgress(String, String),
+    /// How far each opened chapter of the manga with this identifier was read, and the
+    /// chapter that was read last
+    DisplayProgress(String, String, Vec<ReadingProgress>, Option<String>),
+    /// Starts the source and opens the details of the manga with this identifier
+    OpenManga(String, String),
+    /// Looks for new chapters in the library, and downloads them if set
+    CheckUpdates(bool),
+    DisplayUpdate(UpdateReport),
+    /// Downloads the page image at this URL for the reader
+    FetchPageImage(String),
+    DisplayPageImage(String, PageImage),
     SetActiveSource(Source),
-    SetActiveManga(Manga),
-    SetActiveChapter(Chapter),
-    DisplayMangaList(MangaList),
-    DisplayChapterList(ChapterList),
+    DeactivateSource(String),
+    SetActiveManga(String, Manga),
+    SetActiveChapter(String, Chapter),
+    DisplayMangaList(String, MangaList),
+    DisplayChapterList(String, ChapterList),
+    DisplayTags(String, Vec<TagGroup>),
     DisplaySourceList(Vec<Source>),
+    DisplayActiveSources(Vec<String>),
+    /// Searches the keyword in every installed source
+    SearchEverywhere(String, Filter),
+    DisplayFederatedResult(FederatedResult),
     InvokeError(String),
 }
 
+/// How a source is doing with a search that was sent to every source
+#[derive(Clone)]
+pub enum SearchOutcome {
+    Searching,
+    Found(MangaList),
+    Failed(String),
+    TimedOut,
+}
+
+/// The answer of one source to a search that was sent to every source
+#[derive(Clone)]
+pub struct FederatedResult {
+    pub keyword: String,
+    pub source: String,
+    pub outcome: SearchOutcome,
+}
+
+/// A page downloaded for the reader
+#[derive(Clone)]
+pub enum PageImage {
+    Loaded(Arc<DynamicImage>),
+    Failed(String),
+}
+
+/// How checking a manga of the library for new chapters is going
+#[derive(Clone)]
+pub enum UpdateOutcome {
+    Checking,
+    /// How many new chapters there are
+    Found(usize),
+    Failed(String),
+}
+
+/// How checking the manga with this identifier is going
+#[derive(Clone)]
+pub struct UpdateReport {
+    pub source: String,
+    pub identifier: String,
+    pub outcome: UpdateOutcome,
+}
+
 #[derive(Clone)]
 pub enum Command {
     SearchManga {
@@ -33,6 +122,11 @@ pub enum Command {
         page: u32,
         filter: Filter,
     },
+    BrowseManga {
+        mode: BrowseMode,
+        page: u32,
+        filter: Filter,
+    },
     FetchChapterList {
         identifier: String,
         page: u32,
@@ -44,21 +138,17 @@ pub enum Command {
     FetchChapterDetail {
         identifier: String,
     },
+    ListTags,
 }
 
 #[derive(Clone)]
 pub enum Page {
     Sources,
-    Mangas,
-    MangaDetails,
-}
-
-#[derive(Clone)]
-pub enum AsyncItem {
-    Ok,
-    MangaList(Response<MangaList>),
-    ChapterList(Response<ChapterList>),
-    Manga(Response<Manga>),
-    Chapter(Response<Chapter>),
-    Sources(Vec<Source>),
+    Mangas(String),
+    SearchEverywhere,
+    MangaDetails(String),
+    Downloads,
+    /// Reads the chapter with this identifier from the given page, the chapters being the
+    /// ones the reader can move on to
+    Reader(String, Vec<ChapterListEntry>, String, usize),
 }
@@ -1,22 +1,42 @@
+use std::io::stdout;
+use std::sync::Arc;
+
 use color_eyre::eyre::Result;
 use dto::carriers::Status;
+use ratatui::prelude::*;
+use ratatui::widgets::{Block, Clear, Paragraph, Wrap};
 use tokio::sync::mpsc;
-use tokio::task::JoinHandle;
+use tracing::error;
 
 use crate::actions::*;
+use crate::components::downloads_page::DownloadsPage;
+use crate::components::library_page::LibraryPage;
 use crate::components::manga_details_page::MangaDetailsPage;
 use crate::components::manga_list_page::MangaListPage;
+use crate::components::reader_page::ReaderPage;
 use crate::components::source_list_page::SourcesPage;
 use crate::components::Component;
+use crate::download_manager::DownloadManager;
+use crate::graphics::Protocol;
 use crate::mager::*;
-use crate::source::Source;
+use crate::source_manager::SourceManager;
 use crate::tui::{Event, Tui};
-use crate::utils;
+use crate::utils::fetch_image;
+use mager_core::config::Config;
+use mager_core::library::{Library, LibraryEntry};
+use mager_core::update::ChapterScan;
 
 pub(crate) struct App {
     tui: Tui,
-    active_source: Option<Source>,
-    source_port: u16,
+    config: Config,
+    library: Library,
+    sources: SourceManager,
+    downloads: DownloadManager,
+    // The last error, shown on top of the active page until a key is pressed
+    error: Option<String>,
+    /// Whether the screen has to be cleared before the next frame, which is needed to get
+    /// rid of the images pages write over the frame
+    clear_screen: bool,
     should_exit: bool,
     action_tx: ActionTx,
     action_rx: ActionRx,
@@ -27,70 +47,214 @@ impl App {
     pub(crate) fn new() -> Result<Self> {
         let (action_tx, action_rx) = mpsc::unbounded_channel();
 
+        let config = Config::load()?;
+        let sources = SourceManager::new(config.sources_dir.clone());
+        let downloads = DownloadManager::load(sources.clone(), config.clone(), action_tx.clone())?;
+
         Ok(Self {
             tui: Tui::new()?,
-            active_source: None,
-            source_port: 7878,
+            config,
+            library: Library::open(&Library::path())?,
+            sources,
+            downloads,
+            error: None,
+            clear_screen: false,
             should_exit: false,
-            active_pages: vec![Box::new(SourcesPage::new(action_tx.clone()))],
+            active_pages: vec![Box::new(LibraryPage::new(action_tx.clone()))],
             action_tx,
             action_rx,
         })
     }
 
-    async fn handle_commands(&mut self, command: Command) -> Result<()> {
-        let source_port = self.source_port;
+    async fn handle_commands(&mut self, source: String, command: Command) -> Result<()> {
+        let Some(connection) = self.sources.connection(&source) else {
+            self.action_tx
+                .send(Action::InvokeError(format!("{source} is not active")))?;
+            return Ok(());
+        };
+
         let action_tx = self.action_tx.clone();
-        let _: JoinHandle<()> = tokio::spawn(async move {
+        tokio::spawn(async move {
             let result: Action = match command {
                 Command::SearchManga {
                     keyword,
                     page,
                     filter,
-                } => match search_manga(source_port, &keyword, page, &filter).await {
+                } => match search_manga(&connection, &keyword, page, &filter).await {
                     Ok(response) => match response.status {
-                        Status::Ok => Action::DisplayMangaList(response.content.unwrap()),
-                        Status::Error => Action::InvokeError(response.reason),
+                        Status::Ok => Action::DisplayMangaList(source, response.content.unwrap()),
+                        Status::Error(_) => {
+                            Action::InvokeError(describe_error("search results", &response))
+                        }
                     },
                     Err(error) => Action::InvokeError(error.to_string()),
                 },
+                Command::BrowseManga { mode, page, filter } => {
+                    match browse_manga(&connection, mode, page, &filter).await {
+                        Ok(response) => match response.status {
+                            Status::Ok => {
+                                Action::DisplayMangaList(source, response.content.unwrap())
+                            }
+                            Status::Error(_) => {
+                                Action::InvokeError(describe_error("manga list", &response))
+                            }
+                        },
+                        Err(error) => Action::InvokeError(error.to_string()),
+                    }
+                }
                 Command::FetchChapterList {
                     identifier,
                     page,
                     filter,
-                } => match fetch_chapters(source_port, &identifier, page, &filter).await {
+                } => match fetch_chapters(&connection, &identifier, page, &filter).await {
                     Ok(response) => match response.status {
-                        Status::Ok => Action::DisplayChapterList(response.content.unwrap()),
-                        Status::Error => Action::InvokeError(response.reason),
+                        Status::Ok => Action::DisplayChapterList(source, response.content.unwrap()),
+                        Status::Error(_) => {
+                            Action::InvokeError(describe_error("chapter list", &response))
+                        }
                     },
                     Err(error) => Action::InvokeError(error.to_string()),
                 },
                 Command::FetchMangaDetail { identifier } => {
-                    match fetch_manga(source_port, &identifier).await {
+                    match fetch_manga(&connection, &identifier).await {
                         Ok(response) => match response.status {
-                            Status::Ok => Action::SetActiveManga(response.content.unwrap()),
-                            Status::Error => Action::InvokeError(response.reason),
+                            Status::Ok => Action::SetActiveManga(source, response.content.unwrap()),
+                            Status::Error(_) => {
+                                Action::InvokeError(describe_error("manga", &response))
+                            }
                         },
                         Err(error) => Action::InvokeError(error.to_string()),
                     }
                 }
                 Command::FetchChapterDetail { identifier } => {
-                    match fetch_chapter(source_port, &identifier).await {
+                    match fetch_chapter(&connection, &identifier).await {
                         Ok(response) => match response.status {
-                            Status::Ok => Action::SetActiveChapter(response.content.unwrap()),
-                            Status::Error => Action::InvokeError(response.reason),
+                            Status::Ok => {
+                                Action::SetActiveChapter(source, response.content.unwrap())
+                            }
+                            Status::Error(_) => {
+                                Action::InvokeError(describe_error("chapter", &response))
+                            }
                         },
                         Err(error) => Action::InvokeError(error.to_string()),
                     }
                 }
+                Command::ListTags => match list_tags(&connection).await {
+                    Ok(response) => match response.status {
+                        Status::Ok => Action::DisplayTags(source, response.content.unwrap()),
+                        Status::Error(_) => Action::InvokeError(describe_error("tags", &response)),
+                    },
+                    Err(error) => Action::InvokeError(error.to_string()),
+                },
             };
 
-            action_tx.send(result);
+            let _ = action_tx.send(result);
         });
 
         Ok(())
     }
 
+    /// Keeps the library up to date with what the user does
+    fn handle_library(&mut self, action: &Action) -> Result<()> {
+        match action {
+            Action::FetchLibrary => {
+                self.action_tx
+                    .send(Action::DisplayLibrary(self.library.entries()?))?;
+            }
+            Action::AddToLibrary(source, manga) => {
+                self.library.add(source, manga)?;
+                self.display_library_chapters(source, &manga.identifier)?;
+            }
+            Action::RemoveFromLibrary(source, identifier) => {
+                self.library.remove(source, identifier)?;
+                self.display_library_chapters(source, identifier)?;
+                self.action_tx.send(Action::FetchLibrary)?;
+            }
+            Action::SetActiveManga(source, manga) => {
+                // The cached details are refreshed every time the manga is opened
+                if self.library.contains(source, &manga.identifier)? {
+                    self.library.add(source, manga)?;
+                }
+                self.display_library_chapters(source, &manga.identifier)?;
+                self.display_progress(source, &manga.identifier)?;
+            }
+            Action::TrackChapters(source, identifier, chapters) => {
+                // Only new chapters change what the pages show
+                let added = self.library.add_chapters(source, identifier, chapters)?;
+                if added > 0 {
+                    self.display_library_chapters(source, identifier)?;
+                }
+            }
+            Action::ChaptersChecked(source, identifier, chapters) => {
+                let added = self.library.add_chapters(source, identifier, chapters)?;
+                self.library.set_checked(source, identifier)?;
+                if added > 0 {
+                    self.display_library_chapters(source, identifier)?;
+                }
+            }
+            Action::SetChapterRead(source, chapter, read) => {
+                self.library.set_read(source, chapter, *read)?;
+            }
+            Action::SaveProgress(source, chapter, page) => {
+                self.library.save_progress(source, chapter, *page)?;
+            }
+            Action::FetchProgress(source, identifier) => {
+                // Reading may have marked chapters as read too
+                self.display_library_chapters(source, identifier)?;
+                self.display_progress(source, identifier)?;
+            }
+            Action::ChapterDownloaded(source, chapter) => {
+                self.library.mark_downloaded(source, chapter)?;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Tells the pages what the library knows of the manga
+    fn display_library_chapters(&self, source: &str, identifier: &str) -> Result<()> {
+        let chapters = match self.library.contains(source, identifier)? {
+            true => Some(self.library.chapters(source, identifier)?),
+            false => None,
+        };
+        self.action_tx.send(Action::DisplayLibraryChapters(
+            source.to_string(),
+            identifier.to_string(),
+            chapters,
+        ))?;
+
+        Ok(())
+    }
+
+    /// Tells the pages how far the manga was read
+    fn display_progress(&self, source: &str, identifier: &str) -> Result<()> {
+        self.action_tx.send(Action::DisplayProgress(
+            source.to_string(),
+            identifier.to_string(),
+            self.library.progress(source, identifier)?,
+            self.library.last_read(source, identifier)?,
+        ))?;
+
+        Ok(())
+    }
+
+    /// Every manga of the library, with the chapters already known of it
+    fn update_targets(&self) -> Result<Vec<(LibraryEntry, Vec<String>)>> {
+        let mut targets = Vec::new();
+        for entry in self.library.entries()? {
+            let known = self
+                .library
+                .chapters(&entry.source, &entry.manga.identifier)?
+                .into_iter()
+                .map(|c| c.identifier)
+                .collect();
+            targets.push((entry, known));
+        }
+
+        Ok(targets)
+    }
+
     pub(crate) async fn run(&mut self) -> Result<()> {
         self.tui.enter()?;
 
@@ -103,9 +267,7 @@ impl App {
         }
 
         self.tui.exit()?;
-        if let Some(c) = self.active_source.as_mut() {
-            c.deactivate_source();
-        }
+        self.sources.deactivate_all();
         Ok(())
     }
 
@@ -118,6 +280,12 @@ impl App {
         match event.clone() {
             Event::Tick => action_sx.send(Action::Tick)?,
             Event::Render => action_sx.send(Action::Render)?,
+            Event::Key(_) if self.error.is_some() => {
+                // The key press only dismisses the error
+                self.error = None;
+                self.clear_screen = true;
+                return Ok(());
+            }
             _ => {}
         }
 
@@ -130,46 +298,186 @@ impl App {
 
     async fn handle_actions(&mut self) -> Result<()> {
         while let Ok(action) = self.action_rx.try_recv() {
+            if let Err(e) = self.handle_library(&action) {
+                self.action_tx
+                    .send(Action::InvokeError(format!("Library error: {e}")))?;
+            }
+
             match action.clone() {
                 Action::Render => self.render()?,
                 Action::Quit => self.should_exit = true,
                 Action::NextPage(p) => {
                     let page: Box<dyn Component> = match p {
                         Page::Sources => Box::new(SourcesPage::new(self.action_tx.clone())),
-                        Page::Mangas => Box::new(MangaListPage::new(self.action_tx.clone())),
-                        Page::MangaDetails => {
-                            Box::new(MangaDetailsPage::new(self.action_tx.clone()))
+                        Page::Mangas(source) => {
+                            let connection = self.sources.connection(&source);
+                            Box::new(MangaListPage::new(
+                                self.action_tx.clone(),
+                                source,
+                                self.config.filter(),
+                                connection.as_ref().map(|c| c.info()),
+                            ))
+                        }
+                        Page::SearchEverywhere => Box::new(MangaListPage::everywhere(
+                            self.action_tx.clone(),
+                            self.config.filter(),
+                        )),
+                        Page::MangaDetails(source) => Box::new(MangaDetailsPage::new(
+                            self.action_tx.clone(),
+                            source,
+                            self.config.filter(),
+                        )),
+                        Page::Downloads => {
+                            self.action_tx
+                                .send(Action::DisplayDownloads(self.downloads.jobs()))?;
+                            Box::new(DownloadsPage::new(self.action_tx.clone()))
                         }
+                        Page::Reader(source, chapters, chapter, page) => Box::new(ReaderPage::new(
+                            self.action_tx.clone(),
+                            source,
+                            chapters,
+                            &chapter,
+                            page,
+                            Protocol::from_config(&self.config.image_protocol),
+                        )),
                     };
 
+                    if let Some(p) = self.active_pages.last_mut() {
+                        p.hide_graphics(&mut stdout())?;
+                    }
                     self.active_pages.push(page);
+                    self.clear_screen = true;
+                }
+                Action::InvokeError(ref e) => {
+                    error!("{e}");
+                    self.error = Some(e.clone());
+                    self.clear_screen = true;
                 }
                 Action::PrevPage => {
-                    let _ = self.active_pages.pop();
+                    if let Some(mut p) = self.active_pages.pop() {
+                        p.hide_graphics(&mut stdout())?;
+                    }
+                    self.clear_screen = true;
+                    // The page below may show the active sources, and they might have
+                    // changed in the meantime
+                    self.action_tx
+                        .send(Action::DisplayActiveSources(self.sources.active_sources()))?;
+                    self.action_tx.send(Action::FetchLibrary)?;
+                }
+                Action::SetActiveSource(s) => {
+                    let sources = self.sources.clone();
+                    let action_tx = self.action_tx.clone();
+
+                    tokio::spawn(async move {
+                        let name = s.name.clone();
+                        let _ = match sources.activate(s).await {
+                            Ok(_) => action_tx.send(Action::NextPage(Page::Mangas(name))),
+                            Err(e) => action_tx.send(Action::InvokeError(e.to_string())),
+                        };
+                        let _ =
+                            action_tx.send(Action::DisplayActiveSources(sources.active_sources()));
+                    });
                 }
-                Action::SetActiveSource(mut s) => {
-                    s.activate_source(self.source_port).await?;
-                    self.active_source = Some(s);
+                Action::SearchEverywhere(ref keyword, ref filter) => {
+                    let sources = self.sources.clone();
+                    let action_tx = self.action_tx.clone();
+                    let (keyword, filter) = (keyword.clone(), filter.clone());
+
+                    tokio::spawn(async move {
+                        if let Err(e) =
+                            search_everywhere(sources, keyword, filter, action_tx.clone()).await
+                        {
+                            let _ = action_tx.send(Action::InvokeError(e.to_string()));
+                        }
+                    });
                 }
-                Action::FetchSources => {
+                Action::OpenManga(source, identifier) => {
+                    let sources = self.sources.clone();
+                    let action_tx = self.action_tx.clone();
+                    let filter = self.config.filter();
+
+                    tokio::spawn(async move {
+                        if let Err(e) = sources.connect(&source).await {
+                            let _ = action_tx.send(Action::InvokeError(e.to_string()));
+                            return;
+                        }
+
+                        let _ =
+                            action_tx.send(Action::NextPage(Page::MangaDetails(source.clone())));
+                        let _ = action_tx.send(Action::RunCommand(
+                            source.clone(),
+                            Command::FetchMangaDetail {
+                                identifier: identifier.clone(),
+                            },
+                        ));
+                        let _ = action_tx.send(Action::RunCommand(
+                            source,
+                            Command::FetchChapterList {
+                                identifier,
+                                page: 1,
+                                filter,
+                            },
+                        ));
+                    });
+                }
+                Action::CheckUpdates(download) => match self.update_targets() {
+                    Ok(targets) => {
+                        let sources = self.sources.clone();
+                        let filter = ChapterScan::filter(&self.config);
+                        let action_tx = self.action_tx.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) =
+                                check_updates(sources, targets, filter, download, action_tx.clone())
+                                    .await
+                            {
+                                let _ = action_tx.send(Action::InvokeError(e.to_string()));
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        self.action_tx
+                            .send(Action::InvokeError(format!("Library error: {e}")))?;
+                    }
+                },
+                Action::FetchPageImage(url) => {
                     let action_tx = self.action_tx.clone();
+                    let user_agent = self.config.user_agent.clone();
 
                     tokio::spawn(async move {
-                        fetch_sources(action_tx).await.unwrap();
+                        let image = match fetch_image(&url, &user_agent).await {
+                            Ok(image) => PageImage::Loaded(Arc::new(image)),
+                            Err(e) => PageImage::Failed(e.to_string()),
+                        };
+                        let _ = action_tx.send(Action::DisplayPageImage(url, image));
                     });
                 }
-                Action::RunCommand(c) => {
-                    self.handle_commands(c).await?;
+                Action::DeactivateSource(ref name) => {
+                    self.sources.deactivate(name);
+                    self.action_tx
+                        .send(Action::DisplayActiveSources(self.sources.active_sources()))?;
                 }
-                Action::DownloadChapter(ch_id) => {
-                    let ch_id = ch_id.clone();
-                    let sc_port = self.source_port;
+                Action::FetchSources => {
+                    let action_tx = self.action_tx.clone();
+                    let sources_dir = self.config.sources_dir.clone();
+
                     tokio::spawn(async move {
-                        let ch_id_2 = ch_id;
-                        let sc_port_2 = sc_port;
-                        let _ = download_chapter(sc_port_2, &ch_id_2).await;
+                        if let Err(e) = fetch_sources(&sources_dir, action_tx.clone()).await {
+                            let _ = action_tx.send(Action::InvokeError(e.to_string()));
+                        }
                     });
                 }
+                Action::RunCommand(source, c) => {
+                    self.handle_commands(source, c).await?;
+                }
+                Action::DownloadChapter(source, ch_id, format) => {
+                    self.downloads.enqueue(source, ch_id, format, false);
+                }
+                Action::DownloadVolume(source, ch_id, format) => {
+                    self.downloads.enqueue(source, ch_id, format, true);
+                }
+                Action::PauseDownload(id) => self.downloads.pause(id),
+                Action::ResumeDownload(id) => self.downloads.resume(id),
+                Action::CancelDownload(id) => self.downloads.cancel(id),
                 _ => {}
             }
 
@@ -182,6 +490,12 @@ impl App {
     }
 
     fn render(&mut self) -> Result<()> {
+        let cleared = self.clear_screen;
+        if cleared {
+            self.tui.terminal.clear()?;
+            self.clear_screen = false;
+        }
+
         self.tui.terminal.draw(|f| {
             let Some(p) = self.active_pages.last_mut() else {
                 return;
@@ -190,7 +504,38 @@ impl App {
             if p.draw(f, f.area()).is_err() {
                 eprintln!("Error drawing");
             }
+
+            if let Some(e) = self.error.as_ref() {
+                draw_error(f, e);
+            }
         })?;
+
+        if let Some(p) = self.active_pages.last_mut() {
+            match self.error {
+                // The error has to show over the page
+                Some(_) if cleared => p.hide_graphics(&mut stdout())?,
+                Some(_) => {}
+                None => p.draw_graphics(&mut stdout(), cleared)?,
+            }
+        }
         Ok(())
     }
 }
+
+/// Draws the error message at the bottom of the screen
+fn draw_error(frame: &mut Frame, message: &str) {
+    let area = frame.area();
+    let height = 4.min(area.height);
+    let area = Rect::new(area.x, area.bottom() - height, area.width, height);
+
+    let block = Block::bordered()
+        .border_style(Style::new().red())
+        .title(" Error ".bold().red())
+        .title_bottom(" Press any key to dismiss ".dim());
+    let error = Paragraph::new(message)
+        .wrap(Wrap { trim: true })
+        .block(block);
+
+    frame.render_widget(Clear, area);
+    frame.render_widget(error, area);
+}
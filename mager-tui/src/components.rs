@@ -1,7 +1,14 @@
+pub mod downloads_page;
+pub mod filter_panel;
+pub mod library_page;
 pub mod manga_details_page;
 pub mod manga_list_page;
+pub mod reader_page;
 pub mod search_bar;
 pub mod source_list_page;
+pub mod tag_picker;
+
+use std::io::Write;
 
 use color_eyre::eyre::Result;
 use ratatui::layout::Rect;
@@ -16,4 +23,15 @@ pub trait Component {
     fn update(&mut self, action: Action) -> Result<()>;
 
     fn draw(&mut self, frame: &mut Frame, area: Rect) -> Result<()>;
+
+    /// Writes what can't be drawn with cells, like images, over the frame once it is
+    /// drawn. `redraw` is set when the screen was cleared since the last call.
+    fn draw_graphics(&mut self, _out: &mut dyn Write, _redraw: bool) -> Result<()> {
+        Ok(())
+    }
+
+    /// Removes what `draw_graphics` wrote, for terminals that keep it over the frame
+    fn hide_graphics(&mut self, _out: &mut dyn Write) -> Result<()> {
+        Ok(())
+    }
 }
@@ -0,0 +1,156 @@
+use color_eyre::Result;
+use crossterm::event::{KeyCode, KeyEventKind};
+use ratatui::prelude::*;
+use ratatui::widgets::{Block, Cell, Padding, Paragraph, Row, Table, TableState};
+
+use crate::actions::{Action, ActionTx};
+use crate::download_manager::{DownloadJob, JobState};
+use crate::tui::Event;
+
+use super::Component;
+
+/// Lists the chapters in the download queue, running ones first and finished ones last
+pub struct DownloadsPage {
+    action_tx: ActionTx,
+    jobs: Vec<DownloadJob>,
+    state: TableState,
+}
+
+impl DownloadsPage {
+    pub(crate) fn new(action_tx: ActionTx) -> Self {
+        Self {
+            action_tx,
+            jobs: Vec::new(),
+            state: TableState::default(),
+        }
+    }
+
+    fn selected_job(&self) -> Option<&DownloadJob> {
+        self.state.selected().and_then(|i| self.jobs.get(i))
+    }
+}
+
+fn state_order(state: JobState) -> u8 {
+    match state {
+        JobState::Running => 0,
+        JobState::Queued => 1,
+        JobState::Paused => 2,
+        JobState::Failed => 3,
+        JobState::Finished => 4,
+    }
+}
+
+impl Component for DownloadsPage {
+    fn handle_events(&mut self, event: Event) -> Result<()> {
+        let Event::Key(k_event) = event else {
+            return Ok(());
+        };
+
+        let KeyEventKind::Press = k_event.kind else {
+            return Ok(());
+        };
+
+        match k_event.code {
+            KeyCode::Char('b') => self.action_tx.send(Action::PrevPage)?,
+            KeyCode::Up => self.state.select_previous(),
+            KeyCode::Down => self.state.select_next(),
+            KeyCode::Char('p') => {
+                let Some(job) = self.selected_job() else {
+                    return Ok(());
+                };
+
+                let action = match job.state {
+                    JobState::Paused | JobState::Failed => Action::ResumeDownload(job.id),
+                    _ => Action::PauseDownload(job.id),
+                };
+                self.action_tx.send(action)?;
+            }
+            KeyCode::Char('c') => {
+                if let Some(job) = self.selected_job() {
+                    self.action_tx.send(Action::CancelDownload(job.id))?;
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn update(&mut self, action: Action) -> Result<()> {
+        if let Action::DisplayDownloads(mut jobs) = action {
+            // The selection follows the job, not the row
+            let selected = self.selected_job().map(|j| j.id);
+
+            jobs.sort_by_key(|j| (state_order(j.state), j.id));
+            self.jobs = jobs;
+
+            let position = selected.and_then(|id| self.jobs.iter().position(|j| j.id == id));
+            self.state.select(position);
+        }
+
+        Ok(())
+    }
+
+    fn draw(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
+        let layout = Layout::vertical([Constraint::Fill(1), Constraint::Length(3)]).split(area);
+
+        let rows: Vec<Row> = self
+            .jobs
+            .iter()
+            .map(|j| {
+                let state = match j.state {
+                    JobState::Running => "Downloading".yellow(),
+                    JobState::Queued => "Queued".dim(),
+                    JobState::Paused => "Paused".magenta(),
+                    JobState::Failed => "Failed".red(),
+                    JobState::Finished => "Finished".green(),
+                };
+                let manga = j.manga_title.clone().unwrap_or(format!("[{}]", j.source));
+                let chapter = j
+                    .chapter_title
+                    .clone()
+                    .unwrap_or(j.chapter_identifier.clone());
+                let progress = match (&j.error, j.pages_total) {
+                    (Some(e), _) => e.clone().red(),
+                    (None, 0) => "".into(),
+                    (None, total) => format!(
+                        "{}/{total} pages ({:.0}%)",
+                        j.pages_done,
+                        j.progress() * 100.0
+                    )
+                    .into(),
+                };
+
+                Row::from_iter([
+                    Cell::from(manga),
+                    Cell::from(chapter),
+                    Cell::from(state),
+                    Cell::from(progress),
+                ])
+            })
+            .collect();
+
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Fill(2),
+                Constraint::Fill(2),
+                Constraint::Length(12),
+                Constraint::Fill(1),
+            ],
+        )
+        .header(Row::from_iter(["Manga", "Chapter", "Status", "Progress"]).bold())
+        .block(Block::bordered().title(" Downloads ".bold().light_yellow()))
+        .column_spacing(2)
+        .highlight_symbol("│ ")
+        .highlight_spacing(ratatui::widgets::HighlightSpacing::Always)
+        .highlight_style(Style::new().yellow().bold());
+
+        let help = Paragraph::new("p: pause/resume/retry  c: cancel  b: back".dim())
+            .block(Block::bordered().padding(Padding::horizontal(1)));
+
+        frame.render_stateful_widget(table, layout[0], &mut self.state);
+        frame.render_widget(help, layout[1]);
+        Ok(())
+    }
+}
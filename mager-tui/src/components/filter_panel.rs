@@ -0,0 +1,907 @@
+use std::collections::HashMap;
+
+use color_eyre::Result;
+use crossterm::event::{KeyCode, KeyEventKind};
+use dto::{
+    ContentRating, Demographic, FieldKind, FieldOption, FieldValue, Filter, FilterField, Order,
+    PublicationStatus, Sort, SortKey, TagMode,
+};
+use ratatui::prelude::*;
+use ratatui::widgets::{Block, Clear, Padding, Paragraph};
+
+use crate::actions::{Action, ActionTx, Command};
+use crate::tui::Event;
+
+use super::tag_picker::TagPickerComponent;
+use super::Component;
+
+/// The rows of the panel, in the order they show
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Field {
+    IncludedTags,
+    TagMode,
+    ExcludedTags,
+    Statuses,
+    ContentRatings,
+    Demographics,
+    Year,
+    OriginalLanguages,
+    Sort,
+}
+
+const FIELDS: [Field; 9] = [
+    Field::IncludedTags,
+    Field::TagMode,
+    Field::ExcludedTags,
+    Field::Statuses,
+    Field::ContentRatings,
+    Field::Demographics,
+    Field::Year,
+    Field::OriginalLanguages,
+    Field::Sort,
+];
+
+impl Field {
+    fn name(self) -> &'static str {
+        match self {
+            Field::IncludedTags => "Tags",
+            Field::TagMode => "Tag mode",
+            Field::ExcludedTags => "Excluded tags",
+            Field::Statuses => "Status",
+            Field::ContentRatings => "Content rating",
+            Field::Demographics => "Demographic",
+            Field::Year => "Year",
+            Field::OriginalLanguages => "Original language",
+            Field::Sort => "Sort",
+        }
+    }
+
+    /// The name of the field in `SourceInfo::filters`
+    fn id(self) -> &'static str {
+        match self {
+            Field::IncludedTags => "included_tags",
+            Field::TagMode => "tag_mode",
+            Field::ExcludedTags => "excluded_tags",
+            Field::Statuses => "statuses",
+            Field::ContentRatings => "content_ratings",
+            Field::Demographics => "demographics",
+            Field::Year => "year",
+            Field::OriginalLanguages => "original_languages",
+            Field::Sort => "sort",
+        }
+    }
+
+    /// How many options can be toggled in the row, for rows that aren't typed in
+    fn options(self) -> usize {
+        match self {
+            Field::Statuses => PublicationStatus::KNOWN.len(),
+            Field::ContentRatings => ContentRating::ALL.len(),
+            Field::Demographics => Demographic::ALL.len(),
+            _ => 0,
+        }
+    }
+}
+
+/// A row of the panel: a filter every source can have, or one the source described
+#[derive(Clone, Copy)]
+enum Row {
+    Common(Field),
+    Source(usize),
+}
+
+/// The options of a field the source described, for fields that have some
+fn kind_options(kind: &FieldKind) -> Option<&[FieldOption]> {
+    match kind {
+        FieldKind::Select { options, .. }
+        | FieldKind::MultiSelect { options }
+        | FieldKind::TriState { options }
+        | FieldKind::Sort { options } => Some(options),
+        FieldKind::Text | FieldKind::Range { .. } => None,
+    }
+}
+
+/// Reads a range typed like `10-20`, `10-`, `-20` or `15`. `-` separates the bounds, so
+/// they can't be negative: `-5` is everything up to 5.
+fn parse_range(text: &str, min: Option<f64>, max: Option<f64>) -> Result<FieldValue, String> {
+    let bound = |b: &str| -> Result<Option<f64>, String> {
+        match b.trim() {
+            "" => Ok(None),
+            b => b
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("{b} is not a number")),
+        }
+    };
+
+    let (start, end) = match text.split_once('-') {
+        Some((start, end)) => (bound(start)?, bound(end)?),
+        None => (bound(text)?, bound(text)?),
+    };
+
+    let outside = |v: Option<f64>| {
+        v.is_some_and(|v| min.is_some_and(|min| v < min) || max.is_some_and(|max| v > max))
+    };
+    if outside(start) || outside(end) {
+        let min = min.map_or(String::from("..."), |min| min.to_string());
+        let max = max.map_or(String::from("..."), |max| max.to_string());
+        return Err(format!("{text} is out of {min}-{max}"));
+    }
+    if let (Some(start), Some(end)) = (start, end) {
+        if start > end {
+            return Err(format!("{text} starts after it ends"));
+        }
+    }
+
+    Ok(FieldValue::Range {
+        min: start,
+        max: end,
+    })
+}
+
+/// How a typed field shows its value, for fields the source described
+fn field_text(value: Option<&FieldValue>) -> String {
+    let bound = |b: &Option<f64>| b.map(|b| b.to_string()).unwrap_or_default();
+    match value {
+        Some(FieldValue::Text(text)) => text.clone(),
+        Some(FieldValue::Range { min, max }) => format!("{}-{}", bound(min), bound(max)),
+        _ => String::new(),
+    }
+}
+
+fn sort_key_name(key: SortKey) -> &'static str {
+    match key {
+        SortKey::Relevance => "Relevance",
+        SortKey::Title => "Title",
+        SortKey::Year => "Year",
+        SortKey::CreatedAt => "Date added",
+        SortKey::UpdatedAt => "Last update",
+        SortKey::LatestChapter => "Latest chapter",
+        SortKey::Follows => "Follows",
+        SortKey::Rating => "Rating",
+    }
+}
+
+fn status_name(status: PublicationStatus) -> &'static str {
+    match status {
+        PublicationStatus::Ongoing => "Ongoing",
+        PublicationStatus::Completed => "Completed",
+        PublicationStatus::Hiatus => "Hiatus",
+        PublicationStatus::Cancelled => "Cancelled",
+        PublicationStatus::Unknown => "Unknown",
+    }
+}
+
+fn rating_name(rating: ContentRating) -> &'static str {
+    match rating {
+        ContentRating::Safe => "Safe",
+        ContentRating::Suggestive => "Suggestive",
+        ContentRating::Erotica => "Erotica",
+        ContentRating::Pornographic => "Pornographic",
+    }
+}
+
+fn demographic_name(demographic: Demographic) -> &'static str {
+    match demographic {
+        Demographic::Shounen => "Shounen",
+        Demographic::Shoujo => "Shoujo",
+        Demographic::Seinen => "Seinen",
+        Demographic::Josei => "Josei",
+        Demographic::None => "None",
+    }
+}
+
+/// Adds the value to the list, or removes it if it is already there
+fn toggle<T: PartialEq>(list: &mut Vec<T>, value: T) {
+    match list.iter().position(|v| *v == value) {
+        Some(i) => {
+            list.remove(i);
+        }
+        None => list.push(value),
+    }
+}
+
+/// Splits a comma separated list, dropping the empty items
+fn split_list(text: &str) -> Vec<String> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Edits a copy of a filter. The copy only replaces the filter once it is applied.
+pub(crate) struct FilterPanelComponent {
+    action_tx: ActionTx,
+    /// The source tags are picked from, if the filter is for a single source
+    source: Option<String>,
+    /// Lists the tags of the source, while tags are being picked
+    tag_picker: Option<TagPickerComponent>,
+    /// The filters only the source has, shown below the other ones
+    fields: Vec<FilterField>,
+    /// The rows shown, only the filters the source takes into account
+    rows: Vec<Row>,
+    /// What the source can sort by
+    sort_keys: Vec<SortKey>,
+    filter: Filter,
+    // The fields that are typed in, as they were typed
+    included_tags: String,
+    excluded_tags: String,
+    year: String,
+    original_languages: String,
+    /// Typed fields the source described, by field id
+    field_texts: HashMap<String, String>,
+    field: usize,
+    /// The option selected in rows that have several
+    option: usize,
+}
+
+impl FilterPanelComponent {
+    /// `filters` lists the filters the source takes into account (`SourceInfo::filters`),
+    /// every one of them is shown when it is `None`
+    pub(crate) fn new(
+        action_tx: ActionTx,
+        source: Option<String>,
+        filters: Option<&[String]>,
+        fields: Vec<FilterField>,
+        sort_keys: Vec<SortKey>,
+        filter: &Filter,
+    ) -> Self {
+        let field_texts = fields
+            .iter()
+            .filter(|f| matches!(f.kind, FieldKind::Text | FieldKind::Range { .. }))
+            .map(|f| (f.id.clone(), field_text(filter.fields.get(&f.id))))
+            .collect();
+
+        let rows = FIELDS
+            .into_iter()
+            .filter(|field| filters.is_none_or(|f| f.iter().any(|f| f == field.id())))
+            .map(Row::Common)
+            .chain((0..fields.len()).map(Row::Source))
+            .collect();
+
+        Self {
+            action_tx,
+            source,
+            tag_picker: None,
+            fields,
+            rows,
+            sort_keys,
+            field_texts,
+            included_tags: filter.included_tags.join(", "),
+            excluded_tags: filter.excluded_tags.join(", "),
+            year: filter.year.map(|y| y.to_string()).unwrap_or_default(),
+            original_languages: filter.original_languages.join(", "),
+            filter: filter.clone(),
+            field: 0,
+            option: 0,
+        }
+    }
+
+    /// The filter with every change made in the panel
+    pub(crate) fn filter(&self) -> Result<Filter, String> {
+        let year = match self.year.trim() {
+            "" => None,
+            year => Some(year.parse().map_err(|_| format!("{year} is not a year"))?),
+        };
+
+        let mut fields = self.filter.fields.clone();
+        for field in &self.fields {
+            let Some(text) = self.field_texts.get(&field.id) else {
+                continue;
+            };
+
+            let value = match (&field.kind, text.trim()) {
+                (_, "") => None,
+                (FieldKind::Range { min, max }, text) => Some(parse_range(text, *min, *max)?),
+                (_, text) => Some(FieldValue::Text(text.to_string())),
+            };
+            match value {
+                Some(value) => fields.insert(field.id.clone(), value),
+                None => fields.remove(&field.id),
+            };
+        }
+
+        Ok(Filter {
+            included_tags: split_list(&self.included_tags),
+            excluded_tags: split_list(&self.excluded_tags),
+            original_languages: split_list(&self.original_languages),
+            year,
+            fields,
+            ..self.filter.clone()
+        })
+    }
+
+    fn row_name(&self, row: Row) -> &str {
+        match row {
+            Row::Common(field) => field.name(),
+            Row::Source(i) => &self.fields[i].label,
+        }
+    }
+
+    /// How many options can be toggled in the row, for rows that aren't typed in
+    fn options(&self, row: Row) -> usize {
+        match row {
+            // The source's default comes before the keys
+            Row::Common(Field::Sort) => self.sort_keys.len() + 1,
+            Row::Common(field) => field.options(),
+            Row::Source(i) => kind_options(&self.fields[i].kind).map_or(0, |o| o.len()),
+        }
+    }
+
+    /// Whether the tag picker is open. It takes the keys that would close the panel.
+    pub(crate) fn is_picking(&self) -> bool {
+        self.tag_picker.is_some()
+    }
+
+    /// Opens the tag picker and asks the source for its tags
+    fn pick_tags(&mut self) -> Result<()> {
+        let Some(source) = self.source.clone() else {
+            let message = "Tags can only be picked when searching in a single source";
+            self.action_tx
+                .send(Action::InvokeError(message.to_string()))?;
+            return Ok(());
+        };
+
+        self.tag_picker = Some(TagPickerComponent::new(
+            source.clone(),
+            self.filter.language.clone(),
+            split_list(&self.included_tags),
+            split_list(&self.excluded_tags),
+        ));
+        self.action_tx
+            .send(Action::RunCommand(source, Command::ListTags))?;
+
+        Ok(())
+    }
+
+    fn text_mut(&mut self, row: Row) -> Option<&mut String> {
+        match row {
+            Row::Common(Field::IncludedTags) => Some(&mut self.included_tags),
+            Row::Common(Field::ExcludedTags) => Some(&mut self.excluded_tags),
+            Row::Common(Field::Year) => Some(&mut self.year),
+            Row::Common(Field::OriginalLanguages) => Some(&mut self.original_languages),
+            Row::Common(_) => None,
+            Row::Source(i) => self.field_texts.get_mut(&self.fields[i].id),
+        }
+    }
+
+    fn text(&self, row: Row) -> Option<&String> {
+        match row {
+            Row::Common(Field::IncludedTags) => Some(&self.included_tags),
+            Row::Common(Field::ExcludedTags) => Some(&self.excluded_tags),
+            Row::Common(Field::Year) => Some(&self.year),
+            Row::Common(Field::OriginalLanguages) => Some(&self.original_languages),
+            Row::Common(_) => None,
+            Row::Source(i) => self.field_texts.get(&self.fields[i].id),
+        }
+    }
+
+    /// Toggles the selected option of a row the source described
+    fn toggle_source(&mut self, i: usize) {
+        let field = &self.fields[i];
+        let Some(option) = kind_options(&field.kind).and_then(|o| o.get(self.option)) else {
+            return;
+        };
+        let option = option.id.clone();
+
+        let values = &mut self.filter.fields;
+        let value = match (&field.kind, values.remove(&field.id)) {
+            (FieldKind::Select { .. }, _) => Some(FieldValue::Select(option)),
+            (FieldKind::MultiSelect { .. }, value) => {
+                let mut chosen = match value {
+                    Some(FieldValue::MultiSelect(chosen)) => chosen,
+                    _ => Vec::new(),
+                };
+                toggle(&mut chosen, option);
+                (!chosen.is_empty()).then_some(FieldValue::MultiSelect(chosen))
+            }
+            (FieldKind::TriState { .. }, value) => {
+                let (mut included, mut excluded) = match value {
+                    Some(FieldValue::TriState { included, excluded }) => (included, excluded),
+                    _ => (Vec::new(), Vec::new()),
+                };
+
+                // Ignored options become included, included ones excluded and excluded
+                // ones ignored
+                if let Some(i) = included.iter().position(|o| *o == option) {
+                    included.remove(i);
+                    excluded.push(option);
+                } else if let Some(i) = excluded.iter().position(|o| *o == option) {
+                    excluded.remove(i);
+                } else {
+                    included.push(option);
+                }
+
+                (!included.is_empty() || !excluded.is_empty())
+                    .then_some(FieldValue::TriState { included, excluded })
+            }
+            // Choosing the option sorted by again flips the direction
+            (
+                FieldKind::Sort { .. },
+                Some(FieldValue::Sort {
+                    option: sorted,
+                    order,
+                }),
+            ) if sorted == option => {
+                let order = match order {
+                    Order::Ascending => Order::Descending,
+                    Order::Descending => Order::Ascending,
+                };
+                Some(FieldValue::Sort { option, order })
+            }
+            (FieldKind::Sort { .. }, _) => Some(FieldValue::Sort {
+                option,
+                order: Order::Descending,
+            }),
+            (FieldKind::Text | FieldKind::Range { .. }, value) => value,
+        };
+
+        if let Some(value) = value {
+            values.insert(field.id.clone(), value);
+        }
+    }
+
+    /// Toggles the selected option of the row
+    fn toggle(&mut self, row: Row) {
+        let field = match row {
+            Row::Common(field) => field,
+            Row::Source(i) => return self.toggle_source(i),
+        };
+
+        let filter = &mut self.filter;
+        match field {
+            Field::TagMode => {
+                filter.tag_mode = match filter.tag_mode {
+                    TagMode::All => TagMode::Any,
+                    TagMode::Any => TagMode::All,
+                }
+            }
+            // Choosing the key sorted by again flips the direction
+            Field::Sort => {
+                let key = match self.option {
+                    0 => None,
+                    i => self.sort_keys.get(i - 1).copied(),
+                };
+                let order = match (key == filter.sort.key, filter.sort.order) {
+                    (true, Order::Ascending) => Order::Descending,
+                    (true, Order::Descending) => Order::Ascending,
+                    (false, order) => order,
+                };
+                filter.sort = Sort { key, order };
+            }
+            Field::Statuses => toggle(&mut filter.statuses, PublicationStatus::KNOWN[self.option]),
+            Field::ContentRatings => {
+                toggle(&mut filter.content_ratings, ContentRating::ALL[self.option])
+            }
+            Field::Demographics => toggle(&mut filter.demographics, Demographic::ALL[self.option]),
+            _ => {}
+        }
+    }
+
+    fn select_field(&mut self, field: usize) {
+        self.field = field.min(self.rows.len().saturating_sub(1));
+        self.option = 0;
+    }
+
+    /// The options of a row, the selected one being highlighted
+    fn options_line(&self, field: Field, selected: bool) -> Line<'static> {
+        let choices: Vec<(&str, bool)> = match field {
+            Field::TagMode => vec![
+                ("every tag", self.filter.tag_mode == TagMode::All),
+                ("any tag", self.filter.tag_mode == TagMode::Any),
+            ],
+            Field::Statuses => PublicationStatus::KNOWN
+                .iter()
+                .map(|&s| (status_name(s), self.filter.statuses.contains(&s)))
+                .collect(),
+            Field::ContentRatings => ContentRating::ALL
+                .iter()
+                .map(|&r| (rating_name(r), self.filter.content_ratings.contains(&r)))
+                .collect(),
+            Field::Demographics => Demographic::ALL
+                .iter()
+                .map(|&d| (demographic_name(d), self.filter.demographics.contains(&d)))
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        // Rows with a single choice show it, the other ones show check boxes
+        let checkboxes = field.options() > 0;
+        let mut spans = Vec::new();
+        for (i, (name, checked)) in choices.into_iter().enumerate() {
+            let span = match (checkboxes, checked) {
+                (true, true) => format!("[x] {name}  ").into(),
+                (true, false) => format!("[ ] {name}  ").dim(),
+                (false, true) => format!("{name}  ").bold(),
+                (false, false) => format!("{name}  ").dim(),
+            };
+
+            spans.push(match selected && checkboxes && i == self.option {
+                true => span.yellow().underlined(),
+                false => span,
+            });
+        }
+
+        Line::from(spans)
+    }
+
+    /// The keys that can be sorted by, with the direction next to the one sorted by
+    fn sort_line(&self, selected: bool) -> Line<'static> {
+        let arrow = match self.filter.sort.order {
+            Order::Ascending => "↑",
+            Order::Descending => "↓",
+        };
+
+        let keys = std::iter::once(None).chain(self.sort_keys.iter().copied().map(Some));
+        let spans = keys.enumerate().map(|(n, key)| {
+            let name = key.map_or("Default", sort_key_name);
+            let span = match key == self.filter.sort.key {
+                true => format!("{name} {arrow}  ").bold(),
+                false => format!("{name}  ").dim(),
+            };
+
+            match selected && n == self.option {
+                true => span.yellow().underlined(),
+                false => span,
+            }
+        });
+
+        Line::from_iter(spans)
+    }
+
+    /// The options of a row the source described, the selected one being highlighted
+    fn source_options_line(&self, i: usize, selected: bool) -> Line<'static> {
+        let field = &self.fields[i];
+        let value = self.filter.fields.get(&field.id);
+        let options = kind_options(&field.kind).unwrap_or_default();
+
+        let spans = options.iter().enumerate().map(|(n, option)| {
+            let (id, label) = (&option.id, &option.label);
+            let span = match (&field.kind, value) {
+                (FieldKind::Select { default, .. }, value) => {
+                    let chosen = match value {
+                        Some(FieldValue::Select(chosen)) => chosen == id,
+                        _ => default.as_ref() == Some(id),
+                    };
+                    match chosen {
+                        true => format!("{label}  ").bold(),
+                        false => format!("{label}  ").dim(),
+                    }
+                }
+                (FieldKind::MultiSelect { .. }, Some(FieldValue::MultiSelect(chosen)))
+                    if chosen.contains(id) =>
+                {
+                    format!("[x] {label}  ").into()
+                }
+                (FieldKind::MultiSelect { .. }, _) => format!("[ ] {label}  ").dim(),
+                (FieldKind::TriState { .. }, Some(FieldValue::TriState { included, .. }))
+                    if included.contains(id) =>
+                {
+                    format!("[+] {label}  ").green()
+                }
+                (FieldKind::TriState { .. }, Some(FieldValue::TriState { excluded, .. }))
+                    if excluded.contains(id) =>
+                {
+                    format!("[-] {label}  ").red()
+                }
+                (FieldKind::TriState { .. }, _) => format!("[ ] {label}  ").dim(),
+                (FieldKind::Sort { .. }, Some(FieldValue::Sort { option, order }))
+                    if option == id =>
+                {
+                    let arrow = match order {
+                        Order::Ascending => "↑",
+                        Order::Descending => "↓",
+                    };
+                    format!("{label} {arrow}  ").bold()
+                }
+                _ => format!("{label}  ").dim(),
+            };
+
+            match selected && n == self.option {
+                true => span.yellow().underlined(),
+                false => span,
+            }
+        });
+
+        Line::from_iter(spans)
+    }
+}
+
+impl Component for FilterPanelComponent {
+    fn handle_events(&mut self, event: Event) -> Result<()> {
+        let Event::Key(k_event) = event else {
+            return Ok(());
+        };
+
+        let KeyEventKind::Press = k_event.kind else {
+            return Ok(());
+        };
+
+        if let Some(picker) = self.tag_picker.as_mut() {
+            match k_event.code {
+                KeyCode::Enter => {
+                    let (included, excluded) = picker.picked();
+                    self.included_tags = included.join(", ");
+                    self.excluded_tags = excluded.join(", ");
+                    self.tag_picker = None;
+                }
+                KeyCode::Esc => self.tag_picker = None,
+                _ => picker.handle_events(event)?,
+            }
+            return Ok(());
+        }
+
+        let Some(&row) = self.rows.get(self.field) else {
+            return Ok(());
+        };
+        let options = self.options(row);
+        match k_event.code {
+            KeyCode::Tab
+                if matches!(row, Row::Common(Field::IncludedTags | Field::ExcludedTags)) =>
+            {
+                self.pick_tags()?
+            }
+            KeyCode::Up => self.select_field(self.field.saturating_sub(1)),
+            KeyCode::Down => self.select_field(self.field + 1),
+            KeyCode::Left if options > 0 => self.option = self.option.saturating_sub(1),
+            KeyCode::Right if options > 0 => self.option = (self.option + 1).min(options - 1),
+            KeyCode::Backspace => {
+                if let Some(text) = self.text_mut(row) {
+                    text.pop();
+                }
+            }
+            KeyCode::Char(c) => match self.text_mut(row) {
+                Some(text) => text.push(c),
+                None if c == ' ' => self.toggle(row),
+                None => {}
+            },
+            KeyCode::Left | KeyCode::Right => self.toggle(row),
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn update(&mut self, action: Action) -> Result<()> {
+        if let Some(picker) = self.tag_picker.as_mut() {
+            picker.update(action)?;
+        }
+        Ok(())
+    }
+
+    fn draw(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
+        let outer = area;
+        let width = area.width.min(96);
+        let height = (self.rows.len() as u16 + 4).min(area.height);
+        let area = Rect {
+            x: area.x + (area.width - width) / 2,
+            y: area.y + (area.height - height) / 2,
+            width,
+            height,
+        };
+
+        let name_width = (self.rows.iter())
+            .map(|&row| self.row_name(row).chars().count() + 1)
+            .max()
+            .unwrap_or_default()
+            .max(18);
+
+        let lines: Vec<Line> = (self.rows.iter().enumerate())
+            .map(|(i, &row)| {
+                let selected = i == self.field;
+                let name = format!("{:<name_width$}", self.row_name(row));
+                let name = match selected {
+                    true => name.yellow().bold(),
+                    false => name.bold(),
+                };
+
+                let value = match self.text(row) {
+                    Some(text) if selected => Line::from_iter([text.clone().into(), " ".on_gray()]),
+                    Some(text) if text.is_empty() => Line::from("any".dim()),
+                    Some(text) => Line::from(text.clone()),
+                    None => match row {
+                        Row::Common(Field::Sort) => self.sort_line(selected),
+                        Row::Common(field) => self.options_line(field, selected),
+                        Row::Source(i) => self.source_options_line(i, selected),
+                    },
+                };
+
+                let mut spans = vec![name];
+                spans.extend(value.spans);
+                Line::from(spans)
+            })
+            .collect();
+
+        let hint = match self.rows.get(self.field) {
+            Some(Row::Common(Field::IncludedTags | Field::ExcludedTags))
+                if self.source.is_some() =>
+            {
+                " ↑/↓: field  tab: pick tags  enter: apply  esc: cancel "
+            }
+            _ => " ↑/↓: field  ←/→: option  space: toggle  enter: apply  esc: cancel ",
+        };
+        let block = Block::bordered()
+            .padding(Padding::horizontal(1))
+            .title(" Filters ".bold().light_yellow())
+            .title_bottom(hint.dim());
+
+        frame.render_widget(Clear, area);
+        frame.render_widget(Paragraph::new(lines).block(block), area);
+
+        if let Some(picker) = self.tag_picker.as_mut() {
+            picker.draw(frame, outer)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    fn range(min: Option<f64>, max: Option<f64>) -> FieldValue {
+        FieldValue::Range { min, max }
+    }
+
+    fn options(ids: &[&str]) -> Vec<FieldOption> {
+        ids.iter()
+            .map(|id| FieldOption {
+                id: id.to_string(),
+                label: id.to_string(),
+            })
+            .collect()
+    }
+
+    fn panel(fields: Vec<FilterField>, sort_keys: Vec<SortKey>) -> FilterPanelComponent {
+        let (action_tx, _) = mpsc::unbounded_channel();
+        FilterPanelComponent::new(action_tx, None, None, fields, sort_keys, &Filter::default())
+    }
+
+    #[test]
+    fn unsupported_filters_hidden() {
+        let (action_tx, _) = mpsc::unbounded_channel();
+        let fields = vec![FilterField {
+            id: String::from("chapters"),
+            label: String::from("Chapters"),
+            kind: FieldKind::Text,
+        }];
+        let filters = [String::from("year"), String::from("authors")];
+        let panel = FilterPanelComponent::new(
+            action_tx,
+            None,
+            Some(&filters),
+            fields,
+            Vec::new(),
+            &Filter::default(),
+        );
+
+        let names: Vec<&str> = panel.rows.iter().map(|&row| panel.row_name(row)).collect();
+        assert_eq!(names, ["Year", "Chapters"]);
+    }
+
+    #[test]
+    fn ranges() {
+        let (min, max) = (Some(0.0), Some(100.0));
+        assert_eq!(
+            parse_range("10-20", min, max),
+            Ok(range(Some(10.0), Some(20.0)))
+        );
+        assert_eq!(parse_range("10-", min, max), Ok(range(Some(10.0), None)));
+        assert_eq!(parse_range("-20", min, max), Ok(range(None, Some(20.0))));
+        assert_eq!(
+            parse_range(" 15 ", min, max),
+            Ok(range(Some(15.0), Some(15.0)))
+        );
+        // Bounds can't be negative, the dash means "up to"
+        assert_eq!(parse_range("-5", None, None), Ok(range(None, Some(5.0))));
+
+        assert!(parse_range("10-200", min, max).is_err());
+        assert!(parse_range("150", min, max).is_err());
+        assert!(parse_range("20-10", min, max).is_err());
+        assert!(parse_range("ten", min, max).is_err());
+    }
+
+    #[test]
+    fn tri_state_cycle() {
+        let field = FilterField {
+            id: String::from("genres"),
+            label: String::from("Genres"),
+            kind: FieldKind::TriState {
+                options: options(&["action", "drama"]),
+            },
+        };
+        let mut panel = panel(vec![field], Vec::new());
+        let value = |panel: &FilterPanelComponent| panel.filter.fields.get("genres").cloned();
+        let tri_state = |included: &[&str], excluded: &[&str]| {
+            Some(FieldValue::TriState {
+                included: included.iter().map(|s| s.to_string()).collect(),
+                excluded: excluded.iter().map(|s| s.to_string()).collect(),
+            })
+        };
+
+        panel.option = 1;
+        panel.toggle_source(0);
+        assert_eq!(value(&panel), tri_state(&["drama"], &[]));
+        panel.toggle_source(0);
+        assert_eq!(value(&panel), tri_state(&[], &["drama"]));
+        panel.option = 0;
+        panel.toggle_source(0);
+        assert_eq!(value(&panel), tri_state(&["action"], &["drama"]));
+
+        panel.option = 1;
+        panel.toggle_source(0);
+        panel.option = 0;
+        panel.toggle_source(0);
+        panel.toggle_source(0);
+        // Nothing chosen is the same as no value
+        assert_eq!(value(&panel), None);
+    }
+
+    #[test]
+    fn source_sort_cycle() {
+        let field = FilterField {
+            id: String::from("sort"),
+            label: String::from("Sort"),
+            kind: FieldKind::Sort {
+                options: options(&["views", "name"]),
+            },
+        };
+        let mut panel = panel(vec![field], Vec::new());
+        let sort = |option: &str, order| {
+            Some(FieldValue::Sort {
+                option: option.to_string(),
+                order,
+            })
+        };
+
+        panel.toggle_source(0);
+        assert_eq!(
+            panel.filter.fields.get("sort").cloned(),
+            sort("views", Order::Descending)
+        );
+        panel.toggle_source(0);
+        assert_eq!(
+            panel.filter.fields.get("sort").cloned(),
+            sort("views", Order::Ascending)
+        );
+
+        // Another option starts descending again
+        panel.option = 1;
+        panel.toggle_source(0);
+        assert_eq!(
+            panel.filter.fields.get("sort").cloned(),
+            sort("name", Order::Descending)
+        );
+    }
+
+    #[test]
+    fn sort_cycle() {
+        let mut panel = panel(Vec::new(), vec![SortKey::Title, SortKey::Rating]);
+        let sort_row = Row::Common(Field::Sort);
+
+        panel.option = 1;
+        panel.toggle(sort_row);
+        assert_eq!(panel.filter.sort.key, Some(SortKey::Title));
+        // The direction is kept when the key changes, and flips when it doesn't
+        assert_eq!(panel.filter.sort.order, Order::Descending);
+        panel.toggle(sort_row);
+        assert_eq!(panel.filter.sort.order, Order::Ascending);
+
+        panel.option = 2;
+        panel.toggle(sort_row);
+        assert_eq!(panel.filter.sort.key, Some(SortKey::Rating));
+        assert_eq!(panel.filter.sort.order, Order::Ascending);
+
+        // The first option leaves it to the source
+        panel.option = 0;
+        panel.toggle(sort_row);
+        assert_eq!(panel.filter.sort.key, None);
+
+        panel.toggle(Row::Common(Field::TagMode));
+        assert_ne!(panel.filter.tag_mode, TagMode::default());
+        panel.toggle(Row::Common(Field::TagMode));
+        assert_eq!(panel.filter.tag_mode, TagMode::default());
+    }
+}
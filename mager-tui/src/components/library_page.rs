@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+
+use color_eyre::Result;
+use crossterm::event::{KeyCode, KeyEventKind};
+use mager_core::library::LibraryEntry;
+use ratatui::prelude::*;
+use ratatui::widgets::{Block, Cell, Padding, Paragraph, Row, Table, TableState};
+
+use crate::actions::{Action, ActionTx, Page, UpdateOutcome};
+use crate::tui::Event;
+
+use super::Component;
+
+/// The manga the user follows. This is the first page, it works without any active source.
+pub struct LibraryPage {
+    action_tx: ActionTx,
+    entries: Vec<LibraryEntry>,
+    /// How the last update check went, by source and manga identifier
+    updates: HashMap<(String, String), UpdateOutcome>,
+    state: TableState,
+}
+
+impl LibraryPage {
+    pub(crate) fn new(action_tx: ActionTx) -> Self {
+        action_tx.send(Action::FetchLibrary).unwrap();
+
+        Self {
+            action_tx,
+            entries: Vec::new(),
+            updates: HashMap::new(),
+            state: TableState::default(),
+        }
+    }
+
+    fn selected_entry(&self) -> Option<&LibraryEntry> {
+        self.state.selected().and_then(|i| self.entries.get(i))
+    }
+}
+
+impl Component for LibraryPage {
+    fn handle_events(&mut self, event: Event) -> Result<()> {
+        let Event::Key(k_event) = event else {
+            return Ok(());
+        };
+
+        let KeyEventKind::Press = k_event.kind else {
+            return Ok(());
+        };
+
+        match k_event.code {
+            KeyCode::Char('q') => self.action_tx.send(Action::Quit)?,
+            KeyCode::Char('s') => self.action_tx.send(Action::NextPage(Page::Sources))?,
+            KeyCode::Char('a') => self
+                .action_tx
+                .send(Action::NextPage(Page::SearchEverywhere))?,
+            KeyCode::Char('D') => self.action_tx.send(Action::NextPage(Page::Downloads))?,
+            KeyCode::Char('u') => self.action_tx.send(Action::CheckUpdates(false))?,
+            KeyCode::Char('U') => self.action_tx.send(Action::CheckUpdates(true))?,
+            KeyCode::Up => self.state.select_previous(),
+            KeyCode::Down => self.state.select_next(),
+            KeyCode::Enter => {
+                if let Some(entry) = self.selected_entry() {
+                    self.action_tx.send(Action::OpenManga(
+                        entry.source.clone(),
+                        entry.manga.identifier.clone(),
+                    ))?;
+                }
+            }
+            KeyCode::Char('d') => {
+                if let Some(entry) = self.selected_entry() {
+                    self.action_tx.send(Action::RemoveFromLibrary(
+                        entry.source.clone(),
+                        entry.manga.identifier.clone(),
+                    ))?;
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn update(&mut self, action: Action) -> Result<()> {
+        match action {
+            Action::DisplayLibrary(entries) => {
+                self.entries = entries;
+
+                if self.entries.is_empty() {
+                    self.state.select(None);
+                } else if self.state.selected().is_none() {
+                    self.state.select(Some(0));
+                }
+            }
+            Action::DisplayUpdate(report) => {
+                self.updates
+                    .insert((report.source, report.identifier), report.outcome);
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn draw(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
+        let layout = Layout::vertical([Constraint::Fill(1), Constraint::Length(3)]).split(area);
+
+        let rows: Vec<Row> = self
+            .entries
+            .iter()
+            .map(|e| {
+                let unread = match e.unread {
+                    0 => "".into(),
+                    n => format!("{n} unread").yellow(),
+                };
+                let key = (e.source.clone(), e.manga.identifier.clone());
+                let update = match self.updates.get(&key) {
+                    None => "".into(),
+                    Some(UpdateOutcome::Checking) => "checking...".dim(),
+                    Some(UpdateOutcome::Found(0)) => "up to date".dim(),
+                    Some(UpdateOutcome::Found(n)) => format!("{n} new").green(),
+                    Some(UpdateOutcome::Failed(e)) => e.clone().red(),
+                };
+
+                Row::from_iter([
+                    Cell::from(e.manga.title.clone()),
+                    Cell::from(e.source.clone().cyan()),
+                    Cell::from(unread),
+                    Cell::from(format!("{} chapters", e.chapters)),
+                    Cell::from(format!("{} downloaded", e.downloaded).dim()),
+                    Cell::from(update),
+                ])
+            })
+            .collect();
+
+        let mut block = Block::bordered().title(" Library ".bold().light_yellow());
+        if self.entries.is_empty() {
+            block =
+                block.title_bottom(" Nothing here yet, add manga from their details page ".dim());
+        }
+
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Fill(1),
+                Constraint::Length(16),
+                Constraint::Length(12),
+                Constraint::Length(14),
+                Constraint::Length(16),
+                Constraint::Fill(1),
+            ],
+        )
+        .header(Row::from_iter(["Title", "Source", "", "", "", "Update"]).bold())
+        .block(block)
+        .column_spacing(2)
+        .highlight_symbol("│ ")
+        .highlight_spacing(ratatui::widgets::HighlightSpacing::Always)
+        .highlight_style(Style::new().yellow().bold());
+
+        let help = Paragraph::new(
+            "enter: open  d: remove  u: check for new chapters  U: check and download  \
+             s: sources  a: search everywhere  D: downloads  q: quit"
+                .dim(),
+        )
+        .block(Block::bordered().padding(Padding::horizontal(1)));
+
+        frame.render_stateful_widget(table, layout[0], &mut self.state);
+        frame.render_widget(help, layout[1]);
+        Ok(())
+    }
+}
@@ -1,14 +1,18 @@
 use color_eyre::eyre::Result;
 use crossterm::event::{KeyCode, KeyEventKind};
-use dto::{Chapter, ChapterListEntry, Filter, Manga};
+use dto::{ChapterListEntry, Filter, Manga};
+use mager_core::library::{LibraryChapter, ReadingProgress};
 use ratatui::prelude::*;
 use ratatui::widgets::{Block, Cell, Padding, Paragraph, Row, Table, TableState, Wrap};
 
 use crate::actions::*;
+use crate::export::ExportFormat;
 use crate::tui::Event;
 
+use super::reader_page::reading_order;
 use super::Component;
 
+#[allow(dead_code)]
 enum Focus {
     ChapterList,
     MangaDesc,
@@ -16,6 +20,7 @@ enum Focus {
 }
 
 pub struct MangaDetailsPage {
+    source: String,
     manga: Option<Manga>,
     action_tx: ActionTx,
     chapter_table: ChapterTableComponent,
@@ -24,11 +29,12 @@ pub struct MangaDetailsPage {
 }
 
 impl MangaDetailsPage {
-    pub(crate) fn new(action_tx: ActionTx) -> Self {
+    pub(crate) fn new(action_tx: ActionTx, source: String, filter: Filter) -> Self {
         Self {
             manga: None,
-            chapter_table: ChapterTableComponent::new(action_tx.clone()),
-            manga_details: MangaDetailsComponent::new(action_tx.clone()),
+            chapter_table: ChapterTableComponent::new(action_tx.clone(), source.clone(), filter),
+            manga_details: MangaDetailsComponent::new(action_tx.clone(), source.clone()),
+            source,
             focus: Focus::ChapterList,
             action_tx,
         }
@@ -40,6 +46,7 @@ impl Component for MangaDetailsPage {
         if let Event::Key(k) = event.clone() {
             match k.code {
                 KeyCode::Char('b') => self.action_tx.send(Action::PrevPage)?,
+                KeyCode::Char('D') => self.action_tx.send(Action::NextPage(Page::Downloads))?,
                 KeyCode::Char('s') => todo!(),
                 _ => {}
             }
@@ -55,8 +62,17 @@ impl Component for MangaDetailsPage {
     }
 
     fn update(&mut self, action: Action) -> Result<()> {
-        if let Action::SetActiveManga(manga) = action.clone() {
-            self.manga = Some(manga);
+        match &action {
+            Action::SetActiveManga(source, _)
+            | Action::DisplayChapterList(source, _)
+            | Action::DisplayLibraryChapters(source, _, _)
+            | Action::DisplayProgress(source, _, _, _)
+                if *source != self.source =>
+            {
+                return Ok(());
+            }
+            Action::SetActiveManga(_, manga) => self.manga = Some(manga.clone()),
+            _ => {}
         }
 
         self.chapter_table.update(action.clone())?;
@@ -77,13 +93,16 @@ impl Component for MangaDetailsPage {
 }
 
 struct MangaDetailsComponent {
+    source: String,
     active_manga: Option<Manga>,
+    #[allow(dead_code)]
     action_tx: ActionTx,
 }
 
 impl MangaDetailsComponent {
-    fn new(action_tx: ActionTx) -> Self {
+    fn new(action_tx: ActionTx, source: String) -> Self {
         Self {
+            source,
             action_tx,
             active_manga: None,
         }
@@ -132,6 +151,7 @@ impl MangaDetailsComponent {
         ]);
         let original_lang =
             Line::from_iter(["Language: ".bold(), m.original_language.clone().into()]);
+        let source = Line::from_iter(["Source: ".bold(), self.source.clone().cyan()]);
 
         let manga_info = Paragraph::new(Text::from_iter([
             identifier,
@@ -139,6 +159,7 @@ impl MangaDetailsComponent {
             status,
             authors,
             original_lang,
+            source,
         ]))
         .wrap(Wrap { trim: false })
         .block(block.clone().title(" Manga Info ".bold().light_yellow()));
@@ -162,7 +183,7 @@ impl Component for MangaDetailsComponent {
     }
 
     fn update(&mut self, action: Action) -> Result<()> {
-        if let Action::SetActiveManga(m) = action.clone() {
+        if let Action::SetActiveManga(_, m) = action.clone() {
             self.active_manga = Some(m);
         }
 
@@ -180,17 +201,33 @@ impl Component for MangaDetailsComponent {
 }
 
 struct ChapterTableComponent {
+    source: String,
+    download_format: ExportFormat,
     active_manga: Option<Manga>,
     action_tx: ActionTx,
     state: TableState,
     chapters: Vec<ChapterListEntry>,
     curr_page: u32,
     max_page: u32,
+    /// The filter the next chapter pages are requested with
+    filter: Filter,
+    /// What the library knows of the chapters, `None` if the manga isn't in the library
+    library: Option<Vec<LibraryChapter>>,
+    /// How far the chapters that were opened were read
+    progress: Vec<ReadingProgress>,
+    /// The identifier of the chapter that was read last
+    last_read: Option<String>,
 }
 
 impl ChapterTableComponent {
-    fn new(action_tx: ActionTx) -> Self {
+    fn new(action_tx: ActionTx, source: String, filter: Filter) -> Self {
         Self {
+            source,
+            filter,
+            library: None,
+            progress: Vec::new(),
+            last_read: None,
+            download_format: ExportFormat::default(),
             active_manga: None,
             action_tx,
             state: TableState::default(),
@@ -201,6 +238,90 @@ impl ChapterTableComponent {
     }
 }
 
+impl ChapterTableComponent {
+    /// Has the library remember the chapters, if the manga is in it
+    fn track_chapters(&self, chapters: &[ChapterListEntry]) -> Result<()> {
+        let Some(manga) = self.active_manga.as_ref() else {
+            return Ok(());
+        };
+
+        self.action_tx.send(Action::TrackChapters(
+            self.source.clone(),
+            manga.identifier.clone(),
+            chapters.to_vec(),
+        ))?;
+        Ok(())
+    }
+
+    fn known_chapter(&self, identifier: &str) -> Option<&LibraryChapter> {
+        self.library
+            .as_ref()
+            .and_then(|l| l.iter().find(|c| c.identifier == identifier))
+    }
+
+    fn progress_of(&self, identifier: &str) -> Option<&ReadingProgress> {
+        self.progress
+            .iter()
+            .find(|p| p.chapter_identifier == identifier)
+    }
+
+    /// Whether the chapter was read. The library has the last word on the chapters it
+    /// knows, since they can be marked as read by hand.
+    fn is_read(&self, identifier: &str) -> bool {
+        match self.known_chapter(identifier) {
+            Some(known) => known.read,
+            None => self.progress_of(identifier).is_some_and(|p| p.finished()),
+        }
+    }
+
+    /// Opens the reader on the chapter, where it was left if it wasn't finished
+    fn read(&self, identifier: &str) -> Result<()> {
+        let page = match self.progress_of(identifier) {
+            Some(p) if !p.finished() => p.page,
+            _ => 0,
+        };
+
+        self.action_tx.send(Action::NextPage(Page::Reader(
+            self.source.clone(),
+            self.chapters.clone(),
+            identifier.to_string(),
+            page,
+        )))?;
+        Ok(())
+    }
+
+    /// Resumes the chapter that was read last, or starts the next one if it was finished
+    fn continue_reading(&self) -> Result<()> {
+        let mut chapters = self.chapters.clone();
+        reading_order(&mut chapters);
+
+        let Some(last_read) = self.last_read.as_ref() else {
+            if let Some(first) = chapters.first() {
+                self.read(&first.identifier)?;
+            }
+            return Ok(());
+        };
+
+        let Some(i) = chapters.iter().position(|c| &c.identifier == last_read) else {
+            self.action_tx.send(Action::InvokeError(String::from(
+                "The chapter read last isn't listed yet, scroll down the chapter list",
+            )))?;
+            return Ok(());
+        };
+
+        let finished = self.progress_of(last_read).is_some_and(|p| p.finished());
+        match (finished, chapters.get(i + 1)) {
+            (false, _) => self.read(last_read)?,
+            (true, Some(next)) => self.read(&next.identifier)?,
+            (true, None) => self.action_tx.send(Action::InvokeError(String::from(
+                "Every listed chapter was read",
+            )))?,
+        }
+
+        Ok(())
+    }
+}
+
 impl Component for ChapterTableComponent {
     fn handle_events(&mut self, event: Event) -> Result<()> {
         let Event::Key(k_event) = event else {
@@ -233,25 +354,97 @@ impl Component for ChapterTableComponent {
                         let command = Command::FetchChapterList {
                             identifier: m.identifier.clone(),
                             page: self.curr_page + 1,
-                            filter: Filter::default(),
+                            filter: self.filter.clone(),
                         };
-                        self.action_tx.send(Action::RunCommand(command))?;
+                        self.action_tx
+                            .send(Action::RunCommand(self.source.clone(), command))?;
                     }
                 }
             }
             KeyCode::Enter => {
-                let Some(i) = self.state.selected() else {
+                let Some(chapter) = self.state.selected().and_then(|i| self.chapters.get(i)) else {
                     return Ok(());
                 };
 
-                let Some(m) = self.active_manga.as_ref() else {
+                self.read(&chapter.identifier)?;
+            }
+            KeyCode::Char('c') => self.continue_reading()?,
+            KeyCode::Char('d') => {
+                let Some(i) = self.state.selected() else {
                     return Ok(());
                 };
 
+                if self.active_manga.is_none() {
+                    return Ok(());
+                }
+
                 let selected_chapter = self.chapters.get(i).unwrap();
 
-                self.action_tx
-                    .send(Action::DownloadChapter(selected_chapter.identifier.clone()))?;
+                self.action_tx.send(Action::DownloadChapter(
+                    self.source.clone(),
+                    selected_chapter.identifier.clone(),
+                    self.download_format,
+                ))?;
+            }
+            KeyCode::Char('v') => {
+                let Some(chapter) = self.state.selected().and_then(|i| self.chapters.get(i)) else {
+                    return Ok(());
+                };
+
+                if chapter.volume.is_none() {
+                    self.action_tx.send(Action::InvokeError(String::from(
+                        "This chapter doesn't belong to any volume",
+                    )))?;
+                    return Ok(());
+                }
+
+                self.action_tx.send(Action::DownloadVolume(
+                    self.source.clone(),
+                    chapter.identifier.clone(),
+                    self.download_format,
+                ))?;
+            }
+            KeyCode::Char('f') => self.download_format = self.download_format.next(),
+            KeyCode::Char('l') => {
+                let Some(manga) = self.active_manga.as_ref() else {
+                    return Ok(());
+                };
+
+                let action = match self.library {
+                    Some(_) => {
+                        Action::RemoveFromLibrary(self.source.clone(), manga.identifier.clone())
+                    }
+                    None => Action::AddToLibrary(self.source.clone(), manga.clone()),
+                };
+                self.action_tx.send(action)?;
+            }
+            KeyCode::Char('r') => {
+                let Some(i) = self.state.selected() else {
+                    return Ok(());
+                };
+                let Some(chapter) = self.chapters.get(i) else {
+                    return Ok(());
+                };
+                let Some(known) = self
+                    .library
+                    .as_mut()
+                    .and_then(|l| l.iter_mut().find(|c| c.identifier == chapter.identifier))
+                else {
+                    return Ok(());
+                };
+
+                known.read = !known.read;
+                self.action_tx.send(Action::SetChapterRead(
+                    self.source.clone(),
+                    known.identifier.clone(),
+                    known.read,
+                ))?;
+
+                // Chapters marked as unread start over
+                if !known.read {
+                    let identifier = known.identifier.clone();
+                    self.progress.retain(|p| p.chapter_identifier != identifier);
+                }
             }
             _ => {}
         }
@@ -261,20 +454,36 @@ impl Component for ChapterTableComponent {
 
     fn update(&mut self, action: Action) -> Result<()> {
         match action {
-            Action::DisplayChapterList(mut r) => {
+            Action::DisplayChapterList(_, mut r) => {
+                if self.library.is_some() {
+                    self.track_chapters(&r.data)?;
+                }
                 self.chapters.append(&mut r.data);
                 self.curr_page += 1;
                 self.max_page = r.total_page;
             }
-            Action::SetActiveManga(m) => {
+            Action::SetActiveManga(_, m) => {
                 self.active_manga = Some(m);
+            }
+            Action::DisplayLibraryChapters(_, identifier, chapters) => {
+                if self.active_manga.as_ref().map(|m| &m.identifier) != Some(&identifier) {
+                    return Ok(());
+                }
+
+                // The chapters listed before the manga was added are tracked too
+                let added = self.library.is_none() && chapters.is_some();
+                self.library = chapters;
+                if added {
+                    self.track_chapters(&self.chapters)?;
+                }
+            }
+            Action::DisplayProgress(_, identifier, progress, last_read) => {
+                if self.active_manga.as_ref().map(|m| &m.identifier) != Some(&identifier) {
+                    return Ok(());
+                }
 
-                self.action_tx
-                    .send(Action::RunCommand(Command::FetchChapterList {
-                        identifier: self.active_manga.as_ref().unwrap().identifier.clone(),
-                        page: 1,
-                        filter: Filter::default(),
-                    }))?;
+                self.progress = progress;
+                self.last_read = last_read;
             }
             _ => {}
         }
@@ -283,7 +492,22 @@ impl Component for ChapterTableComponent {
 
     fn draw(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
         // for me next morning: Implement draw. fuck it, we go back to initial design
-        let mut block = Block::bordered().title(" Chapter List ".bold().light_yellow());
+        let (title, library_help) = match self.library {
+            Some(_) => (
+                " Chapter List (in library) ",
+                "l: remove from library  r: mark read",
+            ),
+            None => (" Chapter List ", "l: add to library"),
+        };
+        let block = Block::bordered()
+            .title(title.bold().light_yellow())
+            .title_bottom(
+                format!(
+                    " enter: read  c: continue reading  d: download as {}  f: change format  v: download the volume  {library_help} ",
+                    self.download_format.name()
+                )
+                .dim(),
+            );
 
         // if let Focus::ChapterList = self.focus {
         //     block = block.not_dim();
@@ -293,27 +517,55 @@ impl Component for ChapterTableComponent {
             .chapters
             .iter()
             .map(|c| {
-                Row::from_iter([
+                let known = self.known_chapter(&c.identifier);
+                let downloaded = match known.map(|k| k.downloaded) {
+                    Some(true) => "↓".green(),
+                    _ => "".into(),
+                };
+
+                let read = self.is_read(&c.identifier);
+                let progress = match self.progress_of(&c.identifier) {
+                    _ if read => "read".into(),
+                    Some(p) => format!("p. {}/{}", p.page + 1, p.pages).yellow(),
+                    None => "●".blue(),
+                };
+                let last_read = match self.last_read.as_ref() == Some(&c.identifier) {
+                    true => "◀".light_yellow(),
+                    false => "".into(),
+                };
+
+                let row = Row::from_iter([
+                    Cell::from(downloaded),
                     Cell::from(Text::from(c.number.clone()).alignment(Alignment::Left)),
                     Cell::from(Text::from(c.title.clone()).alignment(Alignment::Left)),
+                    Cell::from(Line::from_iter([progress, " ".into(), last_read])),
                     Cell::from(Text::from("00-00-0000").alignment(Alignment::Center)),
                 ])
-                .bottom_margin(1)
+                .bottom_margin(1);
+
+                match read {
+                    true => row.dim(),
+                    false => row,
+                }
             })
             .collect();
 
         let table = Table::new(
             rows,
             vec![
+                Constraint::Length(1),
                 Constraint::Length(4),
                 Constraint::Fill(1),
+                Constraint::Length(12),
                 Constraint::Length(17),
             ],
         )
         .header(
             Row::from_iter([
+                Text::from(""),
                 Text::from("Num."),
                 Text::from("Title"),
+                Text::from("Progress"),
                 Text::from("Release Date").alignment(Alignment::Center),
             ])
             .bold(),
@@ -1,43 +1,183 @@
 use color_eyre::Result;
 use crossterm::event::{KeyCode, KeyEventKind};
-use dto::{Filter, Manga, MangaListEntry};
+use dto::carriers::SourceInfo;
+use dto::{BrowseMode, Filter, FilterField, MangaListEntry, SortKey};
 use ratatui::prelude::*;
-use ratatui::widgets::{Block, List, ListItem, ListState};
+use ratatui::widgets::{Block, List, ListItem, ListState, Tabs};
 
 use crate::actions::*;
 use crate::tui::Event;
 
+use super::filter_panel::FilterPanelComponent;
 use super::search_bar::SearchBarComponent;
 use super::Component;
 
+fn mode_name(mode: BrowseMode) -> &'static str {
+    match mode {
+        BrowseMode::Latest => "Latest updates",
+        BrowseMode::Popular => "Popular",
+        BrowseMode::TopRated => "Top rated",
+        BrowseMode::RecentlyAdded => "Recently added",
+    }
+}
+
 pub enum Focus {
     MangaList,
     SearchBar,
+    Filters,
 }
 
 pub struct MangaListPage {
     search_bar: SearchBarComponent,
-    manga_list: MangaListComponent,
+    // Pages opened from the sources list search in their own source, the other ones
+    // can only search everywhere
+    manga_list: Option<MangaListComponent>,
+    federated_results: FederatedResultsComponent,
+    everywhere: bool,
+    /// The filter searches are sent with
+    filter: Filter,
+    /// The filters the source of the page takes into account, all of them if it is unknown
+    filters: Option<Vec<String>>,
+    /// The filters only the source of the page has
+    filter_fields: Vec<FilterField>,
+    /// What the source of the page can sort by
+    sort_keys: Vec<SortKey>,
+    /// The filter being edited, while the filter panel is open
+    filter_panel: Option<FilterPanelComponent>,
+    /// The keyword searched last, searched again when the filter changes
+    keyword: Option<String>,
+    /// The lists the source can be browsed by while there is no keyword
+    browse_modes: Vec<BrowseMode>,
+    /// The list being browsed
+    mode: usize,
     action_tx: ActionTx,
     focus: Focus,
 }
 
 impl MangaListPage {
-    pub fn new(action_tx: ActionTx) -> Self {
-        Self {
+    pub fn new(
+        action_tx: ActionTx,
+        source: String,
+        filter: Filter,
+        info: Option<&SourceInfo>,
+    ) -> Self {
+        let mut page = Self::everywhere(action_tx.clone(), filter);
+        page.manga_list = Some(MangaListComponent::new(action_tx, source));
+        if let Some(info) = info {
+            page.filters = Some(info.filters.clone());
+            page.filter_fields = info.filter_fields.clone();
+            page.sort_keys = info.sort_keys.clone();
+            page.browse_modes = info.browse_modes.clone();
+        }
+        page.set_everywhere(false);
+
+        // Something shows up before anything is searched. Sending only fails once the app
+        // is closing.
+        if page.browsing() {
+            let _ = page.browse();
+        }
+        page
+    }
+
+    /// A page that searches in every installed source at once
+    pub fn everywhere(action_tx: ActionTx, filter: Filter) -> Self {
+        let mut page = Self {
             search_bar: SearchBarComponent::new(),
-            manga_list: MangaListComponent::new(action_tx.clone()),
+            manga_list: None,
+            federated_results: FederatedResultsComponent::new(action_tx.clone()),
+            everywhere: true,
+            filter,
+            filters: None,
+            filter_fields: Vec::new(),
+            sort_keys: Vec::new(),
+            filter_panel: None,
+            keyword: None,
+            browse_modes: Vec::new(),
+            mode: 0,
             action_tx,
             focus: Focus::SearchBar,
+        };
+        page.set_everywhere(true);
+        page
+    }
+
+    fn set_everywhere(&mut self, everywhere: bool) {
+        self.everywhere = everywhere;
+
+        let title = match (&self.manga_list, everywhere) {
+            (Some(list), false) => format!("Search in {} (Tab: everywhere)", list.source),
+            (Some(list), true) => format!("Search everywhere (Tab: {} only)", list.source),
+            (None, _) => String::from("Search everywhere"),
+        };
+        self.search_bar.set_title(title);
+    }
+
+    /// Whether the lists of the source are shown instead of search results
+    fn browsing(&self) -> bool {
+        self.manga_list.is_some()
+            && !self.everywhere
+            && self.keyword.is_none()
+            && !self.browse_modes.is_empty()
+    }
+
+    /// Shows the list of the selected browse mode
+    fn browse(&mut self) -> Result<()> {
+        let Some(list) = self.manga_list.as_mut() else {
+            return Ok(());
+        };
+
+        list.clear();
+        list.browse_manga(self.browse_modes[self.mode], &self.filter)?;
+        self.keyword = None;
+
+        Ok(())
+    }
+
+    fn search(&mut self, keyword: String) -> Result<()> {
+        // Searching nothing in a source that can be browsed goes back to its lists
+        if keyword.trim().is_empty() && self.manga_list.is_some() && !self.everywhere {
+            self.keyword = None;
+            if self.browsing() {
+                return self.browse();
+            }
+        }
+
+        match self.manga_list.as_mut() {
+            Some(list) if !self.everywhere => {
+                list.clear();
+                list.search_manga(&keyword, &self.filter)?;
+            }
+            _ => self.federated_results.search(&keyword, &self.filter)?,
+        }
+        self.keyword = Some(keyword);
+
+        Ok(())
+    }
+
+    fn results(&mut self) -> &mut dyn Component {
+        match self.manga_list.as_mut() {
+            Some(list) if !self.everywhere => list,
+            _ => &mut self.federated_results,
         }
     }
 }
 
 impl Component for MangaListPage {
     fn handle_events(&mut self, event: Event) -> Result<()> {
+        // Enter and Esc close the tag picker instead of the filter panel
+        let picking = self
+            .filter_panel
+            .as_ref()
+            .is_some_and(|panel| panel.is_picking());
+
         match self.focus {
             Focus::SearchBar => self.search_bar.handle_events(event.clone())?,
-            Focus::MangaList => self.manga_list.handle_events(event.clone())?,
+            Focus::MangaList => self.results().handle_events(event.clone())?,
+            Focus::Filters => {
+                if let Some(panel) = self.filter_panel.as_mut() {
+                    panel.handle_events(event.clone())?;
+                }
+            }
         };
 
         let Event::Key(k_event) = event else {
@@ -58,21 +198,77 @@ impl Component for MangaListPage {
                 KeyCode::Char('b') => {
                     self.action_tx.send(Action::PrevPage)?;
                 }
+                KeyCode::Left | KeyCode::Right if self.browsing() => {
+                    let count = self.browse_modes.len();
+                    self.mode = match key_code {
+                        KeyCode::Left => (self.mode + count - 1) % count,
+                        _ => (self.mode + 1) % count,
+                    };
+                    self.browse()?;
+                }
+                KeyCode::Char('f') => {
+                    // Tags and the filters only a source has belong to the source that
+                    // is searched. Searching everywhere, each source ignores what it
+                    // doesn't take into account.
+                    let (source, filters, fields, sort_keys) =
+                        match (&self.manga_list, self.everywhere) {
+                            (Some(list), false) => (
+                                Some(list.source.clone()),
+                                self.filters.as_deref(),
+                                self.filter_fields.clone(),
+                                self.sort_keys.clone(),
+                            ),
+                            _ => (None, None, Vec::new(), SortKey::ALL.to_vec()),
+                        };
+                    self.filter_panel = Some(FilterPanelComponent::new(
+                        self.action_tx.clone(),
+                        source,
+                        filters,
+                        fields,
+                        sort_keys,
+                        &self.filter,
+                    ));
+                    self.focus = Focus::Filters;
+                }
                 _ => {}
             },
-            Focus::SearchBar => match key_code {
+            Focus::Filters if picking => {}
+            Focus::Filters => match key_code {
                 KeyCode::Esc => {
+                    self.filter_panel = None;
                     self.focus = Focus::MangaList;
                 }
                 KeyCode::Enter => {
-                    let keyword = self.search_bar.get_contents();
-                    let filter = Filter {
-                        language: String::from("en"),
-                        sort: dto::Order::Descending,
+                    let Some(panel) = self.filter_panel.as_ref() else {
+                        return Ok(());
                     };
 
-                    self.manga_list.clear();
-                    self.manga_list.search_manga(&keyword, &filter)?;
+                    match panel.filter() {
+                        Ok(filter) => {
+                            self.filter = filter;
+                            self.filter_panel = None;
+                            self.focus = Focus::MangaList;
+
+                            match self.keyword.clone() {
+                                Some(keyword) => self.search(keyword)?,
+                                None if self.browsing() => self.browse()?,
+                                None => {}
+                            }
+                        }
+                        Err(e) => self.action_tx.send(Action::InvokeError(e))?,
+                    }
+                }
+                _ => {}
+            },
+            Focus::SearchBar => match key_code {
+                KeyCode::Esc => {
+                    self.focus = Focus::MangaList;
+                }
+                KeyCode::Tab if self.manga_list.is_some() => {
+                    self.set_everywhere(!self.everywhere);
+                }
+                KeyCode::Enter => {
+                    self.search(self.search_bar.get_contents())?;
                     self.focus = Focus::MangaList;
                 }
                 _ => {}
@@ -83,46 +279,100 @@ impl Component for MangaListPage {
     }
 
     fn update(&mut self, action: Action) -> Result<()> {
-        self.manga_list.update(action)?;
+        if let Some(panel) = self.filter_panel.as_mut() {
+            panel.update(action.clone())?;
+        }
+        if let Some(list) = self.manga_list.as_mut() {
+            list.update(action.clone())?;
+        }
+        self.federated_results.update(action)?;
         Ok(())
     }
 
     fn draw(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
+        let tabs_height = match self.browsing() {
+            true => 1,
+            false => 0,
+        };
         let layout = Layout::default()
             .direction(Direction::Vertical)
-            .constraints([Constraint::Length(3), Constraint::Fill(10)])
+            .constraints([
+                Constraint::Length(3),
+                Constraint::Length(tabs_height),
+                Constraint::Fill(10),
+            ])
             .split(area);
+        let (tabs_area, results_area) = (layout[1], layout[2]);
 
-        match self.focus {
-            Focus::MangaList => {
-                self.manga_list.set_dim(false);
-                self.search_bar.set_dim(true);
-            }
-            Focus::SearchBar => {
-                self.manga_list.set_dim(true);
-                self.search_bar.set_dim(false);
-            }
+        let results_dim = match self.focus {
+            Focus::MangaList | Focus::Filters => false,
+            Focus::SearchBar => true,
+        };
+        self.search_bar.set_dim(!results_dim);
+        if let Some(list) = self.manga_list.as_mut() {
+            list.set_dim(results_dim);
         }
+        self.federated_results.set_dim(results_dim);
 
         self.search_bar.draw(frame, layout[0])?;
-        self.manga_list.draw(frame, layout[1])?;
+        self.results().draw(frame, results_area)?;
+
+        if self.browsing() {
+            let titles = self.browse_modes.iter().map(|&m| mode_name(m));
+            let tabs = Tabs::new(titles)
+                .select(self.mode)
+                .highlight_style(Style::new().yellow().bold())
+                .dim();
+            frame.render_widget(tabs, tabs_area);
+            frame.render_widget(Line::from(" ←/→: list ".dim()).right_aligned(), tabs_area);
+        }
+
+        // The hint sits on the top border of the results
+        let narrowed = !(self.filter.included_tags.is_empty()
+            && self.filter.excluded_tags.is_empty()
+            && self.filter.statuses.is_empty()
+            && self.filter.content_ratings.is_empty()
+            && self.filter.demographics.is_empty()
+            && self.filter.year.is_none()
+            && self.filter.original_languages.is_empty()
+            && self.filter.fields.is_empty());
+        let hint = match narrowed {
+            true => " f: filters (on) ".yellow(),
+            false => " f: filters ".dim(),
+        };
+        let hint_area = Rect {
+            x: results_area.x + 1,
+            width: results_area.width.saturating_sub(2),
+            height: 1.min(results_area.height),
+            ..results_area
+        };
+        frame.render_widget(Line::from(hint).right_aligned(), hint_area);
+
+        if let Some(panel) = self.filter_panel.as_mut() {
+            panel.draw(frame, results_area)?;
+        }
         Ok(())
     }
 }
 
 pub(crate) struct MangaListComponent {
     action_tx: ActionTx,
+    source: String,
     items: Vec<MangaListEntry>,
     list_state: ListState,
     curr_page: u32,
     max_page: u32,
     dim: bool,
+    /// The filter of the last search, chapters of the manga found are listed with it
+    filter: Filter,
 }
 
 impl MangaListComponent {
-    pub(crate) fn new(action_tx: ActionTx) -> Self {
+    pub(crate) fn new(action_tx: ActionTx, source: String) -> Self {
         Self {
             action_tx,
+            source,
+            filter: Filter::default(),
             items: Vec::new(),
             list_state: ListState::default(),
             curr_page: 0,
@@ -142,14 +392,30 @@ impl MangaListComponent {
         self.max_page = 0;
     }
 
+    pub(crate) fn browse_manga(&mut self, mode: BrowseMode, filter: &Filter) -> Result<()> {
+        self.filter = filter.clone();
+        let command = Command::BrowseManga {
+            mode,
+            page: 1,
+            filter: filter.clone(),
+        };
+
+        self.action_tx
+            .send(Action::RunCommand(self.source.clone(), command))?;
+
+        Ok(())
+    }
+
     pub(crate) fn search_manga(&mut self, keyword: &str, filter: &Filter) -> Result<()> {
+        self.filter = filter.clone();
         let command = Command::SearchManga {
             keyword: keyword.to_string(),
             page: 1,
             filter: filter.clone(),
         };
 
-        self.action_tx.send(Action::RunCommand(command))?;
+        self.action_tx
+            .send(Action::RunCommand(self.source.clone(), command))?;
 
         Ok(())
     }
@@ -168,14 +434,26 @@ impl Component for MangaListComponent {
         match k_event.code {
             KeyCode::Enter => {
                 if let Some(i) = self.list_state.selected() {
-                    self.action_tx.send(Action::NextPage(Page::MangaDetails))?;
-                    // self.action_tx.send(
-                    //     Action::RunCommand(Command::Search { keyword: String::from("Blue arcive"), page: 1, filter: Filter::default() })
-                    // )?;
+                    let identifier = self.items.get(i).unwrap().identifier.clone();
                     self.action_tx
-                        .send(Action::RunCommand(Command::FetchMangaDetail {
-                            identifier: self.items.get(i).unwrap().identifier.clone(),
-                        }))?;
+                        .send(Action::NextPage(Page::MangaDetails(self.source.clone())))?;
+
+                    // The details and the first chapter page don't depend on each other,
+                    // so both are requested at once
+                    self.action_tx.send(Action::RunCommand(
+                        self.source.clone(),
+                        Command::FetchMangaDetail {
+                            identifier: identifier.clone(),
+                        },
+                    ))?;
+                    self.action_tx.send(Action::RunCommand(
+                        self.source.clone(),
+                        Command::FetchChapterList {
+                            identifier,
+                            page: 1,
+                            filter: self.filter.clone(),
+                        },
+                    ))?;
                 }
             }
             KeyCode::Up => {
@@ -192,18 +470,22 @@ impl Component for MangaListComponent {
     }
 
     fn update(&mut self, action: Action) -> Result<()> {
-        if let Action::DisplayMangaList(mut mg_list) = action {
-            self.curr_page += 1;
-            self.max_page = mg_list.total_page;
+        match action {
+            // Results from other sources belong to another page
+            Action::DisplayMangaList(source, mut mg_list) if source == self.source => {
+                self.curr_page += 1;
+                self.max_page = mg_list.total_page;
 
-            self.items.append(&mut mg_list.data);
+                self.items.append(&mut mg_list.data);
+            }
+            _ => {}
         }
 
         Ok(())
     }
 
     fn draw(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
-        let mut block = Block::bordered();
+        let mut block = Block::bordered().title(format!(" {} ", self.source).bold());
 
         let list_item: Vec<ListItem> = self
             .items
@@ -237,3 +519,175 @@ impl Component for MangaListComponent {
         Ok(())
     }
 }
+
+/// A row of the federated results: either the header of a source or one of its manga
+enum FederatedRow {
+    Source(usize),
+    Manga(usize, usize),
+}
+
+/// Results of a search sent to every source, grouped by source. Sources are listed as
+/// soon as the search starts and their results show up as they answer.
+pub(crate) struct FederatedResultsComponent {
+    action_tx: ActionTx,
+    keyword: String,
+    results: Vec<(String, SearchOutcome)>,
+    list_state: ListState,
+    dim: bool,
+    /// The filter of the last search, chapters of the manga found are listed with it
+    filter: Filter,
+}
+
+impl FederatedResultsComponent {
+    pub(crate) fn new(action_tx: ActionTx) -> Self {
+        Self {
+            action_tx,
+            filter: Filter::default(),
+            keyword: String::new(),
+            results: Vec::new(),
+            list_state: ListState::default(),
+            dim: true,
+        }
+    }
+
+    pub(crate) fn set_dim(&mut self, dim: bool) {
+        self.dim = dim;
+    }
+
+    pub(crate) fn search(&mut self, keyword: &str, filter: &Filter) -> Result<()> {
+        self.keyword = keyword.to_string();
+        self.filter = filter.clone();
+        self.results.clear();
+        self.list_state = ListState::default();
+
+        self.action_tx.send(Action::SearchEverywhere(
+            keyword.to_string(),
+            filter.clone(),
+        ))?;
+
+        Ok(())
+    }
+
+    fn rows(&self) -> Vec<FederatedRow> {
+        let mut rows = Vec::new();
+        for (i, (_, outcome)) in self.results.iter().enumerate() {
+            rows.push(FederatedRow::Source(i));
+
+            if let SearchOutcome::Found(list) = outcome {
+                rows.extend((0..list.data.len()).map(|j| FederatedRow::Manga(i, j)));
+            }
+        }
+        rows
+    }
+}
+
+impl Component for FederatedResultsComponent {
+    fn handle_events(&mut self, event: Event) -> Result<()> {
+        let Event::Key(k_event) = event else {
+            return Ok(());
+        };
+
+        let KeyEventKind::Press = k_event.kind else {
+            return Ok(());
+        };
+
+        match k_event.code {
+            KeyCode::Enter => {
+                let rows = self.rows();
+                let Some(FederatedRow::Manga(i, j)) =
+                    self.list_state.selected().and_then(|s| rows.get(s))
+                else {
+                    return Ok(());
+                };
+
+                let (source, SearchOutcome::Found(list)) = &self.results[*i] else {
+                    return Ok(());
+                };
+                let identifier = list.data[*j].identifier.clone();
+
+                self.action_tx
+                    .send(Action::NextPage(Page::MangaDetails(source.clone())))?;
+                self.action_tx.send(Action::RunCommand(
+                    source.clone(),
+                    Command::FetchMangaDetail {
+                        identifier: identifier.clone(),
+                    },
+                ))?;
+                self.action_tx.send(Action::RunCommand(
+                    source.clone(),
+                    Command::FetchChapterList {
+                        identifier,
+                        page: 1,
+                        filter: self.filter.clone(),
+                    },
+                ))?;
+            }
+            KeyCode::Up => self.list_state.select_previous(),
+            KeyCode::Down => self.list_state.select_next(),
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn update(&mut self, action: Action) -> Result<()> {
+        let Action::DisplayFederatedResult(result) = action else {
+            return Ok(());
+        };
+
+        // Late answers to a previous search are dropped
+        if result.keyword != self.keyword {
+            return Ok(());
+        }
+
+        match self.results.iter_mut().find(|(s, _)| *s == result.source) {
+            Some((_, outcome)) => *outcome = result.outcome,
+            None => self.results.push((result.source, result.outcome)),
+        }
+
+        Ok(())
+    }
+
+    fn draw(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
+        let mut block = Block::bordered().title(" All sources ".bold());
+        if self.dim {
+            block = block.dim();
+        }
+
+        let list_item: Vec<ListItem> = self
+            .rows()
+            .into_iter()
+            .map(|row| match row {
+                FederatedRow::Source(i) => {
+                    let (source, outcome) = &self.results[i];
+                    let status = match outcome {
+                        SearchOutcome::Searching => "searching...".dim(),
+                        SearchOutcome::Found(list) if list.data.is_empty() => "nothing found".dim(),
+                        SearchOutcome::Found(list) => format!("{} found", list.data.len()).green(),
+                        SearchOutcome::Failed(e) => format!("failed: {e}").red(),
+                        SearchOutcome::TimedOut => "timed out".red(),
+                    };
+
+                    Line::from_iter([source.clone().bold().cyan(), " ".into(), status]).into()
+                }
+                FederatedRow::Manga(i, j) => {
+                    let SearchOutcome::Found(list) = &self.results[i].1 else {
+                        unreachable!();
+                    };
+                    Line::from(format!("  {}", list.data[j].title)).into()
+                }
+            })
+            .collect();
+
+        let list = List::new(list_item)
+            .direction(ratatui::widgets::ListDirection::TopToBottom)
+            .block(block)
+            .highlight_symbol(" ")
+            .highlight_spacing(ratatui::widgets::HighlightSpacing::Always)
+            .repeat_highlight_symbol(true)
+            .highlight_style(Style::new().on_dark_gray());
+
+        frame.render_stateful_widget(list, area, &mut self.list_state);
+        Ok(())
+    }
+}
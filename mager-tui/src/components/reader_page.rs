@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use color_eyre::Result;
+use crossterm::event::{KeyCode, KeyEventKind};
+use dto::{Chapter, ChapterListEntry};
+use image::RgbaImage;
+use ratatui::prelude::*;
+use ratatui::widgets::{Clear, Paragraph};
+
+use crate::actions::{Action, ActionTx, Command, PageImage};
+use crate::graphics::{self, Fit, Placement, Protocol, KITTY_CLEAR};
+use crate::tui::Event;
+
+use super::Component;
+
+/// How many pages after the current one are downloaded ahead of time
+const PREFETCH: usize = 3;
+
+/// An image ready to be shown
+struct Graphic {
+    url: String,
+    placement: Placement,
+    rendered: Rendered,
+}
+
+/// What a page becomes for the protocol
+enum Rendered {
+    /// An escape sequence, written over the frame
+    Sequence(String),
+    /// The resized page, drawn with cells
+    Cells(RgbaImage),
+}
+
+/// Reads a chapter page by page, and moves on to the next chapters of the manga
+pub struct ReaderPage {
+    action_tx: ActionTx,
+    source: String,
+    /// Every chapter of the manga that is known, in reading order
+    chapters: Vec<ChapterListEntry>,
+    current: usize,
+    chapter: Option<Chapter>,
+    page: usize,
+    /// The page the first chapter opens on
+    start_page: usize,
+    images: HashMap<String, Option<PageImage>>,
+    protocol: Protocol,
+    fit: Fit,
+    scroll: u16,
+    /// The image of the current page, once rendered for the protocol
+    graphic: Option<Graphic>,
+    /// Whether the graphic is the one showing on screen
+    graphic_shown: bool,
+}
+
+impl ReaderPage {
+    /// Opens the chapter with this identifier on `start_page`, `chapters` being every
+    /// chapter the reader can move on to
+    pub(crate) fn new(
+        action_tx: ActionTx,
+        source: String,
+        mut chapters: Vec<ChapterListEntry>,
+        chapter_identifier: &str,
+        start_page: usize,
+        protocol: Protocol,
+    ) -> Self {
+        reading_order(&mut chapters);
+        let current = chapters
+            .iter()
+            .position(|c| c.identifier == chapter_identifier)
+            .unwrap_or_default();
+
+        let mut reader = Self {
+            action_tx,
+            source,
+            chapters,
+            current,
+            chapter: None,
+            page: 0,
+            start_page,
+            images: HashMap::new(),
+            protocol,
+            fit: Fit::Height,
+            scroll: 0,
+            graphic: None,
+            graphic_shown: false,
+        };
+        reader.open_chapter(current);
+        reader
+    }
+
+    fn open_chapter(&mut self, index: usize) {
+        let Some(entry) = self.chapters.get(index) else {
+            return;
+        };
+
+        self.current = index;
+        self.chapter = None;
+        self.page = 0;
+        self.scroll = 0;
+        self.images.clear();
+
+        let _ = self.action_tx.send(Action::RunCommand(
+            self.source.clone(),
+            Command::FetchChapterDetail {
+                identifier: entry.identifier.clone(),
+            },
+        ));
+    }
+
+    fn page_count(&self) -> usize {
+        self.chapter.as_ref().map_or(0, |c| c.page_urls.len())
+    }
+
+    fn url(&self, page: usize) -> Option<&String> {
+        self.chapter.as_ref().and_then(|c| c.page_urls.get(page))
+    }
+
+    /// Requests the current page and the next ones, and forgets the pages that are far
+    /// behind
+    fn prefetch(&mut self) {
+        let Some(chapter) = self.chapter.as_ref() else {
+            return;
+        };
+
+        let window = self.page.saturating_sub(1)..=self.page + PREFETCH;
+        let wanted: Vec<String> = chapter.page_urls[*window.start()..]
+            .iter()
+            .take(window.end() - window.start() + 1)
+            .cloned()
+            .collect();
+
+        self.images.retain(|url, _| wanted.contains(url));
+        for url in wanted {
+            if !self.images.contains_key(&url) {
+                self.images.insert(url.clone(), None);
+                let _ = self.action_tx.send(Action::FetchPageImage(url));
+            }
+        }
+    }
+
+    fn go_to_page(&mut self, page: usize) {
+        self.page = page;
+        self.scroll = 0;
+        self.prefetch();
+
+        if let Some(chapter) = self.chapter.as_ref().filter(|c| !c.page_urls.is_empty()) {
+            let _ = self.action_tx.send(Action::SaveProgress(
+                self.source.clone(),
+                chapter.clone(),
+                page,
+            ));
+        }
+    }
+
+    fn next_page(&mut self) {
+        if self.page + 1 < self.page_count() {
+            self.go_to_page(self.page + 1);
+        } else if self.chapter.is_some() && self.current + 1 < self.chapters.len() {
+            self.open_chapter(self.current + 1);
+        }
+    }
+
+    fn previous_page(&mut self) {
+        if self.page > 0 {
+            self.go_to_page(self.page - 1);
+        }
+    }
+
+    fn status(&self) -> Line<'static> {
+        let chapter = match self.chapters.get(self.current) {
+            Some(c) => format!("#{} - {}", c.number, c.title),
+            None => String::new(),
+        };
+        let page = match self.page_count() {
+            0 => String::from("loading"),
+            count => format!("page {}/{count}", self.page + 1),
+        };
+        let fit = match self.fit {
+            Fit::Height => "fit: height",
+            Fit::Width => "fit: width",
+        };
+
+        Line::from_iter([
+            format!(" {chapter}  ").bold(),
+            format!("{page}  {fit}  ").into(),
+            "←/→: page  ↑/↓: scroll  n/p: chapter  w: fit  b: back".dim(),
+        ])
+    }
+
+    fn draw_page(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
+        let url = self.url(self.page).cloned();
+        let image = url.as_ref().and_then(|u| self.images.get(u)).cloned();
+
+        let (url, image) = match (url, image) {
+            (Some(url), Some(Some(PageImage::Loaded(image)))) => (url, image),
+            (_, Some(Some(PageImage::Failed(e)))) => {
+                self.graphic = None;
+                let message = Paragraph::new(format!("This page can't be shown: {e}").red());
+                frame.render_widget(message.centered(), centered_line(area));
+                return Ok(());
+            }
+            _ => {
+                self.graphic = None;
+                let message = Paragraph::new("Loading...".dim()).centered();
+                frame.render_widget(message, centered_line(area));
+                return Ok(());
+            }
+        };
+
+        let size = (image.width(), image.height());
+        let placement =
+            graphics::place(size, area, self.protocol.cell_size(), self.fit, self.scroll);
+        self.scroll = self.scroll.min(placement.max_scroll);
+
+        // Resizing and encoding are slow, so they are only done when the page or its
+        // placement changes
+        let cached = self
+            .graphic
+            .as_ref()
+            .is_some_and(|g| g.url == url && g.placement == placement);
+        if !cached {
+            let image = graphics::render(&image, &placement);
+            let rendered = match self.protocol {
+                Protocol::Halfblocks => Rendered::Cells(image),
+                protocol => Rendered::Sequence(graphics::encode(protocol, &image, placement.area)?),
+            };
+            self.graphic = Some(Graphic {
+                url,
+                placement,
+                rendered,
+            });
+            self.graphic_shown = false;
+        }
+
+        if let Some(Graphic {
+            rendered: Rendered::Cells(image),
+            ..
+        }) = &self.graphic
+        {
+            graphics::draw_halfblocks(image, placement.area, frame.buffer_mut());
+            return Ok(());
+        }
+
+        // The image is written over those cells, ratatui must leave them alone
+        for y in placement.area.top()..placement.area.bottom() {
+            for x in placement.area.left()..placement.area.right() {
+                if let Some(cell) = frame.buffer_mut().cell_mut((x, y)) {
+                    cell.set_skip(true);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Sorts the chapters by number, the way they are read
+pub(crate) fn reading_order(chapters: &mut [ChapterListEntry]) {
+    let number = |c: &ChapterListEntry| c.number.parse::<f32>().unwrap_or(f32::MAX);
+    chapters.sort_by(|a, b| number(a).total_cmp(&number(b)));
+}
+
+/// The middle line of the area
+fn centered_line(area: Rect) -> Rect {
+    Rect::new(
+        area.x,
+        area.y + area.height / 2,
+        area.width,
+        1.min(area.height),
+    )
+}
+
+impl Component for ReaderPage {
+    fn handle_events(&mut self, event: Event) -> Result<()> {
+        let Event::Key(k_event) = event else {
+            return Ok(());
+        };
+
+        let KeyEventKind::Press = k_event.kind else {
+            return Ok(());
+        };
+
+        match k_event.code {
+            KeyCode::Char('b') | KeyCode::Esc => {
+                self.action_tx.send(Action::PrevPage)?;
+                // The page below shows how far the manga was read
+                if let Some(chapter) = self.chapter.as_ref() {
+                    self.action_tx.send(Action::FetchProgress(
+                        self.source.clone(),
+                        chapter.manga_identifier.clone(),
+                    ))?;
+                }
+            }
+            KeyCode::Right | KeyCode::Char('l') | KeyCode::Char(' ') => self.next_page(),
+            KeyCode::Left | KeyCode::Char('h') => self.previous_page(),
+            KeyCode::Down | KeyCode::Char('j') => self.scroll = self.scroll.saturating_add(1),
+            KeyCode::Up | KeyCode::Char('k') => self.scroll = self.scroll.saturating_sub(1),
+            KeyCode::Char('n') if self.current + 1 < self.chapters.len() => {
+                self.open_chapter(self.current + 1)
+            }
+            KeyCode::Char('p') if self.current > 0 => self.open_chapter(self.current - 1),
+            KeyCode::Char('w') => {
+                self.fit = match self.fit {
+                    Fit::Height => Fit::Width,
+                    Fit::Width => Fit::Height,
+                };
+                self.scroll = 0;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn update(&mut self, action: Action) -> Result<()> {
+        match action {
+            Action::SetActiveChapter(source, chapter) if source == self.source => {
+                let expected = self.chapters.get(self.current).map(|c| &c.identifier);
+                if expected == Some(&chapter.identifier) {
+                    let last = chapter.page_urls.len().saturating_sub(1);
+                    self.chapter = Some(chapter);
+                    let page = std::mem::take(&mut self.start_page).min(last);
+                    self.go_to_page(page);
+                }
+            }
+            Action::DisplayPageImage(url, image) => {
+                if let Some(slot) = self.images.get_mut(&url) {
+                    *slot = Some(image);
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn draw(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
+        let layout = Layout::vertical([Constraint::Fill(1), Constraint::Length(1)]).split(area);
+
+        frame.render_widget(Clear, layout[0]);
+        self.draw_page(frame, layout[0])?;
+        frame.render_widget(Paragraph::new(self.status()), layout[1]);
+
+        Ok(())
+    }
+
+    fn draw_graphics(&mut self, out: &mut dyn Write, redraw: bool) -> Result<()> {
+        match self.graphic.as_ref() {
+            Some(Graphic {
+                placement,
+                rendered: Rendered::Sequence(sequence),
+                ..
+            }) if redraw || !self.graphic_shown => {
+                graphics::write_at(out, placement.area, sequence)?;
+                self.graphic_shown = true;
+            }
+            Some(_) => {}
+            // The page that showed before is gone, but Kitty keeps its images around
+            None if self.graphic_shown => self.hide_graphics(out)?,
+            None => {}
+        }
+
+        Ok(())
+    }
+
+    fn hide_graphics(&mut self, out: &mut dyn Write) -> Result<()> {
+        if self.protocol == Protocol::Kitty {
+            out.write_all(KITTY_CLEAR.as_bytes())?;
+            out.flush()?;
+        }
+        self.graphic_shown = false;
+
+        Ok(())
+    }
+}
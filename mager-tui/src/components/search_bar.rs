@@ -10,6 +10,7 @@ use crate::tui::Event;
 pub(crate) struct SearchBarComponent {
     cursor_pos: usize,
     query_string: String,
+    title: String,
     dim: bool,
 }
 
@@ -18,6 +19,7 @@ impl SearchBarComponent {
         Self {
             cursor_pos: 0,
             query_string: String::from(" "),
+            title: String::new(),
             dim: false,
         }
     }
@@ -35,6 +37,10 @@ impl SearchBarComponent {
         self.dim = dim;
     }
 
+    pub(crate) fn set_title(&mut self, title: impl Into<String>) {
+        self.title = title.into();
+    }
+
     fn delete_char(&mut self) {
         if self.query_string.is_empty() || self.cursor_pos == 0 {
             return;
@@ -145,6 +151,9 @@ impl Component for SearchBarComponent {
         };
 
         let mut block = Block::bordered().padding(Padding::horizontal(1));
+        if !self.title.is_empty() {
+            block = block.title(format!(" {} ", self.title).bold());
+        }
         if self.dim {
             block = block.dim();
         }
@@ -3,7 +3,7 @@ use crossterm::event::{KeyCode, KeyEventKind};
 use ratatui::prelude::*;
 use ratatui::widgets::{Block, List, ListDirection, ListItem, ListState, Padding};
 
-use crate::actions::{Action, ActionTx, AsyncItem, Page};
+use crate::actions::{Action, ActionTx, Page};
 use crate::source::Source;
 use crate::tui::Event;
 
@@ -40,6 +40,11 @@ impl Component for SourcesPage {
 
         match k_event.code {
             KeyCode::Char('q') => self.action_tx.send(Action::Quit)?,
+            KeyCode::Char('b') => self.action_tx.send(Action::PrevPage)?,
+            KeyCode::Char('D') => self.action_tx.send(Action::NextPage(Page::Downloads))?,
+            KeyCode::Char('a') => self
+                .action_tx
+                .send(Action::NextPage(Page::SearchEverywhere))?,
             KeyCode::Enter => {
                 // let keyword = self.search_bar.get_contents();
             }
@@ -74,6 +79,7 @@ pub struct SourceListComp {
     action_tx: ActionTx,
     list_state: ListState,
     source_list: Vec<Source>,
+    active_sources: Vec<String>,
 }
 
 impl SourceListComp {
@@ -82,6 +88,7 @@ impl SourceListComp {
             action_tx,
             list_state: ListState::default(),
             source_list: Vec::new(),
+            active_sources: Vec::new(),
         }
     }
 }
@@ -100,13 +107,23 @@ impl Component for SourceListComp {
             KeyCode::Up => self.list_state.select_previous(),
             KeyCode::Down => self.list_state.select_next(),
             KeyCode::Enter => {
+                // The manga list is opened once the source is up
                 if let Some(index) = self.list_state.selected() {
                     self.action_tx.send(Action::SetActiveSource(
                         self.source_list.get(index).cloned().unwrap(),
                     ))?;
-                    self.action_tx.send(Action::NextPage(Page::Mangas))?;
                 };
             }
+            KeyCode::Char('d') => {
+                if let Some(source) = self
+                    .list_state
+                    .selected()
+                    .and_then(|i| self.source_list.get(i))
+                {
+                    self.action_tx
+                        .send(Action::DeactivateSource(source.name.clone()))?;
+                }
+            }
             _ => {}
         }
 
@@ -114,8 +131,10 @@ impl Component for SourceListComp {
     }
 
     fn update(&mut self, action: Action) -> Result<()> {
-        if let Action::DisplaySourceList(s_list) = action {
-            self.source_list = s_list
+        match action {
+            Action::DisplaySourceList(s_list) => self.source_list = s_list,
+            Action::DisplayActiveSources(active) => self.active_sources = active,
+            _ => {}
         }
 
         Ok(())
@@ -132,8 +151,12 @@ impl Component for SourceListComp {
                     true => "local".green(),
                     false => "repo".gray(),
                 };
+                let mut name = Line::from(s.name.clone());
+                if self.active_sources.contains(&s.name) {
+                    name.push_span(" (active)".cyan());
+                }
 
-                Text::from_iter([Line::from(s.name.clone()), Line::from(is_local)]).into()
+                Text::from_iter([name, Line::from(is_local)]).into()
             })
             .collect();
 
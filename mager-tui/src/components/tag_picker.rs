@@ -0,0 +1,165 @@
+use color_eyre::Result;
+use crossterm::event::{KeyCode, KeyEventKind};
+use dto::{Tag, TagGroup};
+use ratatui::prelude::*;
+use ratatui::widgets::{Block, Clear, List, ListItem, ListState, Padding, Paragraph};
+
+use crate::actions::Action;
+use crate::tui::Event;
+
+use super::Component;
+
+/// Removes the tag from the list, whatever its case is. Returns whether it was there.
+fn remove_tag(list: &mut Vec<String>, name: &str) -> bool {
+    let len = list.len();
+    list.retain(|t| !t.eq_ignore_ascii_case(name));
+    list.len() != len
+}
+
+fn has_tag(list: &[String], name: &str) -> bool {
+    list.iter().any(|t| t.eq_ignore_ascii_case(name))
+}
+
+/// Picks tags among the ones a source lists. A tag is either ignored, included or excluded.
+/// Tags that were typed but aren't listed by the source are kept as they are.
+pub(crate) struct TagPickerComponent {
+    source: String,
+    /// The language tag names are shown in, next to their usual name
+    language: String,
+    groups: Option<Vec<TagGroup>>,
+    included: Vec<String>,
+    excluded: Vec<String>,
+    /// The selected tag, counted across every group
+    selected: usize,
+    state: ListState,
+}
+
+impl TagPickerComponent {
+    pub(crate) fn new(
+        source: String,
+        language: String,
+        included: Vec<String>,
+        excluded: Vec<String>,
+    ) -> Self {
+        Self {
+            source,
+            language,
+            groups: None,
+            included,
+            excluded,
+            selected: 0,
+            state: ListState::default(),
+        }
+    }
+
+    /// The included and excluded tags
+    pub(crate) fn picked(&self) -> (&[String], &[String]) {
+        (&self.included, &self.excluded)
+    }
+
+    fn tags(&self) -> impl Iterator<Item = &Tag> {
+        self.groups.iter().flatten().flat_map(|g| &g.tags)
+    }
+
+    /// Ignored tags become included, included ones excluded and excluded ones ignored
+    fn cycle(&mut self) {
+        let Some(name) = self.tags().nth(self.selected).map(|t| t.name.clone()) else {
+            return;
+        };
+
+        if remove_tag(&mut self.included, &name) {
+            self.excluded.push(name);
+        } else if !remove_tag(&mut self.excluded, &name) {
+            self.included.push(name);
+        }
+    }
+}
+
+impl Component for TagPickerComponent {
+    fn handle_events(&mut self, event: Event) -> Result<()> {
+        let Event::Key(k_event) = event else {
+            return Ok(());
+        };
+
+        let KeyEventKind::Press = k_event.kind else {
+            return Ok(());
+        };
+
+        let count = self.tags().count();
+        match k_event.code {
+            KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down => self.selected = (self.selected + 1).min(count.saturating_sub(1)),
+            KeyCode::PageUp => self.selected = self.selected.saturating_sub(10),
+            KeyCode::PageDown => self.selected = (self.selected + 10).min(count.saturating_sub(1)),
+            KeyCode::Char(' ') => self.cycle(),
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn update(&mut self, action: Action) -> Result<()> {
+        if let Action::DisplayTags(source, groups) = action {
+            if source == self.source {
+                self.groups = Some(groups);
+            }
+        }
+        Ok(())
+    }
+
+    fn draw(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
+        let width = area.width.min(60);
+        let height = area.height.saturating_sub(2).max(area.height.min(3));
+        let area = Rect {
+            x: area.x + (area.width - width) / 2,
+            y: area.y + (area.height - height) / 2,
+            width,
+            height,
+        };
+
+        let block = Block::bordered()
+            .padding(Padding::horizontal(1))
+            .title(format!(" {} tags ", self.source).bold().light_yellow())
+            .title_bottom(" ↑/↓: tag  space: include/exclude  enter: done  esc: cancel ".dim());
+        frame.render_widget(Clear, area);
+
+        let Some(groups) = self.groups.as_ref() else {
+            frame.render_widget(Paragraph::new("Loading tags...".dim()).block(block), area);
+            return Ok(());
+        };
+
+        let mut items = Vec::new();
+        let mut selected_row = 0;
+        let mut i = 0;
+        for group in groups {
+            items.push(ListItem::new(group.name.clone().bold().cyan()));
+
+            for tag in &group.tags {
+                let (mark, style) = if has_tag(&self.included, &tag.name) {
+                    ("[+]", Style::new().green())
+                } else if has_tag(&self.excluded, &tag.name) {
+                    ("[-]", Style::new().red())
+                } else {
+                    ("[ ]", Style::new().dim())
+                };
+
+                let mut line = Line::from(format!("{mark} {}", tag.name)).style(style);
+                let localized = tag.name_in(&self.language);
+                if localized != tag.name {
+                    line.push_span(format!("  {localized}").dim());
+                }
+
+                if i == self.selected {
+                    selected_row = items.len();
+                    line = line.yellow().underlined();
+                }
+                items.push(ListItem::new(line));
+                i += 1;
+            }
+        }
+
+        self.state.select(Some(selected_row));
+        frame.render_stateful_widget(List::new(items).block(block), area, &mut self.state);
+        Ok(())
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Result};
+use dto::carriers::{Command, ErrorCode, Request, Response, SourceInfo, Status};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use tokio::io::{ReadHalf, WriteHalf};
+use tokio::sync::{oneshot, Mutex as AsyncMutex};
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+use tracing::{event, warn, Level};
+
+use crate::utils::{
+    connect_to_source, ping, read_from_stream, write_to_stream, Endpoint, SourceStream,
+};
+
+type Pending = Arc<Mutex<HashMap<u64, oneshot::Sender<Vec<u8>>>>>;
+
+/// How many times a rate limited request is sent again before giving up
+const MAX_RATE_LIMIT_RETRIES: u32 = 3;
+/// The longest time (in seconds) we are willing to wait before retrying a request
+const MAX_RATE_LIMIT_WAIT: u64 = 30;
+
+/// Only the routing part of a response. It is used to find the request a response
+/// belongs to before knowing what the content type is.
+#[derive(Deserialize)]
+struct ResponseId {
+    #[serde(default)]
+    id: u64,
+}
+
+/// A long-lived GMTP session with a source. Requests can be sent from any task at the
+/// same time, responses are matched back to their request by id so they can arrive
+/// in any order.
+pub(crate) struct Connection {
+    info: SourceInfo,
+    writer: AsyncMutex<WriteHalf<Box<dyn SourceStream>>>,
+    pending: Pending,
+    next_id: AtomicU64,
+    reader: JoinHandle<()>,
+}
+
+impl Connection {
+    /// Opens a session with the source listening on the endpoint. The source is pinged
+    /// first, so this fails if it doesn't speak a compatible GMTP version.
+    pub(crate) async fn connect(endpoint: &Endpoint) -> Result<Self> {
+        let mut stream = connect_to_source(endpoint).await?;
+        let info = ping(&mut stream).await?;
+
+        Ok(Self::new(stream, info))
+    }
+
+    fn new(stream: Box<dyn SourceStream>, info: SourceInfo) -> Self {
+        let (read_half, write_half) = tokio::io::split(stream);
+        let pending = Pending::default();
+        let reader = tokio::spawn(read_responses(read_half, pending.clone()));
+
+        Self {
+            info,
+            writer: AsyncMutex::new(write_half),
+            pending,
+            next_id: AtomicU64::new(1),
+            reader,
+        }
+    }
+
+    /// What the source told about itself when the session was opened
+    pub(crate) fn info(&self) -> &SourceInfo {
+        &self.info
+    }
+
+    /// Whether the source has closed the session
+    pub(crate) fn is_closed(&self) -> bool {
+        self.reader.is_finished()
+    }
+
+    /// Sends a command to the source and waits for its response. Rate limited requests
+    /// are sent again after waiting for a bit, but every other error reported by the source
+    /// is not handled by this function.
+    pub(crate) async fn request<T>(&self, command: Command) -> Result<Response<T>>
+    where
+        T: DeserializeOwned,
+    {
+        if !self.info.supports(command.kind()) {
+            return Err(eyre!(
+                "{} doesn't support {:?}",
+                self.info.name,
+                command.kind()
+            ));
+        }
+
+        let mut retries = 0;
+        loop {
+            let response: Response<T> = self.send(command.clone()).await?;
+
+            let Status::Error(ErrorCode::RateLimited(retry_after)) = response.status else {
+                return Ok(response);
+            };
+            if retries >= MAX_RATE_LIMIT_RETRIES {
+                return Ok(response);
+            }
+
+            retries += 1;
+            // Back off exponentially when the source doesn't know how long to wait
+            let wait = retry_after.unwrap_or(1 << retries).min(MAX_RATE_LIMIT_WAIT);
+            warn!(
+                "{} is rate limited, retrying in {wait} seconds",
+                self.info.name
+            );
+            sleep(Duration::from_secs(wait)).await;
+        }
+    }
+
+    async fn send<T>(&self, command: Command) -> Result<Response<T>>
+    where
+        T: DeserializeOwned,
+    {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let request = serde_json::to_string(&Request::new(id, command))?;
+
+        let (response_tx, response_rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, response_tx);
+
+        let written = write_to_stream(&request, &mut *self.writer.lock().await).await;
+        if let Err(error) = written {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(error);
+        }
+
+        let raw_response = response_rx
+            .await
+            .map_err(|_| eyre!("Connection to source is closed"))?;
+
+        Ok(serde_json::from_slice(&raw_response)?)
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+/// Reads every response sent by the source and hands it over to whoever is waiting for it
+async fn read_responses(mut stream: ReadHalf<Box<dyn SourceStream>>, pending: Pending) {
+    loop {
+        let raw_response = match read_from_stream(&mut stream).await {
+            Ok(r) => r,
+            Err(e) => {
+                event!(Level::DEBUG, "Session with source ended: {e}");
+                break;
+            }
+        };
+
+        let id = match serde_json::from_slice::<ResponseId>(&raw_response) {
+            Ok(r) => r.id,
+            Err(e) => {
+                warn!("Received a malformed response: {e}");
+                continue;
+            }
+        };
+
+        match pending.lock().unwrap().remove(&id) {
+            Some(response_tx) => {
+                let _ = response_tx.send(raw_response);
+            }
+            None => warn!("Received a response for unknown request {id}"),
+        }
+    }
+
+    // Dropping the senders will wake up everyone that is still waiting with an error
+    pending.lock().unwrap().clear();
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, process};
+
+    use dto::carriers::{self, CommandKind};
+    use tokio::net::{TcpListener, UnixListener};
+
+    use super::*;
+
+    async fn answer_ping<S: SourceStream>(stream: &mut S) {
+        let raw = read_from_stream(stream).await.unwrap();
+        let request: Request = serde_json::from_slice(&raw).unwrap();
+
+        let info = SourceInfo {
+            name: String::from("Fake"),
+            versions: vec![carriers::VERSION.to_string()],
+            commands: vec![CommandKind::Ping, CommandKind::FetchManga],
+            languages: vec![String::from("en")],
+            filters: Vec::new(),
+            filter_fields: Vec::new(),
+            browse_modes: Vec::new(),
+            sort_keys: Vec::new(),
+        };
+        let response = Response {
+            id: request.id,
+            status: Status::Ok,
+            reason: String::new(),
+            source_name: String::from("Fake"),
+            content: Some(info),
+        };
+        let response = serde_json::to_string(&response).unwrap();
+        write_to_stream(&response, stream).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn out_of_order_responses() {
+        let path = env::temp_dir().join(format!("mager-test-{}.sock", process::id()));
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let endpoint = Endpoint::Unix(path.clone());
+
+        // This fake source answers the two requests it receives in reverse order
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            answer_ping(&mut stream).await;
+
+            let mut requests = Vec::new();
+            for _ in 0..2 {
+                let raw = read_from_stream(&mut stream).await.unwrap();
+                requests.push(serde_json::from_slice::<Request>(&raw).unwrap());
+            }
+
+            for request in requests.into_iter().rev() {
+                let Command::FetchManga { manga_identifier } = request.command else {
+                    panic!("Unexpected command");
+                };
+                let response = Response {
+                    id: request.id,
+                    status: Status::Ok,
+                    reason: String::new(),
+                    source_name: String::from("Fake"),
+                    content: Some(manga_identifier),
+                };
+                let response = serde_json::to_string(&response).unwrap();
+                write_to_stream(&response, &mut stream).await.unwrap();
+            }
+        });
+
+        let connection = Connection::connect(&endpoint).await.unwrap();
+        let fetch = |id: &str| {
+            connection.request::<String>(Command::FetchManga {
+                manga_identifier: id.to_string(),
+            })
+        };
+
+        let (first, second) = tokio::join!(fetch("first"), fetch("second"));
+        assert_eq!(first.unwrap().content.unwrap(), "first");
+        assert_eq!(second.unwrap().content.unwrap(), "second");
+
+        // Commands the source didn't advertise never leave the client
+        let unsupported = connection
+            .request::<String>(Command::FetchChapter {
+                chapter_identifier: String::from("1"),
+            })
+            .await;
+        assert!(unsupported.is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn retry_rate_limited_requests() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = Endpoint::Tcp(listener.local_addr().unwrap().to_string());
+
+        // This fake source is rate limited once, then answers normally
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            answer_ping(&mut stream).await;
+
+            for status in [Status::Error(ErrorCode::RateLimited(Some(0))), Status::Ok] {
+                let raw = read_from_stream(&mut stream).await.unwrap();
+                let request: Request = serde_json::from_slice(&raw).unwrap();
+                let response = Response {
+                    id: request.id,
+                    content: (status == Status::Ok).then(|| String::from("manga")),
+                    status,
+                    reason: String::new(),
+                    source_name: String::from("Fake"),
+                };
+                let response = serde_json::to_string(&response).unwrap();
+                write_to_stream(&response, &mut stream).await.unwrap();
+            }
+        });
+
+        let connection = Connection::connect(&endpoint).await.unwrap();
+        let response = connection
+            .request::<String>(Command::FetchManga {
+                manga_identifier: String::from("1"),
+            })
+            .await
+            .unwrap();
+        assert_eq!(response.status, Status::Ok);
+        assert_eq!(response.content.unwrap(), "manga");
+    }
+
+    #[tokio::test]
+    async fn refuse_old_sources() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = Endpoint::Tcp(listener.local_addr().unwrap().to_string());
+
+        // A GMTP 0.0.0 source answers ping without an id and without any content
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            read_from_stream(&mut stream).await.unwrap();
+            let response = r#"{"status":"Ok","reason":"Pong","source_name":"Old","content":null}"#;
+            write_to_stream(response, &mut stream).await.unwrap();
+        });
+
+        let error = Connection::connect(&endpoint).await.err().unwrap();
+        assert!(error.to_string().contains("too old"));
+    }
+}
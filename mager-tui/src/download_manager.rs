@@ -0,0 +1,542 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use color_eyre::eyre::Result;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use crate::actions::{Action, ActionTx};
+use crate::export::ExportFormat;
+use crate::mager::{download_chapter, download_volume, DownloadProgress};
+use crate::source_manager::SourceManager;
+use mager_core::config::{self, Config};
+
+/// How many chapters are downloaded at the same time
+const MAX_CONCURRENT_DOWNLOADS: usize = 2;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum JobState {
+    Queued,
+    Running,
+    Paused,
+    Failed,
+    Finished,
+}
+
+/// A chapter to download, or the whole volume it belongs to
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct DownloadJob {
+    pub id: u64,
+    pub source: String,
+    pub chapter_identifier: String,
+    #[serde(default)]
+    pub whole_volume: bool,
+    #[serde(default)]
+    pub format: ExportFormat,
+    // Both are known once the download has started
+    pub manga_title: Option<String>,
+    pub chapter_title: Option<String>,
+    pub state: JobState,
+    pub pages_done: usize,
+    pub pages_total: usize,
+    /// How much of the page being downloaded is done, from 0 to 1
+    #[serde(skip)]
+    pub page_progress: f32,
+    pub error: Option<String>,
+}
+
+impl DownloadJob {
+    /// How much of the chapter is done, from 0 to 1
+    pub(crate) fn progress(&self) -> f32 {
+        if self.state == JobState::Finished {
+            return 1.0;
+        }
+        if self.pages_total == 0 {
+            return 0.0;
+        }
+
+        (self.pages_done as f32 + self.page_progress) / self.pages_total as f32
+    }
+
+    /// Stops the job until it is resumed. The pages that are already downloaded are kept.
+    fn pause(&mut self) {
+        if let JobState::Queued | JobState::Running = self.state {
+            self.state = JobState::Paused;
+            self.page_progress = 0.0;
+        }
+    }
+
+    /// Puts a paused or failed job back in the queue
+    fn resume(&mut self) {
+        if let JobState::Paused | JobState::Failed = self.state {
+            self.state = JobState::Queued;
+            self.error = None;
+        }
+    }
+}
+
+/// The part of the manager that is saved on disk
+#[derive(Serialize, Deserialize, Default)]
+struct Queue {
+    next_id: u64,
+    jobs: Vec<DownloadJob>,
+}
+
+impl Queue {
+    /// Reads the queue saved at `path`. Jobs that were running when mager was closed are
+    /// queued again.
+    fn read(path: &Path) -> Self {
+        let mut queue = match fs::read_to_string(path) {
+            Ok(raw) => serde_json::from_str(&raw).unwrap_or_else(|e| {
+                warn!("Download queue is corrupted, starting a new one: {e}");
+                Queue::default()
+            }),
+            Err(_) => Queue::default(),
+        };
+
+        for job in queue.jobs.iter_mut() {
+            if job.state == JobState::Running {
+                job.state = JobState::Queued;
+            }
+        }
+
+        queue
+    }
+
+    /// The queue as it is saved on disk, without the jobs that are finished
+    fn saved(&self) -> Self {
+        Self {
+            next_id: self.next_id,
+            jobs: (self.jobs.iter())
+                .filter(|j| j.state != JobState::Finished)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    fn write(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+
+        Ok(())
+    }
+
+    /// Adds a job for the chapter, or the volume it belongs to, unless one is already
+    /// waiting. Returns whether it was added.
+    fn push(
+        &mut self,
+        source: String,
+        chapter_identifier: String,
+        format: ExportFormat,
+        whole_volume: bool,
+    ) -> bool {
+        let is_pending = self.jobs.iter().any(|j| {
+            j.source == source
+                && j.chapter_identifier == chapter_identifier
+                && j.whole_volume == whole_volume
+                && !matches!(j.state, JobState::Finished | JobState::Failed)
+        });
+        if is_pending {
+            return false;
+        }
+
+        self.next_id += 1;
+        self.jobs.push(DownloadJob {
+            id: self.next_id,
+            source,
+            chapter_identifier,
+            whole_volume,
+            format,
+            manga_title: None,
+            chapter_title: None,
+            state: JobState::Queued,
+            pages_done: 0,
+            pages_total: 0,
+            page_progress: 0.0,
+            error: None,
+        });
+
+        true
+    }
+
+    fn remove(&mut self, id: u64) {
+        self.jobs.retain(|j| j.id != id);
+    }
+}
+
+struct State {
+    queue: Queue,
+    running: HashMap<u64, JoinHandle<()>>,
+    /// Counts the changes to the queue, so older snapshots aren't saved over newer ones
+    version: u64,
+}
+
+/// A copy of the queue taken while the state is locked, saved once it is unlocked
+struct Snapshot {
+    version: u64,
+    queue: Queue,
+}
+
+/// Downloads queued chapters in the background, a few at a time. The queue is saved on
+/// disk when a job changes state, so downloads that didn't finish are picked up again the
+/// next time mager starts. Finished jobs aren't saved. Every change is reported with
+/// `Action::DisplayDownloads`.
+///
+/// The manager is cheap to clone, every clone manages the same queue.
+#[derive(Clone)]
+pub(crate) struct DownloadManager {
+    state: Arc<Mutex<State>>,
+    sources: SourceManager,
+    config: Arc<Config>,
+    action_tx: ActionTx,
+    queue_file: PathBuf,
+    /// The version of the last snapshot saved on disk
+    written: Arc<Mutex<u64>>,
+}
+
+impl DownloadManager {
+    /// Loads the queue saved by the previous session and starts downloading
+    pub(crate) fn load(
+        sources: SourceManager,
+        config: Config,
+        action_tx: ActionTx,
+    ) -> Result<Self> {
+        let queue_file = config::data_dir().join("downloads.json");
+        let queue = Queue::read(&queue_file);
+
+        let manager = Self {
+            state: Arc::new(Mutex::new(State {
+                queue,
+                running: HashMap::new(),
+                version: 0,
+            })),
+            sources,
+            config: Arc::new(config),
+            action_tx,
+            queue_file,
+            written: Arc::new(Mutex::new(0)),
+        };
+        manager.schedule();
+
+        Ok(manager)
+    }
+
+    pub(crate) fn jobs(&self) -> Vec<DownloadJob> {
+        self.state.lock().unwrap().queue.jobs.clone()
+    }
+
+    /// Adds the chapter, or the volume it belongs to, to the queue, unless it is already
+    /// waiting there
+    pub(crate) fn enqueue(
+        &self,
+        source: String,
+        chapter_identifier: String,
+        format: ExportFormat,
+        whole_volume: bool,
+    ) {
+        let snapshot = {
+            let mut state = self.state.lock().unwrap();
+            let queue = &mut state.queue;
+
+            if !queue.push(source, chapter_identifier, format, whole_volume) {
+                return;
+            }
+            self.changed(&mut state)
+        };
+
+        self.save(snapshot);
+        self.schedule();
+    }
+
+    /// Stops the job until it is resumed. The pages that are already downloaded are kept.
+    pub(crate) fn pause(&self, id: u64) {
+        self.update_job(id, DownloadJob::pause);
+    }
+
+    /// Puts a paused or failed job back in the queue
+    pub(crate) fn resume(&self, id: u64) {
+        self.update_job(id, DownloadJob::resume);
+    }
+
+    /// Stops the job and removes it from the queue
+    pub(crate) fn cancel(&self, id: u64) {
+        let snapshot = {
+            let mut state = self.state.lock().unwrap();
+            if let Some(handle) = state.running.remove(&id) {
+                handle.abort();
+            }
+            state.queue.remove(id);
+            self.changed(&mut state)
+        };
+
+        self.save(snapshot);
+        self.schedule();
+    }
+
+    fn update_job(&self, id: u64, update: impl FnOnce(&mut DownloadJob)) {
+        let snapshot = {
+            let mut guard = self.state.lock().unwrap();
+            let state = &mut *guard;
+            let Some(job) = state.queue.jobs.iter_mut().find(|j| j.id == id) else {
+                return;
+            };
+            update(job);
+
+            // Jobs that aren't running anymore are stopped right away
+            if job.state != JobState::Running {
+                if let Some(handle) = state.running.remove(&id) {
+                    handle.abort();
+                }
+            }
+            self.changed(state)
+        };
+
+        self.save(snapshot);
+        self.schedule();
+    }
+
+    /// Starts queued jobs, as long as there is room for them
+    fn schedule(&self) {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+
+        let free = MAX_CONCURRENT_DOWNLOADS.saturating_sub(state.running.len());
+        let started: Vec<u64> = state
+            .queue
+            .jobs
+            .iter_mut()
+            .filter(|j| j.state == JobState::Queued)
+            .take(free)
+            .map(|j| {
+                j.state = JobState::Running;
+                j.id
+            })
+            .collect();
+        if started.is_empty() {
+            return;
+        }
+
+        for id in started {
+            info!("Starting download {id}");
+            let manager = self.clone();
+            let handle = tokio::spawn(async move { manager.run(id).await });
+            state.running.insert(id, handle);
+        }
+
+        let snapshot = self.changed(state);
+        drop(guard);
+        self.save(snapshot);
+    }
+
+    async fn run(&self, id: u64) {
+        let result = self.download(id).await;
+
+        let snapshot = {
+            let mut state = self.state.lock().unwrap();
+            state.running.remove(&id);
+
+            if let Some(job) = state.queue.jobs.iter_mut().find(|j| j.id == id) {
+                match result {
+                    Ok(_) => job.state = JobState::Finished,
+                    Err(e) => {
+                        error!("Download {id} failed: {e}");
+                        job.state = JobState::Failed;
+                        job.error = Some(e.to_string());
+                    }
+                }
+                job.page_progress = 0.0;
+            }
+            self.changed(&mut state)
+        };
+
+        self.save(snapshot);
+        self.schedule();
+    }
+
+    async fn download(&self, id: u64) -> Result<()> {
+        let Some(job) = self.jobs().into_iter().find(|j| j.id == id) else {
+            return Ok(());
+        };
+
+        let connection = self.sources.connect(&job.source).await?;
+        let on_progress = |progress| self.report_progress(id, progress);
+        let (config, chapter) = (&self.config, &job.chapter_identifier);
+        if job.whole_volume {
+            download_volume(&connection, config, chapter, job.format, on_progress).await
+        } else {
+            download_chapter(&connection, config, chapter, job.format, on_progress).await
+        }
+    }
+
+    /// Shows the progress of the job. Only its start is saved, pages that are already
+    /// downloaded are skipped anyway when the job is resumed.
+    fn report_progress(&self, id: u64, progress: DownloadProgress) {
+        let mut state = self.state.lock().unwrap();
+        let Some(job) = state.queue.jobs.iter_mut().find(|j| j.id == id) else {
+            return;
+        };
+
+        match progress {
+            DownloadProgress::Started {
+                manga_title,
+                chapter_title,
+                pages,
+            } => {
+                job.manga_title = Some(manga_title);
+                job.chapter_title = Some(chapter_title);
+                job.pages_total = pages;
+                let snapshot = self.changed(&mut state);
+                drop(state);
+                self.save(snapshot);
+            }
+            DownloadProgress::Page(progress) => {
+                // Reporting every chunk would flood the UI
+                if progress - job.page_progress < 0.1 {
+                    return;
+                }
+                job.page_progress = progress;
+                self.notify(&state);
+            }
+            DownloadProgress::PageDone(page) => {
+                job.pages_done = page;
+                job.page_progress = 0.0;
+                self.notify(&state);
+            }
+            DownloadProgress::ChapterDone(chapter) => {
+                let _ = self
+                    .action_tx
+                    .send(Action::ChapterDownloaded(job.source.clone(), chapter));
+            }
+        }
+    }
+
+    /// Tells the UI about the change, and takes a snapshot of the queue to save once the
+    /// state is unlocked
+    fn changed(&self, state: &mut State) -> Snapshot {
+        self.notify(state);
+
+        state.version += 1;
+        Snapshot {
+            version: state.version,
+            queue: state.queue.saved(),
+        }
+    }
+
+    /// Saves the snapshot on disk, unless a newer one was saved in the meantime
+    fn save(&self, snapshot: Snapshot) {
+        let mut written = self.written.lock().unwrap();
+        if snapshot.version <= *written {
+            return;
+        }
+
+        if let Err(e) = snapshot.queue.write(&self.queue_file) {
+            error!("Failed to save the download queue: {e}");
+        }
+        *written = snapshot.version;
+    }
+
+    fn notify(&self, state: &State) {
+        let _ = self
+            .action_tx
+            .send(Action::DisplayDownloads(state.queue.jobs.clone()));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, process};
+
+    use super::*;
+
+    fn queue() -> Queue {
+        let mut queue = Queue::default();
+        for chapter in ["a", "b", "c"] {
+            let source = String::from("MangaDex");
+            queue.push(source, chapter.to_string(), ExportFormat::default(), false);
+        }
+        queue
+    }
+
+    #[test]
+    fn pending_jobs_are_not_queued_twice() {
+        let mut queue = queue();
+        let source = String::from("MangaDex");
+        let format = ExportFormat::default();
+
+        assert!(!queue.push(source.clone(), String::from("a"), format, false));
+        assert!(queue.push(source.clone(), String::from("a"), format, true));
+
+        queue.jobs[1].state = JobState::Finished;
+        assert!(queue.push(source.clone(), String::from("b"), format, false));
+        assert_eq!(queue.jobs.len(), 5);
+        assert_eq!(queue.jobs[4].id, 5);
+
+        // A cancelled job can be queued again
+        queue.remove(1);
+        assert!(queue.jobs.iter().all(|j| j.id != 1));
+        assert!(queue.push(source, String::from("a"), format, false));
+    }
+
+    #[test]
+    fn pause_and_resume() {
+        let mut job = queue().jobs.remove(0);
+
+        job.state = JobState::Running;
+        job.page_progress = 0.5;
+        job.pause();
+        assert_eq!(job.state, JobState::Paused);
+        assert_eq!(job.page_progress, 0.0);
+
+        job.resume();
+        assert_eq!(job.state, JobState::Queued);
+
+        job.state = JobState::Failed;
+        job.error = Some(String::from("Network problem"));
+        job.resume();
+        assert_eq!(job.state, JobState::Queued);
+        assert_eq!(job.error, None);
+
+        // Finished jobs stay finished
+        job.state = JobState::Finished;
+        job.pause();
+        job.resume();
+        assert_eq!(job.state, JobState::Finished);
+    }
+
+    #[test]
+    fn saved_queue_is_picked_up_again() {
+        let path = env::temp_dir().join(format!("mager-downloads-{}.json", process::id()));
+        let mut queue = queue();
+        queue.jobs[0].state = JobState::Running;
+        queue.jobs[0].pages_done = 3;
+        queue.jobs[1].state = JobState::Paused;
+        queue.jobs[2].state = JobState::Finished;
+        queue.saved().write(&path).unwrap();
+
+        let read = Queue::read(&path);
+        fs::remove_file(&path).unwrap();
+
+        // The running job was interrupted, it is queued again where it stopped. The
+        // finished one is dropped, without its id being given again.
+        let states: Vec<JobState> = read.jobs.iter().map(|j| j.state).collect();
+        assert_eq!(states, [JobState::Queued, JobState::Paused]);
+        assert_eq!(read.jobs[0].pages_done, 3);
+        assert_eq!(read.next_id, queue.next_id);
+    }
+
+    #[test]
+    fn missing_or_corrupted_queue() {
+        let path = env::temp_dir().join(format!("mager-downloads-bad-{}.json", process::id()));
+        assert!(Queue::read(&path).jobs.is_empty());
+
+        fs::write(&path, "{ not json").unwrap();
+        let read = Queue::read(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(read.jobs.is_empty());
+    }
+}
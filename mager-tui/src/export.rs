@@ -0,0 +1,378 @@
+pub mod cbz;
+pub mod epub;
+pub mod pdf;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{eyre, Result};
+use dto::{Chapter, Manga};
+use mager_core::images::image_extension;
+use serde::{Deserialize, Serialize};
+
+/// How downloaded chapters are stored
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum ExportFormat {
+    /// A folder with one image per page
+    #[default]
+    Images,
+    /// A comic book archive with a ComicInfo.xml, which Komga, Kavita and most e-readers
+    /// can import
+    Cbz,
+    /// A fixed-layout EPUB3, one page per image
+    Epub,
+    /// A PDF, one page per image
+    Pdf,
+}
+
+impl ExportFormat {
+    /// The format after this one, to cycle through them in the UI
+    pub(crate) fn next(self) -> Self {
+        match self {
+            Self::Images => Self::Cbz,
+            Self::Cbz => Self::Epub,
+            Self::Epub => Self::Pdf,
+            Self::Pdf => Self::Images,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Images => "Images",
+            Self::Cbz => "CBZ",
+            Self::Epub => "EPUB",
+            Self::Pdf => "PDF",
+        }
+    }
+
+    /// The extension of the exported file, `None` for formats that are not a single file
+    pub(crate) fn extension(self) -> Option<&'static str> {
+        match self {
+            Self::Images => None,
+            Self::Cbz => Some("cbz"),
+            Self::Epub => Some("epub"),
+            Self::Pdf => Some("pdf"),
+        }
+    }
+}
+
+/// A chapter whose pages are downloaded in `folder`
+pub(crate) struct DownloadedChapter {
+    pub chapter: Chapter,
+    pub folder: PathBuf,
+}
+
+/// One or several downloaded chapters of a manga, to be exported into a single file
+pub(crate) struct Export {
+    pub manga: Manga,
+    /// What the file contains, e.g. "#12 - The End" or "Volume 3"
+    pub title: String,
+    pub chapters: Vec<DownloadedChapter>,
+}
+
+impl Export {
+    /// The volume the chapters belong to, if they all belong to the same one
+    pub(crate) fn volume(&self) -> Option<&str> {
+        let volume = self.chapters.first()?.chapter.volume.as_deref()?;
+        self.chapters
+            .iter()
+            .all(|c| c.chapter.volume.as_deref() == Some(volume))
+            .then_some(volume)
+    }
+
+    /// The language of the chapters
+    pub(crate) fn language(&self) -> &str {
+        self.chapters
+            .first()
+            .map(|c| c.chapter.language.as_str())
+            .unwrap_or(&self.manga.language)
+    }
+
+    /// Names of the authors with the given role ("Author" or "Artist")
+    pub(crate) fn people(&self, role: &str) -> Vec<&str> {
+        self.manga
+            .authors
+            .iter()
+            .filter(|a| a.details == role)
+            .map(|a| a.name.as_str())
+            .collect()
+    }
+}
+
+/// Writes the export to `destination` in the given format. This is blocking, so it should
+/// be run away from the async runtime.
+pub(crate) fn write_export(
+    format: ExportFormat,
+    export: &Export,
+    destination: &Path,
+) -> Result<()> {
+    // Written under another name first, so a broken file is never left behind
+    let mut part = destination.as_os_str().to_owned();
+    part.push(".part");
+    let part = PathBuf::from(part);
+
+    match format {
+        ExportFormat::Images => return Err(eyre!("Images are not exported to a file")),
+        ExportFormat::Cbz => cbz::write_cbz(export, &part)?,
+        ExportFormat::Epub => epub::write_epub(export, &part)?,
+        ExportFormat::Pdf => pdf::write_pdf(export, &part)?,
+    }
+
+    fs::rename(part, destination)?;
+    Ok(())
+}
+
+/// A page image, read from a chapter folder
+pub(crate) struct PageImage {
+    pub bytes: Vec<u8>,
+    pub extension: &'static str,
+}
+
+impl PageImage {
+    pub(crate) fn read(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path)?;
+        let extension = image_extension(&bytes)
+            .ok_or(eyre!("{} is not an image mager knows", path.display()))?;
+
+        Ok(Self { bytes, extension })
+    }
+
+    pub(crate) fn media_type(&self) -> &'static str {
+        match self.extension {
+            "png" => "image/png",
+            "gif" => "image/gif",
+            "webp" => "image/webp",
+            _ => "image/jpeg",
+        }
+    }
+
+    /// Width and height of the image in pixels
+    pub(crate) fn dimensions(&self) -> Result<(u32, u32)> {
+        let format = image::guess_format(&self.bytes)?;
+        let reader = image::ImageReader::with_format(std::io::Cursor::new(&self.bytes), format);
+        Ok(reader.into_dimensions()?)
+    }
+}
+
+/// Every page of the export in reading order, along with the index of its chapter
+pub(crate) fn export_pages(export: &Export) -> Result<Vec<(usize, PathBuf)>> {
+    let mut pages = Vec::new();
+    for (i, chapter) in export.chapters.iter().enumerate() {
+        pages.extend(chapter_pages(&chapter.folder)?.into_iter().map(|p| (i, p)));
+    }
+
+    if pages.is_empty() {
+        return Err(eyre!("There's no page to export"));
+    }
+    Ok(pages)
+}
+
+/// Escapes text so it can be put in XML (and XHTML) documents
+pub(crate) fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Marks chapter folders that only hold pages for an export, so they are removed once
+/// the export is written, even when it is resumed
+const EXPORT_FILE: &str = ".mager-export";
+
+/// Marks the folder as downloaded for an export, unless it already holds pages of its own
+pub(crate) fn mark_export_folder(folder: &Path) -> Result<()> {
+    if !folder.join(EXPORT_FILE).exists() && chapter_pages(folder)?.is_empty() {
+        fs::write(folder.join(EXPORT_FILE), "")?;
+    }
+    Ok(())
+}
+
+/// Keeps the folder once the chapter is downloaded, even if an export marked it
+pub(crate) fn keep_folder(folder: &Path) -> Result<()> {
+    match fs::remove_file(folder.join(EXPORT_FILE)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Removes the folder if it was only downloaded for an export
+pub(crate) fn remove_export_folder(folder: &Path) -> Result<()> {
+    if folder.join(EXPORT_FILE).exists() {
+        fs::remove_dir_all(folder)?;
+    }
+    Ok(())
+}
+
+/// Lists the pages downloaded in the chapter folder, in reading order
+pub(crate) fn chapter_pages(folder: &Path) -> Result<Vec<PathBuf>> {
+    let mut pages: Vec<(u32, PathBuf)> = Vec::new();
+
+    for entry in fs::read_dir(folder)? {
+        let path = entry?.path();
+
+        // Unfinished downloads aren't pages yet
+        if path.extension().is_some_and(|e| e == "part") {
+            continue;
+        }
+        if let Some(number) = page_number(&path) {
+            pages.push((number, path));
+        }
+    }
+
+    pages.sort_by_key(|(number, _)| *number);
+    Ok(pages.into_iter().map(|(_, path)| path).collect())
+}
+
+/// The number of a downloaded page, which ends its name (e.g. `Page 012.jpg`). Older
+/// downloads are named after the number alone, without padding nor extension.
+pub(crate) fn page_number(path: &Path) -> Option<u32> {
+    let name = path.file_name()?.to_str()?;
+    // Pages saved without an extension may still have a dot in their name, e.g. `1.5-001`
+    let stem = match name.rsplit_once('.') {
+        Some((stem, extension)) if extension.chars().all(|c| c.is_ascii_alphabetic()) => stem,
+        _ => name,
+    };
+    let digits = stem.len() - stem.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+
+    stem[stem.len() - digits..].parse().ok()
+}
+
+/// The name of a page inside an export, padded so that they sort in reading order
+pub(crate) fn page_name(page: usize, page_count: usize, extension: &str) -> String {
+    let width = page_count.to_string().len().max(3);
+    format!("{page:0width$}.{extension}")
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::io::Cursor;
+    use std::{env, process};
+
+    use dto::{Author, PublicationStatus};
+    use image::{ImageFormat, RgbImage};
+
+    use super::*;
+
+    /// A temporary folder, removed with everything in it when dropped
+    pub(crate) struct TestFolder {
+        pub path: PathBuf,
+    }
+
+    impl TestFolder {
+        pub(crate) fn new(name: &str) -> Self {
+            let path = env::temp_dir().join(format!("mager-test-{name}-{}", process::id()));
+            fs::create_dir_all(&path).unwrap();
+            Self { path }
+        }
+    }
+
+    impl Drop for TestFolder {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+
+    fn encode(format: ImageFormat) -> Vec<u8> {
+        let mut bytes = Cursor::new(Vec::new());
+        RgbImage::new(4, 6).write_to(&mut bytes, format).unwrap();
+        bytes.into_inner()
+    }
+
+    /// An export of the first volume of a manga, with three pages per chapter
+    pub(crate) fn sample_export(folder: &TestFolder, chapter_count: usize) -> Export {
+        let manga = Manga {
+            identifier: String::from("m"),
+            title: String::from("Tom & Jerry"),
+            authors: vec![
+                Author {
+                    name: String::from("Writer"),
+                    details: String::from("Author"),
+                },
+                Author {
+                    name: String::from("Drawer"),
+                    details: String::from("Artist"),
+                },
+            ],
+            original_language: String::from("ja"),
+            language: String::from("en"),
+            description: String::from("<b>Cat</b> and mouse"),
+            status: PublicationStatus::Completed,
+        };
+
+        let chapters = (1..=chapter_count)
+            .map(|number| {
+                let chapter_folder = folder.path.join(format!("#{number}"));
+                fs::create_dir_all(&chapter_folder).unwrap();
+                // Pages are sorted by number, not by name
+                fs::write(chapter_folder.join("1"), encode(ImageFormat::Jpeg)).unwrap();
+                fs::write(chapter_folder.join("2"), encode(ImageFormat::Png)).unwrap();
+                fs::write(chapter_folder.join("10"), encode(ImageFormat::Jpeg)).unwrap();
+
+                DownloadedChapter {
+                    chapter: Chapter {
+                        identifier: format!("c{number}"),
+                        manga_identifier: String::from("m"),
+                        title: String::from("Pilot"),
+                        number: number.to_string(),
+                        volume: Some(String::from("1")),
+                        group: None,
+                        language: String::from("en"),
+                        page_urls: Vec::new(),
+                    },
+                    folder: chapter_folder,
+                }
+            })
+            .collect();
+
+        Export {
+            manga,
+            title: String::from("Volume 1"),
+            chapters,
+        }
+    }
+
+    #[test]
+    fn downloaded_page_numbers() {
+        assert_eq!(page_number(Path::new("a/012.jpg")), Some(12));
+        assert_eq!(page_number(Path::new("a/Page 3.png")), Some(3));
+        assert_eq!(page_number(Path::new("a/7")), Some(7));
+        assert_eq!(page_number(Path::new("a/1.5-004.jpg")), Some(4));
+        assert_eq!(page_number(Path::new("a/1.5-004")), Some(4));
+        assert_eq!(page_number(Path::new("a/.mager-chapter")), None);
+        assert_eq!(page_number(Path::new("a/.mager-export")), None);
+    }
+
+    #[test]
+    fn export_folders() {
+        let folder = TestFolder::new("export-folders");
+        let (new, own) = (folder.path.join("new"), folder.path.join("own"));
+        fs::create_dir_all(&new).unwrap();
+        fs::create_dir_all(&own).unwrap();
+        fs::write(own.join("001.jpg"), "").unwrap();
+
+        mark_export_folder(&new).unwrap();
+        mark_export_folder(&own).unwrap();
+        // Resuming the export finds pages in the folder, which is still its own
+        fs::write(new.join("001.jpg"), "").unwrap();
+        mark_export_folder(&new).unwrap();
+
+        remove_export_folder(&new).unwrap();
+        remove_export_folder(&own).unwrap();
+        assert!(!new.exists());
+        assert!(own.exists());
+    }
+
+    #[test]
+    fn padded_page_names() {
+        assert_eq!(page_name(1, 30, "jpg"), "001.jpg");
+        assert_eq!(page_name(42, 1200, "png"), "0042.png");
+    }
+}
@@ -0,0 +1,134 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use color_eyre::eyre::Result;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use super::{escape_xml, export_pages, page_name, Export, PageImage};
+
+/// Packs the pages of the export into a CBZ archive, along with a ComicInfo.xml
+/// describing them.
+pub(crate) fn write_cbz(export: &Export, destination: &Path) -> Result<()> {
+    let pages = export_pages(export)?;
+
+    let mut archive = ZipWriter::new(File::create(destination)?);
+    // Images are already compressed, compressing them again only wastes time
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+
+    for (i, (_, page)) in pages.iter().enumerate() {
+        let image = PageImage::read(page)?;
+
+        archive.start_file(page_name(i + 1, pages.len(), image.extension), options)?;
+        archive.write_all(&image.bytes)?;
+    }
+
+    archive.start_file("ComicInfo.xml", options)?;
+    archive.write_all(comic_info(export, pages.len()).as_bytes())?;
+    archive.finish()?;
+
+    Ok(())
+}
+
+/// Builds the ComicInfo.xml of the export, following the Anansi ComicInfo schema v2.0
+fn comic_info(export: &Export, page_count: usize) -> String {
+    // A single chapter is numbered, a volume isn't
+    let number = match export.chapters.as_slice() {
+        [single] => single.chapter.number.clone(),
+        _ => String::new(),
+    };
+    let title = match export.chapters.as_slice() {
+        [single] => single.chapter.title.clone(),
+        _ => export.title.clone(),
+    };
+
+    let fields = [
+        ("Title", title),
+        ("Series", export.manga.title.clone()),
+        ("Number", number),
+        ("Volume", export.volume().unwrap_or_default().to_string()),
+        ("Summary", export.manga.description.clone()),
+        ("Writer", export.people("Author").join(", ")),
+        ("Penciller", export.people("Artist").join(", ")),
+        ("LanguageISO", export.language().to_string()),
+        ("PageCount", page_count.to_string()),
+        ("Manga", String::from("YesAndRightToLeft")),
+    ];
+
+    let mut xml = String::from(concat!(
+        r#"<?xml version="1.0" encoding="utf-8"?>"#,
+        "\n",
+        r#"<ComicInfo xmlns:xsd="http://www.w3.org/2001/XMLSchema" "#,
+        r#"xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance">"#,
+        "\n"
+    ));
+    for (name, value) in fields {
+        if !value.is_empty() {
+            xml.push_str(&format!("  <{name}>{}</{name}>\n", escape_xml(&value)));
+        }
+    }
+    xml.push_str("</ComicInfo>\n");
+
+    xml
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::io::Read;
+
+    use zip::ZipArchive;
+
+    use super::*;
+    use crate::export::test::{sample_export, TestFolder};
+
+    #[test]
+    fn pack_chapter() {
+        let folder = TestFolder::new("cbz");
+        let export = sample_export(&folder, 1);
+        fs::write(export.chapters[0].folder.join("3.part"), b"unfinished").unwrap();
+
+        let destination = folder.path.join("chapter.cbz");
+        write_cbz(&export, &destination).unwrap();
+
+        let mut archive = ZipArchive::new(File::open(&destination).unwrap()).unwrap();
+        let names: Vec<&str> = archive.file_names().collect();
+        assert_eq!(names, ["001.jpg", "002.png", "003.jpg", "ComicInfo.xml"]);
+
+        let mut info = String::new();
+        archive
+            .by_name("ComicInfo.xml")
+            .unwrap()
+            .read_to_string(&mut info)
+            .unwrap();
+        assert!(info.contains("<Series>Tom &amp; Jerry</Series>"));
+        assert!(info.contains("<Number>1</Number>"));
+        assert!(info.contains("<Summary>&lt;b&gt;Cat&lt;/b&gt; and mouse</Summary>"));
+        assert!(info.contains("<Writer>Writer</Writer>"));
+        assert!(info.contains("<Penciller>Drawer</Penciller>"));
+        assert!(info.contains("<PageCount>3</PageCount>"));
+    }
+
+    #[test]
+    fn pack_volume() {
+        let folder = TestFolder::new("cbz-volume");
+        let export = sample_export(&folder, 2);
+
+        let destination = folder.path.join("volume.cbz");
+        write_cbz(&export, &destination).unwrap();
+
+        let mut archive = ZipArchive::new(File::open(&destination).unwrap()).unwrap();
+        assert_eq!(archive.len(), 7);
+
+        let mut info = String::new();
+        archive
+            .by_name("ComicInfo.xml")
+            .unwrap()
+            .read_to_string(&mut info)
+            .unwrap();
+        assert!(info.contains("<Title>Volume 1</Title>"));
+        assert!(info.contains("<Volume>1</Volume>"));
+        assert!(!info.contains("<Number>"));
+    }
+}
@@ -0,0 +1,266 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use color_eyre::eyre::Result;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use super::{escape_xml, export_pages, page_name, Export, PageImage};
+
+const CONTAINER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#;
+
+/// Writes the pages of the export into a fixed-layout EPUB3, one image per page, so
+/// e-readers show them the same way a comic reader would.
+pub(crate) fn write_epub(export: &Export, destination: &Path) -> Result<()> {
+    let pages = export_pages(export)?;
+
+    let mut archive = ZipWriter::new(File::create(destination)?);
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+
+    // The mimetype has to come first and can't be compressed
+    archive.start_file("mimetype", stored)?;
+    archive.write_all(b"application/epub+zip")?;
+    archive.start_file("META-INF/container.xml", stored)?;
+    archive.write_all(CONTAINER.as_bytes())?;
+
+    let mut manifest = Vec::new();
+    let mut spine = Vec::new();
+    let mut toc = Vec::new();
+    let mut last_chapter = None;
+
+    for (i, (chapter, path)) in pages.iter().enumerate() {
+        let image = PageImage::read(path)?;
+        let (width, height) = image.dimensions()?;
+        let name = page_name(i + 1, pages.len(), image.extension);
+        let id = name.replace('.', "-");
+
+        archive.start_file(format!("OEBPS/images/{name}"), stored)?;
+        archive.write_all(&image.bytes)?;
+        archive.start_file(format!("OEBPS/pages/{id}.xhtml"), stored)?;
+        archive.write_all(page_xhtml(i + 1, &name, width, height).as_bytes())?;
+
+        let cover = if i == 0 {
+            r#" properties="cover-image""#
+        } else {
+            ""
+        };
+        manifest.push(format!(
+            r#"    <item id="image-{id}" href="images/{name}" media-type="{}"{cover}/>"#,
+            image.media_type()
+        ));
+        manifest.push(format!(
+            r#"    <item id="{id}" href="pages/{id}.xhtml" media-type="application/xhtml+xml"/>"#
+        ));
+        spine.push(format!(r#"    <itemref idref="{id}"/>"#));
+
+        // Every chapter starts at its first page in the table of contents
+        if last_chapter != Some(chapter) {
+            let c = &export.chapters[*chapter].chapter;
+            toc.push(format!(
+                r#"      <li><a href="pages/{id}.xhtml">{}</a></li>"#,
+                escape_xml(&format!("#{} - {}", c.number, c.title))
+            ));
+            last_chapter = Some(chapter);
+        }
+    }
+
+    archive.start_file("OEBPS/content.opf", stored)?;
+    archive.write_all(package(export, &manifest, &spine).as_bytes())?;
+    archive.start_file("OEBPS/nav.xhtml", stored)?;
+    archive.write_all(navigation(export, &toc).as_bytes())?;
+    archive.finish()?;
+
+    Ok(())
+}
+
+fn page_xhtml(number: usize, image: &str, width: u32, height: u32) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<head>
+  <title>Page {number}</title>
+  <meta name="viewport" content="width={width}, height={height}"/>
+  <style>body {{ margin: 0; }} img {{ width: {width}px; height: {height}px; }}</style>
+</head>
+<body>
+  <img src="../images/{image}" alt="Page {number}"/>
+</body>
+</html>
+"#
+    )
+}
+
+fn package(export: &Export, manifest: &[String], spine: &[String]) -> String {
+    let manga = &export.manga;
+    let first_chapter = export
+        .chapters
+        .first()
+        .map(|c| c.chapter.identifier.as_str())
+        .unwrap_or_default();
+
+    let mut metadata = vec![
+        format!(
+            r#"    <dc:identifier id="book-id">mager:{}:{}</dc:identifier>"#,
+            escape_xml(&manga.identifier),
+            escape_xml(first_chapter)
+        ),
+        format!(
+            "    <dc:title>{}</dc:title>",
+            escape_xml(&format!("{} - {}", manga.title, export.title))
+        ),
+        format!(
+            "    <dc:language>{}</dc:language>",
+            escape_xml(export.language())
+        ),
+        format!(
+            "    <dc:description>{}</dc:description>",
+            escape_xml(&manga.description)
+        ),
+        format!(
+            r#"    <meta property="dcterms:modified">{}</meta>"#,
+            modified_now()
+        ),
+        String::from(r#"    <meta property="rendition:layout">pre-paginated</meta>"#),
+        String::from(r#"    <meta property="rendition:spread">none</meta>"#),
+    ];
+    for author in manga.authors.iter() {
+        metadata.push(format!(
+            "    <dc:creator>{}</dc:creator>",
+            escape_xml(&author.name)
+        ));
+    }
+    if let Some(volume) = export.volume() {
+        metadata.push(format!(
+            r#"    <meta property="belongs-to-collection" id="series">{}</meta>"#,
+            escape_xml(&manga.title)
+        ));
+        metadata.push(format!(
+            r##"    <meta refines="#series" property="group-position">{}</meta>"##,
+            escape_xml(volume)
+        ));
+    }
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="book-id" prefix="rendition: http://www.idpf.org/vocab/rendition/#">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+{}
+  </metadata>
+  <manifest>
+    <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+{}
+  </manifest>
+  <spine page-progression-direction="rtl">
+{}
+  </spine>
+</package>
+"#,
+        metadata.join("\n"),
+        manifest.join("\n"),
+        spine.join("\n")
+    )
+}
+
+fn navigation(export: &Export, toc: &[String]) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<head>
+  <title>{}</title>
+</head>
+<body>
+  <nav epub:type="toc">
+    <ol>
+{}
+    </ol>
+  </nav>
+</body>
+</html>
+"#,
+        escape_xml(&export.title),
+        toc.join("\n")
+    )
+}
+
+/// The current time, as EPUB wants it in `dcterms:modified` (e.g. 2024-08-01T12:00:00Z)
+fn modified_now() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let (days, secs_of_day) = ((secs / 86400) as i64, secs % 86400);
+
+    // Converts days since the epoch into a civil date, from Howard Hinnant's algorithms
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use zip::ZipArchive;
+
+    use super::*;
+    use crate::export::test::{sample_export, TestFolder};
+
+    #[test]
+    fn fixed_layout_epub() {
+        let folder = TestFolder::new("epub");
+        let export = sample_export(&folder, 2);
+
+        let destination = folder.path.join("volume.epub");
+        write_epub(&export, &destination).unwrap();
+
+        let mut archive = ZipArchive::new(File::open(&destination).unwrap()).unwrap();
+        assert_eq!(archive.by_index(0).unwrap().name(), "mimetype");
+
+        let mut read = |name: &str| {
+            let mut content = String::new();
+            archive
+                .by_name(name)
+                .unwrap()
+                .read_to_string(&mut content)
+                .unwrap();
+            content
+        };
+
+        let package = read("OEBPS/content.opf");
+        assert!(package.contains("<dc:title>Tom &amp; Jerry - Volume 1</dc:title>"));
+        assert!(package.contains("<dc:creator>Writer</dc:creator>"));
+        assert!(package.contains("<dc:creator>Drawer</dc:creator>"));
+        assert!(package.contains("pre-paginated"));
+        assert_eq!(package.matches("<itemref").count(), 6);
+
+        let page = read("OEBPS/pages/002-png.xhtml");
+        assert!(page.contains(r#"content="width=4, height=6""#));
+
+        let navigation = read("OEBPS/nav.xhtml");
+        assert!(navigation.contains(r##"<a href="pages/001-jpg.xhtml">#1 - Pilot</a>"##));
+        assert!(navigation.contains(r##"<a href="pages/004-jpg.xhtml">#2 - Pilot</a>"##));
+    }
+}
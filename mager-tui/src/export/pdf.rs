@@ -0,0 +1,275 @@
+use std::fs::File;
+use std::io::{BufWriter, Cursor, Write};
+use std::path::Path;
+
+use color_eyre::eyre::{eyre, Result};
+use image::codecs::jpeg::JpegEncoder;
+
+use super::{export_pages, Export, PageImage};
+
+/// Quality of the pages that have to be converted to JPEG
+const JPEG_QUALITY: u8 = 90;
+
+// The first objects of the document, pages come after them
+const CATALOG: usize = 1;
+const PAGES: usize = 2;
+const INFO: usize = 3;
+
+/// Writes the pages of the export into a PDF, one image per page. Pages are as large as
+/// their image, so nothing is scaled.
+pub(crate) fn write_pdf(export: &Export, destination: &Path) -> Result<()> {
+    let pages = export_pages(export)?;
+    // Every page takes three objects: the page, its content and its image
+    let page_object = |i: usize| INFO + 1 + i * 3;
+
+    let mut pdf = PdfWriter::new(File::create(destination)?, INFO + pages.len() * 3)?;
+
+    pdf.object(
+        CATALOG,
+        format!("<< /Type /Catalog /Pages {PAGES} 0 R /ViewerPreferences << /Direction /R2L >> >>")
+            .as_bytes(),
+    )?;
+    let kids: Vec<String> = (0..pages.len())
+        .map(|i| format!("{} 0 R", page_object(i)))
+        .collect();
+    pdf.object(
+        PAGES,
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            pages.len()
+        )
+        .as_bytes(),
+    )?;
+
+    let authors: Vec<&str> = export
+        .manga
+        .authors
+        .iter()
+        .map(|a| a.name.as_str())
+        .collect();
+    pdf.object(
+        INFO,
+        format!(
+            "<< /Title {} /Author {} /Creator {} >>",
+            text_string(&format!("{} - {}", export.manga.title, export.title)),
+            text_string(&authors.join(", ")),
+            text_string("mager")
+        )
+        .as_bytes(),
+    )?;
+
+    for (i, (_, path)) in pages.iter().enumerate() {
+        let image = PdfImage::new(PageImage::read(path)?)?;
+        let (page, content, xobject) = (page_object(i), page_object(i) + 1, page_object(i) + 2);
+
+        pdf.object(
+            page,
+            format!(
+                "<< /Type /Page /Parent {PAGES} 0 R /MediaBox [0 0 {} {}] \
+                 /Resources << /XObject << /Im0 {xobject} 0 R >> >> /Contents {content} 0 R >>",
+                image.width, image.height
+            )
+            .as_bytes(),
+        )?;
+
+        // Draws the image over the whole page
+        let draw = format!("q {} 0 0 {} 0 0 cm /Im0 Do Q", image.width, image.height);
+        pdf.stream(
+            content,
+            &format!("<< /Length {} >>", draw.len()),
+            draw.as_bytes(),
+        )?;
+
+        pdf.stream(
+            xobject,
+            &format!(
+                "<< /Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace {} \
+                 /BitsPerComponent 8 /Filter /DCTDecode{} /Length {} >>",
+                image.width,
+                image.height,
+                image.color_space,
+                image.decode,
+                image.jpeg.len()
+            ),
+            &image.jpeg,
+        )?;
+    }
+
+    pdf.finish()
+}
+
+/// A page as PDF embeds it: PDF readers decode JPEG, but nothing else
+struct PdfImage {
+    jpeg: Vec<u8>,
+    width: u32,
+    height: u32,
+    color_space: &'static str,
+    decode: &'static str,
+}
+
+impl PdfImage {
+    fn new(page: PageImage) -> Result<Self> {
+        if page.extension == "jpg" {
+            let (width, height) = page.dimensions()?;
+            let (color_space, decode) = match jpeg_components(&page.bytes) {
+                Some(1) => ("/DeviceGray", ""),
+                // CMYK JPEGs are mostly written by Adobe software, which stores them inverted
+                Some(4) => ("/DeviceCMYK", " /Decode [1 0 1 0 1 0 1 0]"),
+                _ => ("/DeviceRGB", ""),
+            };
+
+            return Ok(Self {
+                jpeg: page.bytes,
+                width,
+                height,
+                color_space,
+                decode,
+            });
+        }
+
+        let image = image::load_from_memory(&page.bytes)?.to_rgb8();
+        let mut jpeg = Cursor::new(Vec::new());
+        image.write_with_encoder(JpegEncoder::new_with_quality(&mut jpeg, JPEG_QUALITY))?;
+
+        Ok(Self {
+            jpeg: jpeg.into_inner(),
+            width: image.width(),
+            height: image.height(),
+            color_space: "/DeviceRGB",
+            decode: "",
+        })
+    }
+}
+
+/// Reads how many color components the JPEG has from its frame header
+fn jpeg_components(bytes: &[u8]) -> Option<u8> {
+    // Skips the start of image marker
+    let mut i = 2;
+
+    while i + 3 < bytes.len() {
+        if bytes[i] != 0xFF {
+            return None;
+        }
+
+        let marker = bytes[i + 1];
+        // Markers can be padded with any number of 0xFF
+        if marker == 0xFF {
+            i += 1;
+            continue;
+        }
+
+        // Every start of frame marker, except those that are used for something else
+        if (0xC0..=0xCF).contains(&marker) && !matches!(marker, 0xC4 | 0xC8 | 0xCC) {
+            return bytes.get(i + 9).copied();
+        }
+
+        let length = u16::from_be_bytes([bytes[i + 2], bytes[i + 3]]) as usize;
+        i += 2 + length;
+    }
+
+    None
+}
+
+/// Encodes text as a PDF text string, in UTF-16 so that any title can be stored
+fn text_string(text: &str) -> String {
+    let mut hex = String::from("<FEFF");
+    for unit in text.encode_utf16() {
+        hex.push_str(&format!("{unit:04X}"));
+    }
+    hex.push('>');
+    hex
+}
+
+/// Writes the objects of a PDF, and the cross-reference table that lets readers find them
+struct PdfWriter {
+    out: BufWriter<File>,
+    position: usize,
+    /// Where every object starts in the file, by object number
+    offsets: Vec<usize>,
+}
+
+impl PdfWriter {
+    fn new(file: File, object_count: usize) -> Result<Self> {
+        let mut writer = Self {
+            out: BufWriter::new(file),
+            position: 0,
+            offsets: vec![0; object_count + 1],
+        };
+        // The binary comment tells tools the file isn't plain text
+        writer.write(b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n")?;
+
+        Ok(writer)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.out.write_all(bytes)?;
+        self.position += bytes.len();
+        Ok(())
+    }
+
+    fn object(&mut self, number: usize, content: &[u8]) -> Result<()> {
+        self.offsets[number] = self.position;
+        self.write(format!("{number} 0 obj\n").as_bytes())?;
+        self.write(content)?;
+        self.write(b"\nendobj\n")
+    }
+
+    fn stream(&mut self, number: usize, dictionary: &str, data: &[u8]) -> Result<()> {
+        self.offsets[number] = self.position;
+        self.write(format!("{number} 0 obj\n{dictionary}\nstream\n").as_bytes())?;
+        self.write(data)?;
+        self.write(b"\nendstream\nendobj\n")
+    }
+
+    fn finish(mut self) -> Result<()> {
+        if let Some(missing) = self.offsets.iter().skip(1).position(|o| *o == 0) {
+            return Err(eyre!("PDF object {} was never written", missing + 1));
+        }
+
+        let xref = self.position;
+        let mut table = format!("xref\n0 {}\n0000000000 65535 f \n", self.offsets.len());
+        for offset in self.offsets.iter().skip(1) {
+            table.push_str(&format!("{offset:010} 00000 n \n"));
+        }
+        table.push_str(&format!(
+            "trailer\n<< /Size {} /Root {CATALOG} 0 R /Info {INFO} 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+            self.offsets.len()
+        ));
+
+        self.write(table.as_bytes())?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+    use crate::export::test::{sample_export, TestFolder};
+
+    #[test]
+    fn one_page_per_image() {
+        let folder = TestFolder::new("pdf");
+        let export = sample_export(&folder, 2);
+
+        let destination = folder.path.join("volume.pdf");
+        write_pdf(&export, &destination).unwrap();
+
+        let pdf = String::from_utf8_lossy(&fs::read(&destination).unwrap()).into_owned();
+        assert!(pdf.starts_with("%PDF-1.4"));
+        assert!(pdf.ends_with("%%EOF\n"));
+        assert_eq!(pdf.matches("/Type /Page ").count(), 6);
+        assert!(pdf.contains("/Count 6"));
+        assert!(pdf.contains("/MediaBox [0 0 4 6]"));
+        assert!(pdf.contains("/ColorSpace /DeviceRGB"));
+        assert!(pdf.contains(&format!("/Title {}", text_string("Tom & Jerry - Volume 1"))));
+    }
+
+    #[test]
+    fn utf16_text_strings() {
+        assert_eq!(text_string("Aé"), "<FEFF004100E9>");
+    }
+}
@@ -0,0 +1,290 @@
+//! Showing images in the terminal.
+//!
+//! Terminals that speak the Kitty graphics protocol, iTerm2 inline images or Sixel are sent
+//! the image itself, written over the frame once ratatui drew it. Every other terminal
+//! gets the image drawn with halfblocks, two pixels per cell.
+
+use std::env;
+use std::io::{Cursor, Write};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use color_eyre::eyre::{eyre, Result};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use ratatui::buffer::Buffer;
+use ratatui::layout::Rect;
+use ratatui::style::Color;
+
+/// Size of a cell in pixels, for terminals that don't tell
+const DEFAULT_CELL_SIZE: (u16, u16) = (8, 16);
+
+/// Largest chunk of image data the Kitty protocol accepts at once
+const KITTY_CHUNK: usize = 4096;
+
+/// Removes every image Kitty shows
+pub(crate) const KITTY_CLEAR: &str = "\x1b_Ga=d,d=A,q=2\x1b\\";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Protocol {
+    Kitty,
+    Iterm2,
+    Sixel,
+    Halfblocks,
+}
+
+impl Protocol {
+    /// The protocol named in the config, or the one the terminal most likely speaks for
+    /// `auto`
+    pub(crate) fn from_config(name: &str) -> Self {
+        match name {
+            "kitty" => Self::Kitty,
+            "iterm2" => Self::Iterm2,
+            "sixel" => Self::Sixel,
+            "halfblocks" => Self::Halfblocks,
+            _ => Self::detect(|var| env::var(var).ok()),
+        }
+    }
+
+    /// Guesses the protocol from the environment variables terminals set
+    fn detect(var: impl Fn(&str) -> Option<String>) -> Self {
+        let term = var("TERM").unwrap_or_default();
+        let program = var("TERM_PROGRAM").unwrap_or_default();
+
+        if var("KITTY_WINDOW_ID").is_some() || term.contains("kitty") || term.contains("ghostty") {
+            return Self::Kitty;
+        }
+        if ["iTerm.app", "WezTerm", "mintty"].contains(&program.as_str()) {
+            return Self::Iterm2;
+        }
+        if ["foot", "mlterm", "contour", "yaft"]
+            .iter()
+            .any(|t| term.contains(t))
+        {
+            return Self::Sixel;
+        }
+
+        Self::Halfblocks
+    }
+
+    /// Size of a cell, in the pixels of the protocol. A halfblock cell holds two pixels
+    /// on top of each other.
+    pub(crate) fn cell_size(self) -> (u16, u16) {
+        if self == Self::Halfblocks {
+            return (1, 2);
+        }
+
+        match crossterm::terminal::window_size() {
+            Ok(size) if size.width > 0 && size.height > 0 && size.columns > 0 && size.rows > 0 => {
+                (size.width / size.columns, size.height / size.rows)
+            }
+            _ => DEFAULT_CELL_SIZE,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Fit {
+    /// The whole page shows
+    Height,
+    /// The page is as wide as the screen, and scrolled through
+    Width,
+}
+
+/// Where an image goes once fitted in an area of the screen
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct Placement {
+    /// The cells the image covers
+    pub area: Rect,
+    /// Size of the image once scaled, in pixels
+    pub scaled: (u32, u32),
+    /// The first row and the number of rows of the scaled image that show, in pixels
+    pub crop: (u32, u32),
+    /// How many rows the image can be scrolled down by
+    pub max_scroll: u16,
+}
+
+/// Fits an image of `size` pixels in the area. `scroll` is how many rows the image is
+/// scrolled down by, it only matters when it is fitted to the width.
+pub(crate) fn place(
+    size: (u32, u32),
+    area: Rect,
+    cell: (u16, u16),
+    fit: Fit,
+    scroll: u16,
+) -> Placement {
+    let (cell_w, cell_h) = (cell.0.max(1) as u32, cell.1.max(1) as u32);
+    let (area_w, area_h) = (area.width as u32 * cell_w, area.height as u32 * cell_h);
+    let (image_w, image_h) = (size.0.max(1), size.1.max(1));
+
+    let scale = match fit {
+        Fit::Height => (area_w as f64 / image_w as f64).min(area_h as f64 / image_h as f64),
+        Fit::Width => area_w as f64 / image_w as f64,
+    };
+    let scaled_w = ((image_w as f64 * scale).round() as u32).clamp(1, area_w.max(1));
+    let scaled_h = ((image_h as f64 * scale).round() as u32).max(1);
+
+    let rows = scaled_h.div_ceil(cell_h);
+    let max_scroll = rows.saturating_sub(area.height as u32) as u16;
+    let scroll = scroll.min(max_scroll) as u32;
+
+    let top = (scroll * cell_h).min(scaled_h - 1);
+    let height = (scaled_h - top).min(area_h.max(1));
+    let cols = scaled_w.div_ceil(cell_w) as u16;
+
+    Placement {
+        area: Rect {
+            x: area.x + (area.width - cols.min(area.width)) / 2,
+            y: area.y,
+            width: cols.min(area.width),
+            height: (height.div_ceil(cell_h) as u16).min(area.height),
+        },
+        scaled: (scaled_w, scaled_h),
+        crop: (top, height),
+        max_scroll,
+    }
+}
+
+/// The part of the image that shows, at the size it shows at
+pub(crate) fn render(image: &DynamicImage, placement: &Placement) -> RgbaImage {
+    let (width, height) = placement.scaled;
+    let scaled = imageops::resize(&image.to_rgba8(), width, height, FilterType::Triangle);
+
+    let (top, rows) = placement.crop;
+    imageops::crop_imm(&scaled, 0, top, width, rows).to_image()
+}
+
+/// The escape sequence that shows the image in the area, for protocols that send images
+pub(crate) fn encode(protocol: Protocol, image: &RgbaImage, area: Rect) -> Result<String> {
+    match protocol {
+        Protocol::Kitty => Ok(kitty(&png(image)?, area)),
+        Protocol::Iterm2 => Ok(iterm2(&png(image)?, area)),
+        Protocol::Sixel => sixel(image),
+        Protocol::Halfblocks => Err(eyre!("Halfblocks are drawn with cells")),
+    }
+}
+
+/// Writes the escape sequence at the top left corner of the area
+pub(crate) fn write_at(out: &mut dyn Write, area: Rect, sequence: &str) -> Result<()> {
+    // Terminals count rows and columns from one
+    write!(out, "\x1b[{};{}H", area.y + 1, area.x + 1)?;
+    out.write_all(sequence.as_bytes())?;
+    out.flush()?;
+
+    Ok(())
+}
+
+fn png(image: &RgbaImage) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    image.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)?;
+
+    Ok(bytes)
+}
+
+fn kitty(png: &[u8], area: Rect) -> String {
+    let data = STANDARD.encode(png);
+    let chunks: Vec<&[u8]> = data.as_bytes().chunks(KITTY_CHUNK).collect();
+
+    // The previous page is removed first. The image is scaled to the cells and the
+    // cursor isn't moved, so the frame drawn around it stays in place.
+    let mut sequence = String::from(KITTY_CLEAR);
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = (i + 1 < chunks.len()) as u8;
+        let chunk = std::str::from_utf8(chunk).unwrap_or_default();
+        if i == 0 {
+            sequence.push_str(&format!(
+                "\x1b_Ga=T,f=100,q=2,C=1,c={},r={},m={more};{chunk}\x1b\\",
+                area.width, area.height
+            ));
+        } else {
+            sequence.push_str(&format!("\x1b_Gm={more};{chunk}\x1b\\"));
+        }
+    }
+
+    sequence
+}
+
+fn iterm2(png: &[u8], area: Rect) -> String {
+    format!(
+        "\x1b]1337;File=inline=1;size={};width={};height={};preserveAspectRatio=0:{}\x07",
+        png.len(),
+        area.width,
+        area.height,
+        STANDARD.encode(png)
+    )
+}
+
+fn sixel(image: &RgbaImage) -> Result<String> {
+    let rgb = DynamicImage::ImageRgba8(image.clone()).to_rgb8();
+
+    icy_sixel::sixel_string(
+        rgb.as_raw(),
+        rgb.width() as i32,
+        rgb.height() as i32,
+        icy_sixel::PixelFormat::RGB888,
+        icy_sixel::DiffusionMethod::Stucki,
+        icy_sixel::MethodForLargest::Auto,
+        icy_sixel::MethodForRep::Auto,
+        icy_sixel::Quality::HIGH,
+    )
+    .map_err(|e| eyre!("Can't encode the page as sixel: {e}"))
+}
+
+/// Draws the image with one halfblock per two pixels, the image being rendered with
+/// [`Protocol::Halfblocks`] cells
+pub(crate) fn draw_halfblocks(image: &RgbaImage, area: Rect, buffer: &mut Buffer) {
+    let color = |x: u32, y: u32| match image.get_pixel_checked(x, y) {
+        Some(p) => Color::Rgb(p[0], p[1], p[2]),
+        None => Color::Reset,
+    };
+
+    for row in 0..area.height {
+        for col in 0..area.width {
+            let (x, y) = (col as u32, row as u32 * 2);
+            if let Some(cell) = buffer.cell_mut((area.x + col, area.y + row)) {
+                cell.set_symbol("▀")
+                    .set_fg(color(x, y))
+                    .set_bg(color(x, y + 1));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn detect_protocols() {
+        let detect = |vars: &[(&str, &str)]| {
+            Protocol::detect(|name| {
+                vars.iter()
+                    .find(|(n, _)| *n == name)
+                    .map(|(_, v)| v.to_string())
+            })
+        };
+
+        assert_eq!(detect(&[("TERM", "xterm-kitty")]), Protocol::Kitty);
+        assert_eq!(detect(&[("TERM_PROGRAM", "iTerm.app")]), Protocol::Iterm2);
+        assert_eq!(detect(&[("TERM", "foot")]), Protocol::Sixel);
+        assert_eq!(detect(&[("TERM", "xterm-256color")]), Protocol::Halfblocks);
+    }
+
+    #[test]
+    fn fit_pages() {
+        let area = Rect::new(0, 0, 80, 40);
+
+        // A tall page shows whole, centered
+        let placement = place((1000, 2000), area, (10, 20), Fit::Height, 5);
+        assert_eq!(placement.scaled, (400, 800));
+        assert_eq!(placement.area, Rect::new(20, 0, 40, 40));
+        assert_eq!((placement.crop, placement.max_scroll), ((0, 800), 0));
+
+        // Or as wide as the screen, scrolled through
+        let placement = place((1000, 2000), area, (10, 20), Fit::Width, 200);
+        assert_eq!(placement.scaled, (800, 1600));
+        assert_eq!(placement.max_scroll, 40);
+        assert_eq!(placement.crop, (800, 800));
+        assert_eq!(placement.area, area);
+    }
+}
@@ -1,30 +1,47 @@
-use std::env;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use color_eyre::eyre::eyre;
-use color_eyre::Result;
-use dto::carriers::{Command, Response, Status};
-use dto::{carriers::Request, MangaList};
+use color_eyre::eyre::{eyre, Result};
+use dto::carriers::{Command, ErrorCode, Response, Status};
+use dto::MangaList;
 
+use futures::future::join_all;
 use tokio::fs;
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::task;
+use tokio::time::timeout;
 use tracing::instrument;
 
-use crate::actions::Action;
+use crate::actions::{Action, FederatedResult, SearchOutcome, UpdateOutcome, UpdateReport};
+use crate::connection::Connection;
+use crate::export::{
+    chapter_pages, keep_folder, mark_export_folder, page_number, remove_export_folder,
+    write_export, DownloadedChapter, Export, ExportFormat,
+};
 use crate::source::Source;
-use crate::utils::*;
+use crate::source_manager::SourceManager;
+use mager_core::config::Config;
+use mager_core::images::download_image;
+use mager_core::library::LibraryEntry;
+use mager_core::template::{DownloadLayout, PathValues};
+use mager_core::update::ChapterScan;
 
 use dto::*;
 
-/// Function to list sources that are available in the local machine.
-/// By default, the path is located in $HOME/.local/mager/sources/
-pub async fn list_local_sources() -> Result<Vec<Source>> {
-    let mut sources: Vec<Source> = Vec::new();
+/// How long a source has to answer a search sent to every source, starting it included
+const FEDERATED_SEARCH_TIMEOUT: Duration = Duration::from_secs(15);
 
-    let home = env::var("HOME")?;
-    let dir = format!("{home}/.local/mager/sources/");
+/// Function to list sources that are available in the local machine, i.e. the
+/// executables in `dir` (`sources_dir` in the config). There are none when `dir` doesn't
+/// exist yet.
+pub async fn list_local_sources(dir: &Path) -> Result<Vec<Source>> {
+    let mut sources: Vec<Source> = Vec::new();
 
-    fs::create_dir_all(&dir).await?;
-    let mut registries = fs::read_dir(&dir).await?;
+    let mut registries = match fs::read_dir(dir).await {
+        Ok(registries) => registries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(sources),
+        Err(e) => return Err(e.into()),
+    };
 
     while let Some(reg) = registries.next_entry().await? {
         if !reg.file_type().await?.is_file() {
@@ -35,7 +52,9 @@ pub async fn list_local_sources() -> Result<Vec<Source>> {
             name: reg.file_name().clone().to_string_lossy().to_string(),
             url: None,
             is_local: true,
+            executable: Some(reg.path()),
             process: None,
+            socket: None,
         });
     }
 
@@ -44,172 +63,483 @@ pub async fn list_local_sources() -> Result<Vec<Source>> {
 
 /// Function to list sources that can be downloaded from the online repository.
 /// WARNING: This function is not finished! (i still don't know how to properly implements it)
+#[allow(dead_code)]
 pub async fn list_repo_sources() -> Result<Vec<Source>> {
     todo!();
 }
 
 /// Fetch all available sources, either local sources, or the one in the online repository.
 /// WARNING: This function is not finished! Use with caution
-pub async fn fetch_sources(action_tx: UnboundedSender<Action>) -> Result<()> {
-    let local_sources = list_local_sources().await?;
+pub async fn fetch_sources(sources_dir: &Path, action_tx: UnboundedSender<Action>) -> Result<()> {
+    let local_sources = list_local_sources(sources_dir).await?;
 
     action_tx.send(Action::DisplaySourceList(local_sources))?;
     Ok(())
 }
 
-/// Connects to port and verify source. If the current listener responds back, verification
-/// is successful.
-pub fn ping_source(port: u16) -> Result<()> {
-    let request = Request {
-        command: Command::Ping,
-        version: String::from("0.0.0"),
-    };
-    let request_string = serde_json::to_string(&request)?;
-
-    let mut connection = connect_to_source(port)?;
-    write_to_stream(&request_string, &mut connection)?;
-    let raw_response = read_from_stream(&mut connection)?;
-    let response: Response<()> = serde_json::from_slice(&raw_response)?;
-
-    match response.status {
-        Status::Ok => Ok(()),
-        Status::Error => Err(eyre!("Error verifying source.")),
-    }
-}
-
 /// Sends manga search request to active source and return its response. Please take note
 /// that all error from the server are not handled by this function.
 pub async fn search_manga(
-    port: u16,
+    connection: &Connection,
     search_keyword: &str,
     page: u32,
     filter: &Filter,
 ) -> Result<Response<MangaList>> {
-    let request = Request {
-        command: Command::Search {
-            keyword: search_keyword.to_string(),
-            page,
-            filter: filter.clone(),
-        },
-        version: String::from("0.0.0"),
+    let command = Command::Search {
+        keyword: search_keyword.to_string(),
+        page,
+        filter: filter.clone(),
+    };
+
+    connection.request(command).await
+}
+
+/// Searches the keyword in every local source at once. The outcome of each source is
+/// sent as soon as it answers, sources that are too slow or fail are reported as such.
+pub async fn search_everywhere(
+    sources: SourceManager,
+    keyword: String,
+    filter: Filter,
+    action_tx: UnboundedSender<Action>,
+) -> Result<()> {
+    let local_sources = list_local_sources(sources.sources_dir()).await?;
+
+    let send = |source: String, outcome: SearchOutcome| {
+        let _ = action_tx.send(Action::DisplayFederatedResult(FederatedResult {
+            keyword: keyword.clone(),
+            source,
+            outcome,
+        }));
     };
-    let req_string = serde_json::to_string(&request)?;
 
-    // Connect to source and validates it
-    let Ok(()) = ping_source(port) else {
-        return Err(eyre!("Error verifying source"));
+    for source in local_sources.iter() {
+        send(source.name.clone(), SearchOutcome::Searching);
+    }
+
+    let searches = local_sources.into_iter().map(|source| {
+        let name = source.name.clone();
+        let search = async {
+            let connection = sources.activate(source).await?;
+            search_manga(&connection, &keyword, 1, &filter).await
+        };
+
+        async {
+            let outcome = match timeout(FEDERATED_SEARCH_TIMEOUT, search).await {
+                Ok(Ok(response)) => match response.status {
+                    Status::Ok => SearchOutcome::Found(response.content.unwrap_or_default()),
+                    Status::Error(_) => {
+                        SearchOutcome::Failed(describe_error("search results", &response))
+                    }
+                },
+                Ok(Err(e)) => SearchOutcome::Failed(e.to_string()),
+                Err(_) => SearchOutcome::TimedOut,
+            };
+            send(name, outcome);
+        }
+    });
+    join_all(searches).await;
+
+    Ok(())
+}
+
+/// Looks for new chapters in every manga of the library, one after the other. `targets`
+/// are the manga along with the identifiers of the chapters the library knows. Each
+/// outcome is sent as soon as it is known, and the chapters found are sent to the
+/// library. New chapters are queued for download if `download` is set.
+pub async fn check_updates(
+    sources: SourceManager,
+    targets: Vec<(LibraryEntry, Vec<String>)>,
+    filter: Filter,
+    download: bool,
+    action_tx: UnboundedSender<Action>,
+) -> Result<()> {
+    let send = |entry: &LibraryEntry, outcome: UpdateOutcome| {
+        let _ = action_tx.send(Action::DisplayUpdate(UpdateReport {
+            source: entry.source.clone(),
+            identifier: entry.manga.identifier.clone(),
+            outcome,
+        }));
     };
 
-    let mut connection = connect_to_source(port)?;
-    write_to_stream(&req_string, &mut connection)?;
-    let raw_response = read_from_stream(&mut connection)?;
-    let response: Response<MangaList> = serde_json::from_slice(&raw_response)?;
+    for (entry, _) in targets.iter() {
+        send(entry, UpdateOutcome::Checking);
+    }
+
+    for (entry, known) in targets {
+        let scan = async {
+            let connection = sources.connect(&entry.source).await?;
+
+            let mut scan = ChapterScan::new(known, entry.checked);
+            let mut page = Some(1);
+            while let Some(p) = page {
+                let response = fetch_chapters(&connection, &entry.manga.identifier, p, &filter);
+                page = scan.add_page(content_or_error("chapter list", response.await?)?);
+            }
+            Ok::<_, color_eyre::Report>(scan)
+        };
+
+        let scan = match scan.await {
+            Ok(scan) => scan,
+            Err(e) => {
+                send(&entry, UpdateOutcome::Failed(e.to_string()));
+                continue;
+            }
+        };
+
+        let source = &entry.source;
+        if download {
+            // Oldest first, so they show up in the queue in reading order
+            for chapter in scan.new_chapters().iter().rev() {
+                action_tx.send(Action::DownloadChapter(
+                    source.clone(),
+                    chapter.identifier.clone(),
+                    ExportFormat::default(),
+                ))?;
+            }
+        }
+        send(&entry, UpdateOutcome::Found(scan.new_chapters().len()));
+        action_tx.send(Action::ChaptersChecked(
+            source.clone(),
+            entry.manga.identifier.clone(),
+            scan.chapters,
+        ))?;
+        action_tx.send(Action::FetchLibrary)?;
+    }
+
+    Ok(())
+}
+
+/// Sends a request for a list of manga without a keyword to active source and return its
+/// response. Please take note that all error from the server are not handled by this function.
+pub async fn browse_manga(
+    connection: &Connection,
+    mode: BrowseMode,
+    page: u32,
+    filter: &Filter,
+) -> Result<Response<MangaList>> {
+    let command = Command::Browse {
+        mode,
+        page,
+        filter: filter.clone(),
+    };
 
-    Ok(response)
+    connection.request(command).await
 }
 
 /// Sends chapter list request for a specified manga to active ource and return its response.
 /// Please take note that all error from the server are not handled by this function.
 pub async fn fetch_chapters(
-    port: u16,
+    connection: &Connection,
     manga_identifier: &str,
     page: u32,
     filter: &Filter,
 ) -> Result<Response<ChapterList>> {
-    let request = Request {
-        command: Command::FetchChapterList {
-            identifier: manga_identifier.to_string(),
-            page,
-            filter: filter.clone(),
-        },
-        version: String::from("0.0.0"),
-    };
-    let req_string = serde_json::to_string(&request)?;
-
-    // Connect to source and validates it
-    let Ok(()) = ping_source(port) else {
-        return Err(eyre!("Error verifying source"));
+    let command = Command::FetchChapterList {
+        identifier: manga_identifier.to_string(),
+        page,
+        filter: filter.clone(),
     };
 
-    let mut connection = connect_to_source(port)?;
-    write_to_stream(&req_string, &mut connection)?;
-    let raw_response = read_from_stream(&mut connection)?;
-    let response: Response<ChapterList> = serde_json::from_slice(&raw_response)?;
-
-    Ok(response)
+    connection.request(command).await
 }
 
 /// Sends a manga details request for a specified manga to active source and return its
 /// response. Please take note that all error from the server are not handled by this function.
-pub async fn fetch_manga(port: u16, manga_identifier: &str) -> Result<Response<Manga>> {
-    let request = Request {
-        command: Command::FetchManga {
-            manga_identifier: manga_identifier.to_string(),
-        },
-        version: String::from("0.0.0"),
+pub async fn fetch_manga(
+    connection: &Connection,
+    manga_identifier: &str,
+) -> Result<Response<Manga>> {
+    let command = Command::FetchManga {
+        manga_identifier: manga_identifier.to_string(),
     };
 
-    let req_string = serde_json::to_string(&request)?;
+    connection.request(command).await
+}
 
-    // Connect to source and validates it
-    if ping_source(port).is_err() {
-        return Err(eyre!("Error verifying source"));
-    }
+/// Sends a chapter details request for a specified manga to active source and return its
+/// response. Please take note that all error from the server are not handled by this function.
+pub async fn fetch_chapter(
+    connection: &Connection,
+    chapter_identifier: &str,
+) -> Result<Response<Chapter>> {
+    let command = Command::FetchChapter {
+        chapter_identifier: chapter_identifier.to_string(),
+    };
 
-    let mut connection = connect_to_source(port)?;
-    write_to_stream(&req_string, &mut connection)?;
-    let raw_response = read_from_stream(&mut connection)?;
-    let response: Response<Manga> = serde_json::from_slice(&raw_response)?;
+    connection.request(command).await
+}
 
-    Ok(response)
+/// Asks the active source for the tags manga can be filtered by and return its response.
+/// Please take note that all error from the server are not handled by this function.
+pub async fn list_tags(connection: &Connection) -> Result<Response<Vec<TagGroup>>> {
+    connection.request(Command::ListTags).await
 }
 
-/// Sends a chapter details request for a specified manga to active source and return its
-/// response. Please take note that all error from the server are not handled by this function.
-pub async fn fetch_chapter(port: u16, chapter_identifier: &str) -> Result<Response<Chapter>> {
-    let request = Request {
-        command: Command::FetchChapter {
-            chapter_identifier: chapter_identifier.to_string(),
-        },
-        version: String::from("0.0.0"),
+/// Turns an error response into a message that makes sense to the user. `what` is the
+/// thing that was requested, e.g. "manga".
+pub fn describe_error<T>(what: &str, response: &Response<T>) -> String {
+    let source = &response.source_name;
+    let reason = &response.reason;
+
+    let Status::Error(code) = response.status else {
+        return format!("{source} sent the {what} without any problem");
     };
 
-    let req_string = serde_json::to_string(&request)?;
+    match code {
+        ErrorCode::NotFound => format!("{source} doesn't have this {what} (anymore?)"),
+        ErrorCode::RateLimited(_) => {
+            format!("{source} is rate limiting us, try to get the {what} again later")
+        }
+        ErrorCode::Network => format!("Couldn't reach {source} to get the {what}: {reason}"),
+        ErrorCode::Upstream(status) => {
+            format!("{source} answered with HTTP {status} for the {what}: {reason}")
+        }
+        ErrorCode::BadRequest => format!("{source} didn't understand the request: {reason}"),
+        ErrorCode::Unsupported => format!("{source} can't get the {what}: {reason}"),
+        ErrorCode::Internal => format!("{source} failed to get the {what}: {reason}"),
+    }
+}
 
-    // Connect to source and validates it
-    if ping_source(port).is_err() {
-        return Err(eyre!("Error verifying source"));
+/// Takes the content out of a response, or turns the error it carries into a report
+pub fn content_or_error<T>(what: &str, response: Response<T>) -> Result<T> {
+    if let Status::Error(_) = response.status {
+        return Err(eyre!(describe_error(what, &response)));
     }
 
-    let mut connection = connect_to_source(port)?;
-    write_to_stream(&req_string, &mut connection)?;
-    let raw_response = read_from_stream(&mut connection)?;
-    let response: Response<Chapter> = serde_json::from_slice(&raw_response)?;
+    response
+        .content
+        .ok_or(eyre!("{} sent an empty {what}", response.source_name))
+}
 
-    Ok(response)
+/// What happened while downloading a chapter
+pub enum DownloadProgress {
+    /// The chapter details are known and its pages are about to be downloaded
+    Started {
+        manga_title: String,
+        chapter_title: String,
+        pages: usize,
+    },
+    /// How much of the page being downloaded is done, from 0 to 1
+    Page(f32),
+    /// The page is saved on disk. Pages are counted from 1.
+    PageDone(usize),
+    /// Every page of the chapter is saved on disk
+    ChapterDone(Chapter),
 }
 
-#[instrument]
-pub async fn download_chapter(port: u16, chapter_id: &str) -> Result<Vec<UnboundedReceiver<f32>>> {
-    let home = env::var("HOME")?;
+/// Downloads the pages of the chapter that aren't in the folder yet. `pages_before` is how
+/// many pages were downloaded before this chapter, so progress can span several chapters.
+async fn download_pages(
+    config: &Config,
+    layout: &DownloadLayout,
+    values: &PathValues,
+    chapter: &Chapter,
+    folder: &Path,
+    pages_before: usize,
+    on_progress: &mut impl FnMut(DownloadProgress),
+) -> Result<()> {
+    let existing: Vec<u32> = chapter_pages(folder)?
+        .iter()
+        .filter_map(|p| page_number(p))
+        .collect();
+
+    let page_count = chapter.page_urls.len();
+    for (i, url) in chapter.page_urls.iter().enumerate() {
+        let page = i + 1;
 
-    let ch_response = fetch_chapter(port, &chapter_id).await?;
-    let chapter = ch_response.content.unwrap();
+        if !existing.contains(&(page as u32)) {
+            let file_stem = folder.join(layout.template.page_name(values, page, page_count));
+            let progress = |p| on_progress(DownloadProgress::Page(p));
+            download_image(url, &config.user_agent, &file_stem, progress).await?;
+        }
+        on_progress(DownloadProgress::PageDone(pages_before + page));
+    }
 
-    let mng_response = fetch_manga(port, &chapter.manga_identifier).await?;
-    let manga = mng_response.content.unwrap();
+    Ok(())
+}
 
-    let base_folder = format!(
-        "{home}/Downloads/mager/{}/#{} - {}/",
-        manga.title, chapter.number, chapter.title
-    );
+/// Downloads every page of the chapter into its own folder, then exports it to the
+/// requested format. Pages that are already on disk are skipped, so an interrupted
+/// download picks up where it stopped.
+#[instrument(skip(connection, config, on_progress))]
+pub async fn download_chapter(
+    connection: &Connection,
+    config: &Config,
+    chapter_id: &str,
+    format: ExportFormat,
+    mut on_progress: impl FnMut(DownloadProgress),
+) -> Result<()> {
+    let source = &connection.info().name;
+    let layout = &DownloadLayout::from_config(config)?;
+
+    let ch_response = fetch_chapter(connection, chapter_id).await?;
+    let chapter = content_or_error("chapter", ch_response)?;
+
+    let mng_response = fetch_manga(connection, &chapter.manga_identifier).await?;
+    let manga = content_or_error("manga", mng_response)?;
+
+    let values = PathValues::new(source, &manga, &chapter);
+    let folder = layout.chapter_folder(&values, &format!("{source}/{}", chapter.identifier))?;
+    // A chapter that was already downloaded on its own is left alone
+    match format.extension() {
+        Some(_) => mark_export_folder(&folder)?,
+        None => keep_folder(&folder)?,
+    }
+    let title = format!("#{} - {}", chapter.number, chapter.title);
+
+    on_progress(DownloadProgress::Started {
+        manga_title: manga.title.clone(),
+        chapter_title: title.clone(),
+        pages: chapter.page_urls.len(),
+    });
+
+    download_pages(
+        config,
+        layout,
+        &values,
+        &chapter,
+        &folder,
+        0,
+        &mut on_progress,
+    )
+    .await?;
+    on_progress(DownloadProgress::ChapterDone(chapter.clone()));
+
+    let Some(extension) = format.extension() else {
+        return Ok(());
+    };
+    let destination = layout.chapter_file(&folder, extension);
+    let export = Export {
+        manga,
+        title,
+        chapters: vec![DownloadedChapter {
+            chapter,
+            folder: folder.clone(),
+        }],
+    };
 
-    let mut progress_rxs: Vec<UnboundedReceiver<f32>> = Vec::new();
-    for (i, url) in chapter.page_urls.iter().enumerate() {
-        progress_rxs
-            .push(download_resource(url.to_string(), format!("{}/{}", base_folder, i + 1)).await?);
+    task::spawn_blocking(move || -> Result<()> {
+        write_export(format, &export, &destination)?;
+        remove_export_folder(&folder)
+    })
+    .await?
+}
+
+/// Downloads every chapter of the volume the chapter belongs to, then exports them into a
+/// single file. When a chapter was translated several times, the translation of the group
+/// that translated the given chapter is kept, or the first one the source lists.
+#[instrument(skip(connection, config, on_progress))]
+pub async fn download_volume(
+    connection: &Connection,
+    config: &Config,
+    chapter_id: &str,
+    format: ExportFormat,
+    mut on_progress: impl FnMut(DownloadProgress),
+) -> Result<()> {
+    let source = &connection.info().name;
+    let layout = &DownloadLayout::from_config(config)?;
+
+    let ch_response = fetch_chapter(connection, chapter_id).await?;
+    let chapter = content_or_error("chapter", ch_response)?;
+    let volume = chapter.volume.clone().ok_or(eyre!(
+        "Chapter {} doesn't belong to any volume",
+        chapter.number
+    ))?;
+
+    let mng_response = fetch_manga(connection, &chapter.manga_identifier).await?;
+    let manga = content_or_error("manga", mng_response)?;
+
+    let filter = Filter {
+        sort: Sort::new(Order::Ascending),
+        ..config.filter()
+    };
+    let mut entries: Vec<ChapterListEntry> = Vec::new();
+    let mut page = 1;
+    loop {
+        let response = fetch_chapters(connection, &manga.identifier, page, &filter).await?;
+        let list = content_or_error("chapter list", response)?;
+
+        for entry in list.data {
+            if entry.volume.as_ref() != Some(&volume) {
+                continue;
+            }
+
+            match entries.iter_mut().find(|e| e.number == entry.number) {
+                None => entries.push(entry),
+                Some(e) if e.group != chapter.group && entry.group == chapter.group => *e = entry,
+                Some(_) => {}
+            }
+        }
+
+        if page >= list.total_page {
+            break;
+        }
+        page += 1;
+    }
+    entries.sort_by(|a, b| {
+        let number = |e: &ChapterListEntry| e.number.parse::<f32>().unwrap_or(f32::MAX);
+        number(a).total_cmp(&number(b))
+    });
+
+    let mut chapters = Vec::new();
+    for entry in entries {
+        let response = fetch_chapter(connection, &entry.identifier).await?;
+        chapters.push(content_or_error("chapter", response)?);
     }
-    Ok(progress_rxs)
+
+    let title = format!("Volume {volume}");
+    on_progress(DownloadProgress::Started {
+        manga_title: manga.title.clone(),
+        chapter_title: title.clone(),
+        pages: chapters.iter().map(|c| c.page_urls.len()).sum(),
+    });
+
+    let mut downloaded = Vec::new();
+    let mut pages_before = 0;
+    for chapter in chapters {
+        let values = PathValues::new(source, &manga, &chapter);
+        let folder = layout.chapter_folder(&values, &format!("{source}/{}", chapter.identifier))?;
+        // Chapters that were already downloaded on their own are left alone
+        match format.extension() {
+            Some(_) => mark_export_folder(&folder)?,
+            None => keep_folder(&folder)?,
+        }
+
+        let pages = &mut on_progress;
+        download_pages(
+            config,
+            layout,
+            &values,
+            &chapter,
+            &folder,
+            pages_before,
+            pages,
+        )
+        .await?;
+        pages_before += chapter.page_urls.len();
+        on_progress(DownloadProgress::ChapterDone(chapter.clone()));
+        downloaded.push(DownloadedChapter { chapter, folder });
+    }
+
+    let (Some(extension), Some(last)) = (format.extension(), downloaded.last()) else {
+        return Ok(());
+    };
+    let destination = layout.volume_file(&last.folder, &volume, extension);
+    let folders: Vec<PathBuf> = downloaded.iter().map(|c| c.folder.clone()).collect();
+    let export = Export {
+        manga,
+        title,
+        chapters: downloaded,
+    };
+
+    task::spawn_blocking(move || -> Result<()> {
+        write_export(format, &export, &destination)?;
+        for folder in folders {
+            remove_export_folder(&folder)?;
+        }
+        Ok(())
+    })
+    .await?
 }
 
 #[cfg(test)]
@@ -220,7 +550,18 @@ mod test {
     fn local_sources_test() {
         let rt = tokio::runtime::Runtime::new().unwrap();
 
-        let _result = rt.block_on(list_local_sources()).unwrap();
+        let sources_dir = tempfile::tempdir().unwrap();
+        std::fs::write(sources_dir.path().join("mangadex"), "").unwrap();
+        std::fs::create_dir(sources_dir.path().join("not-a-source")).unwrap();
+
+        let sources = rt.block_on(list_local_sources(sources_dir.path())).unwrap();
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0].name, "mangadex");
+
+        let missing = sources_dir.path().join("missing");
+        let sources = rt.block_on(list_local_sources(&missing)).unwrap();
+        assert!(sources.is_empty());
+        assert!(!missing.exists());
     }
 
     #[test]
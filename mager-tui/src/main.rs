@@ -4,8 +4,13 @@ use tracing_subscriber::FmtSubscriber;
 mod actions;
 mod app;
 mod components;
+mod connection;
+mod download_manager;
+mod export;
+mod graphics;
 mod mager;
 mod source;
+mod source_manager;
 mod tui;
 mod utils;
 
@@ -1,26 +1,37 @@
 use std::env;
-use std::process::{Child, Command};
+use std::fs::{self, DirBuilder};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::process::{self, Stdio};
 use std::sync::Arc;
+use std::time::Duration;
 
 use color_eyre::eyre::{eyre, Result};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::{Child, ChildStderr, ChildStdout, Command};
 use tokio::sync::Mutex;
+use tokio::time::timeout;
+use tracing::{debug, info};
+
+use crate::utils::Endpoint;
+
+/// How long a source has to tell it is ready after being spawned
+const READY_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[derive(Clone)]
 pub struct Source {
     pub name: String,
+    /// The `host:port` address of a remote source
     pub url: Option<String>,
     pub is_local: bool,
+    /// The executable of a local source
+    pub executable: Option<PathBuf>,
     pub process: Option<Arc<Mutex<Child>>>,
+    /// The socket a local source is listening on while it is active
+    pub socket: Option<PathBuf>,
 }
 
 impl Source {
-    pub(crate) fn get_process(&mut self) -> Option<Arc<Mutex<Child>>> {
-        if let Some(arc) = self.process.clone() {
-            return Some(arc.clone());
-        }
-        None
-    }
-
     pub(crate) async fn is_active(&mut self) -> bool {
         if let Some(child) = self.process.as_mut() {
             if let Ok(None) = child.lock().await.try_wait() {
@@ -30,23 +41,60 @@ impl Source {
         false
     }
 
-    pub(crate) async fn activate_source(&mut self, port: u16) -> Result<()> {
+    /// Makes the source ready to accept connections and returns where it can be reached.
+    /// Local sources are spawned listening on a socket that belongs to this session,
+    /// remote sources are expected to be running already.
+    pub(crate) async fn activate_source(&mut self) -> Result<Endpoint> {
+        if !self.is_local {
+            return self
+                .url
+                .clone()
+                .map(Endpoint::Tcp)
+                .ok_or(eyre!("{} doesn't have an address", self.name));
+        }
+
         if self.is_active().await {
             return Err(eyre!("This source is already active!"));
         }
 
-        let home = env::var("HOME")?;
-        let path = if cfg!(debug_assertions) {
-            format!("{home}/Projects/mager/target/debug/{}", self.name)
-        } else {
-            format!("{home}/.local/mager/sources/{}", self.name)
-        };
+        let path = self
+            .executable
+            .clone()
+            .ok_or(eyre!("{} isn't installed", self.name))?;
 
-        let process = Command::new(path).arg(&port.to_string()).spawn()?;
+        let socket = socket_dir()?.join(format!("{}-{}.sock", self.name, process::id()));
+        let mut process = Command::new(path)
+            .arg("--socket")
+            .arg(&socket)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        // The source logs to stderr, which would draw over the TUI
+        if let Some(stderr) = process.stderr.take() {
+            tokio::spawn(forward_logs(self.name.clone(), stderr));
+        }
+        let stdout = process.stdout.take();
 
         self.process = Some(Arc::new(Mutex::new(process)));
+        self.socket = Some(socket);
 
-        Ok(())
+        let Some(stdout) = stdout else {
+            return Err(eyre!("Can't read the output of {}", self.name));
+        };
+        match timeout(READY_TIMEOUT, wait_until_ready(stdout)).await {
+            Ok(Ok(endpoint)) => Ok(endpoint),
+            Ok(Err(e)) => {
+                self.deactivate_source();
+                Err(eyre!("{} failed to start: {e}", self.name))
+            }
+            Err(_) => {
+                self.deactivate_source();
+                Err(eyre!("{} took too long to start", self.name))
+            }
+        }
     }
 
     pub(crate) fn deactivate_source(&mut self) {
@@ -54,8 +102,64 @@ impl Source {
             return;
         };
 
-        tokio::spawn(async move { child.lock().await.kill() });
+        tokio::spawn(async move { child.lock().await.start_kill() });
+
+        if let Some(socket) = self.socket.take() {
+            let _ = fs::remove_file(socket);
+        }
+    }
+}
+
+/// Reads the output of a freshly spawned source until it tells where it is listening
+async fn wait_until_ready(stdout: ChildStdout) -> Result<Endpoint> {
+    let mut lines = BufReader::new(stdout).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        if let Some(endpoint) = Endpoint::from_ready_line(&line) {
+            info!("Source is ready on {endpoint}");
+            return Ok(endpoint);
+        }
+        debug!("Source said: {line}");
+    }
+
+    Err(eyre!("it exited before accepting connections"))
+}
+
+async fn forward_logs(name: String, stderr: ChildStderr) {
+    let mut lines = BufReader::new(stderr).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        debug!(source = name, "{line}");
+    }
+}
+
+/// The directory where the sockets of local sources are created. It is only accessible by
+/// the current user, so other users can't talk to our sources.
+fn socket_dir() -> Result<PathBuf> {
+    let runtime_dir = env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(env::temp_dir);
+    let dir = runtime_dir.join("mager");
+
+    DirBuilder::new().recursive(true).mode(0o700).create(&dir)?;
+    check_private(&dir)?;
+    Ok(dir)
+}
+
+/// Fails unless the directory is ours and only we can access it. In a shared temporary
+/// directory, another user may have created it first to control where sockets go.
+fn check_private(dir: &Path) -> Result<()> {
+    let metadata = fs::symlink_metadata(dir)?;
+    // SAFETY: getuid has no preconditions and can't fail
+    let uid = unsafe { libc::getuid() };
+
+    if !metadata.is_dir() || metadata.uid() != uid || metadata.mode() & 0o777 != 0o700 {
+        return Err(eyre!(
+            "{} has to be a directory only you can access, sources can't be started",
+            dir.display()
+        ));
     }
+    Ok(())
 }
 
 impl Drop for Source {
@@ -63,3 +167,27 @@ impl Drop for Source {
         self.deactivate_source();
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::fs::Permissions;
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    #[test]
+    fn socket_dir_has_to_be_private() {
+        let dir = env::temp_dir().join(format!("mager-test-sockets-{}", process::id()));
+        DirBuilder::new().mode(0o700).create(&dir).unwrap();
+        assert!(check_private(&dir).is_ok());
+
+        fs::set_permissions(&dir, Permissions::from_mode(0o755)).unwrap();
+        assert!(check_private(&dir).is_err());
+        fs::remove_dir(&dir).unwrap();
+
+        // Not a directory
+        fs::write(&dir, "").unwrap();
+        assert!(check_private(&dir).is_err());
+        fs::remove_file(&dir).unwrap();
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use color_eyre::eyre::{eyre, Result};
+use tracing::info;
+
+use crate::connection::Connection;
+use crate::mager::list_local_sources;
+use crate::source::Source;
+
+struct ActiveSource {
+    source: Source,
+    connection: Arc<Connection>,
+}
+
+/// Keeps every activated source alive along with the session opened with it, so several
+/// sources can be browsed at the same time. Sources are identified by their name.
+///
+/// The manager is cheap to clone, every clone manages the same sources.
+#[derive(Clone)]
+pub(crate) struct SourceManager {
+    sources: Arc<Mutex<HashMap<String, ActiveSource>>>,
+    sources_dir: Arc<PathBuf>,
+}
+
+impl SourceManager {
+    /// A manager for the sources installed in `sources_dir`
+    pub(crate) fn new(sources_dir: PathBuf) -> Self {
+        Self {
+            sources: Arc::default(),
+            sources_dir: Arc::new(sources_dir),
+        }
+    }
+
+    /// Where the installed sources are
+    pub(crate) fn sources_dir(&self) -> &Path {
+        &self.sources_dir
+    }
+
+    /// Activates the source and opens a session with it. Activating a source that is
+    /// already active just hands out its session, unless that session is closed.
+    pub(crate) async fn activate(&self, mut source: Source) -> Result<Arc<Connection>> {
+        if let Some(connection) = self.connection(&source.name) {
+            if !connection.is_closed() {
+                return Ok(connection);
+            }
+            // The source died or hung up on us, so it is started all over again
+            self.deactivate(&source.name);
+        }
+
+        // The lock isn't held while the source starts, so slow sources don't hold up
+        // the others
+        let endpoint = source.activate_source().await?;
+        let connection = match Connection::connect(&endpoint).await {
+            Ok(c) => Arc::new(c),
+            Err(e) => {
+                source.deactivate_source();
+                return Err(eyre!("Error verifying {}: {e}", source.name));
+            }
+        };
+
+        let info = connection.info();
+        info!(
+            "Connected to {} on {endpoint}, supported languages: {}",
+            info.name,
+            info.languages.join(", ")
+        );
+
+        let mut sources = self.sources.lock().unwrap();
+        if let Some(active) = sources.get(&source.name) {
+            // Someone else activated the same source in the meantime, theirs is kept
+            source.deactivate_source();
+            return Ok(active.connection.clone());
+        }
+        sources.insert(
+            source.name.clone(),
+            ActiveSource {
+                source,
+                connection: connection.clone(),
+            },
+        );
+
+        Ok(connection)
+    }
+
+    /// The session with the installed source with this name. The source is activated if
+    /// it isn't already.
+    pub(crate) async fn connect(&self, name: &str) -> Result<Arc<Connection>> {
+        if let Some(connection) = self.connection(name) {
+            if !connection.is_closed() {
+                return Ok(connection);
+            }
+        }
+
+        let source = list_local_sources(&self.sources_dir)
+            .await?
+            .into_iter()
+            .find(|s| s.name == name)
+            .ok_or(eyre!("{name} is not installed"))?;
+
+        self.activate(source).await
+    }
+
+    /// The session with the source, if it is active
+    pub(crate) fn connection(&self, name: &str) -> Option<Arc<Connection>> {
+        let sources = self.sources.lock().unwrap();
+        sources.get(name).map(|s| s.connection.clone())
+    }
+
+    /// Closes the session with the source and stops it. Requests that are still running
+    /// fail with an error.
+    pub(crate) fn deactivate(&self, name: &str) {
+        let removed = self.sources.lock().unwrap().remove(name);
+        if let Some(mut active) = removed {
+            info!("Deactivating {name}");
+            active.source.deactivate_source();
+        }
+    }
+
+    pub(crate) fn deactivate_all(&self) {
+        for (_, mut active) in self.sources.lock().unwrap().drain() {
+            active.source.deactivate_source();
+        }
+    }
+
+    /// Names of the active sources, sorted
+    pub(crate) fn active_sources(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.sources.lock().unwrap().keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
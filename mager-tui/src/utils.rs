@@ -1,152 +1,181 @@
+use std::fmt::{self, Display, Formatter};
 use std::io;
-use std::io::{Read, Write};
-use std::net::TcpStream;
-use std::path::Path;
-use std::thread;
-use std::time::Duration;
+use std::path::PathBuf;
 
 use color_eyre::eyre::{eyre, Result};
-use futures::StreamExt;
 use reqwest::ClientBuilder;
-use tokio::fs::{create_dir_all, File};
-use tokio::io::AsyncWriteExt;
-use tokio::sync::mpsc::{self, UnboundedReceiver};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UnixStream};
 
-use dto::carriers;
-use dto::carriers::{Request, Response, Status};
-use tracing::{event, info, warn, Level};
-
-pub(crate) async fn download_resource(
-    url: String,
-    file_name: impl AsRef<Path>,
-) -> Result<UnboundedReceiver<f32>> {
-    info!(
-        "Downloading file to {}",
-        file_name.as_ref().to_str().unwrap()
-    );
-    let (progress_tx, progress_rx) = mpsc::unbounded_channel::<f32>();
-    let client = ClientBuilder::new()
-        .user_agent("Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0")
-        .build()?;
-
-    let response = client.get(&url).send().await?;
-
-    let mut downloaded = 0 as f32;
-    let total_size = response
-        .content_length()
-        .ok_or(eyre!("Failed to get length"))? as f32;
-
-    if let Some(parent) = file_name.as_ref().parent() {
-        create_dir_all(parent).await?
-    }
-    let mut file = File::create(&file_name).await?;
-
-    let mut stream = response.bytes_stream();
-    let mut attempt_counter = 1;
-    while let Some(chunk) = stream.next().await {
-        let Ok(chunk) = chunk else {
-            // this code will try to reestablish the connection if interrupted
-            warn!("Network interrrupted, retrying");
-            attempt_counter += 1;
-
-            if attempt_counter >= 20 {
-                event!(Level::ERROR, "Maximum retries reached");
-                return Err(eyre!("Maximum retries reached"));
-            }
-
-            let range_header = format!("bytes={}", downloaded as u64);
-            let response = client
-                .get(&url)
-                .header("Range", range_header)
-                .send()
-                .await?;
-            stream = response.bytes_stream();
-            continue;
-        };
-        attempt_counter = 1;
-        downloaded += chunk.len() as f32;
-
-        file.write(&chunk).await?;
-
-        let progress = downloaded / total_size;
-        event!(Level::DEBUG, "downloading progress: {progress}");
-        progress_tx.send(progress)?;
-    }
-    info!("Download complete");
+use image::DynamicImage;
+use tokio::task;
 
-    Ok(progress_rx)
+use dto::carriers;
+use dto::carriers::{Request, Response, SourceInfo, Status};
+use tracing::{event, info, Level};
+
+/// Downloads the image at `url` and decodes it, without saving it anywhere
+pub(crate) async fn fetch_image(url: &str, user_agent: &str) -> Result<DynamicImage> {
+    let client = ClientBuilder::new().user_agent(user_agent).build()?;
+    let bytes = client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+
+    let image = task::spawn_blocking(move || image::load_from_memory(&bytes)).await??;
+    Ok(image)
 }
 
-pub(crate) fn write_to_stream(request: &str, connection: &mut TcpStream) -> Result<()> {
+pub(crate) async fn write_to_stream<W>(request: &str, connection: &mut W) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
     let size = request.len() as u32;
-    connection.write_all(&size.to_ne_bytes())?;
-    connection.write_all(request.as_bytes())?;
-    connection.flush()?;
+    connection.write_all(&size.to_ne_bytes()).await?;
+    connection.write_all(request.as_bytes()).await?;
+    connection.flush().await?;
 
     Ok(())
 }
 
-pub(crate) fn read_from_stream(connection: &mut TcpStream) -> Result<Vec<u8>> {
+pub(crate) async fn read_from_stream<R>(connection: &mut R) -> Result<Vec<u8>>
+where
+    R: AsyncRead + Unpin,
+{
     let mut len = [0; 4];
-    connection.read_exact(&mut len)?;
+    connection.read_exact(&mut len).await?;
 
     let mut buffer = vec![0; u32::from_ne_bytes(len) as usize];
-    connection.read_exact(&mut buffer)?;
+    connection.read_exact(&mut buffer).await?;
 
     Ok(buffer)
 }
 
-pub(crate) fn connect_to_source(port: u16) -> Result<TcpStream, io::Error> {
-    let mut counter = 0;
-    let mut error: io::Error;
-
-    loop {
-        event!(Level::DEBUG, "Connecting to port {port}");
-        counter += 1;
-        let stream = TcpStream::connect(format!("127.0.0.1:{port}"));
-        match stream {
-            Ok(s) => {
-                event!(Level::DEBUG, "Connection to port {port} established!");
-                return Ok(s);
-            }
-            Err(e) => {
-                event!(Level::DEBUG, "Failed to connect to , trying again...");
-                error = e
-            }
-        }
+/// Where a source can be reached
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Endpoint {
+    /// A Unix domain socket, used by the sources spawned by mager
+    Unix(PathBuf),
+    /// A `host:port` address, used by remote sources
+    Tcp(String),
+}
 
-        if counter > 20 {
-            event!(
-                Level::ERROR,
-                "Failed to connect to {port}. Reached attempt limit"
-            );
-            return Err(error);
+impl Endpoint {
+    /// Reads the endpoint from the line a source prints once it is ready, e.g.
+    /// `GMTP-READY unix /run/user/1000/mager/mangadex.sock`
+    pub(crate) fn from_ready_line(line: &str) -> Option<Self> {
+        let rest = line.trim().strip_prefix(carriers::READY)?.trim_start();
+        let (transport, address) = rest.split_once(' ')?;
+
+        match transport {
+            "unix" => Some(Self::Unix(PathBuf::from(address))),
+            "tcp" => Some(Self::Tcp(address.to_string())),
+            _ => None,
         }
-        thread::sleep(Duration::from_millis(50));
     }
 }
 
-pub(crate) fn ping(port: u16) -> Result<()> {
-    info!("Trying to ping port {port}");
-    let mut connection = connect_to_source(port)?;
+impl Display for Endpoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unix(path) => write!(f, "{}", path.display()),
+            Self::Tcp(address) => write!(f, "{address}"),
+        }
+    }
+}
 
-    let request = Request {
-        command: carriers::Command::Ping,
-        version: "0.0.0".to_string(),
-    };
+/// Any stream GMTP can be spoken over
+pub(crate) trait SourceStream: AsyncRead + AsyncWrite + Send + Unpin {}
 
-    let request = serde_json::to_string(&request)?;
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> SourceStream for T {}
 
-    write_to_stream(&request, &mut connection)?;
+pub(crate) async fn connect_to_source(
+    endpoint: &Endpoint,
+) -> Result<Box<dyn SourceStream>, io::Error> {
+    event!(Level::DEBUG, "Connecting to {endpoint}");
+    let stream: Box<dyn SourceStream> = match endpoint {
+        Endpoint::Unix(path) => Box::new(UnixStream::connect(path).await?),
+        Endpoint::Tcp(address) => Box::new(TcpStream::connect(address).await?),
+    };
+    event!(Level::DEBUG, "Connection to {endpoint} established!");
 
-    let raw_response = read_from_stream(&mut connection)?;
-    let response: Response<()> = serde_json::from_slice(&raw_response)?;
+    Ok(stream)
+}
 
-    if let Status::Error = response.status {
+/// Pings the source and checks whether it speaks a GMTP version we understand. This is
+/// meant to be the very first message of a session, so the response is read directly from
+/// the stream instead of being matched by id (very old sources don't send one back).
+pub(crate) async fn ping<S>(connection: &mut S) -> Result<SourceInfo>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    info!("Trying to ping source");
+    let request = serde_json::to_string(&Request::new(0, carriers::Command::Ping))?;
+    write_to_stream(&request, connection).await?;
+
+    let raw_response = read_from_stream(connection).await?;
+    // Sources older than GMTP 0.2.0 answer with an empty content, or something else
+    // entirely, so the content is checked separately from the status
+    let response: Response<serde_json::Value> = serde_json::from_slice(&raw_response)?;
+
+    if let Status::Error(code) = response.status {
         event!(Level::ERROR, "Response received, ping failed");
-        return Err(eyre!("Not Ok :("));
+        return Err(eyre!(
+            "Source refused the ping ({code}): {}",
+            response.reason
+        ));
     }
-    info!("Response received, ping successful");
 
-    Ok(())
+    let Some(info) = response
+        .content
+        .and_then(|c| serde_json::from_value::<SourceInfo>(c).ok())
+    else {
+        event!(Level::ERROR, "Source doesn't report its GMTP version");
+        return Err(eyre!(
+            "{} is too old for this version of mager (it doesn't speak GMTP {}), please update it",
+            response.source_name,
+            carriers::VERSION
+        ));
+    };
+
+    let Some(version) = info.compatible_version() else {
+        event!(
+            Level::ERROR,
+            "Incompatible source versions: {:?}",
+            info.versions
+        );
+        return Err(eyre!(
+            "{} speaks GMTP {}, but mager needs GMTP {}",
+            info.name,
+            info.versions.join(", "),
+            carriers::VERSION
+        ));
+    };
+    info!("Response received, ping successful. Talking GMTP {version}");
+
+    Ok(info)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_ready_line() {
+        assert_eq!(
+            Endpoint::from_ready_line("GMTP-READY tcp 127.0.0.1:41234\n"),
+            Some(Endpoint::Tcp(String::from("127.0.0.1:41234")))
+        );
+        assert_eq!(
+            Endpoint::from_ready_line("GMTP-READY unix /run/user/1000/mager/my source.sock"),
+            Some(Endpoint::Unix(PathBuf::from(
+                "/run/user/1000/mager/my source.sock"
+            )))
+        );
+        assert_eq!(Endpoint::from_ready_line("Listening on 7878"), None);
+        assert_eq!(Endpoint::from_ready_line("GMTP-READY udp 1.2.3.4:5"), None);
+    }
 }
@@ -0,0 +1,223 @@
+//! Commands that take everything they need as arguments, for scripts. Tables are printed
+//! by default, and JSON with `--json`. Errors go to stderr and end the program with an
+//! error code.
+
+use std::process;
+
+use dto::carriers::Command;
+use dto::{
+    ChapterList, ChapterListEntry, Filter, Manga, MangaList, Order, PublicationStatus, Sort,
+};
+use mager_core::config::Config;
+use mager_core::range::ChapterRange;
+use serde::Serialize;
+
+use crate::manga::{download_chapter, try_request};
+use crate::source::RunningSource;
+use crate::utils::fail;
+use crate::Query;
+
+/// Runs `f` with the address of the started source. The source is stopped before an error ends the
+/// program, so no source is left behind.
+fn with_source<T>(config: &Config, query: &Query, f: impl FnOnce(&str) -> Result<T, String>) -> T {
+    let source = RunningSource::start(config, &query.source).unwrap_or_else(|e| fail(e));
+    let result = f(source.address());
+    drop(source);
+
+    result.unwrap_or_else(|e| fail(e))
+}
+
+/// Prints the value as JSON, or with `table` otherwise
+fn print<T: Serialize>(json: bool, value: &T, table: impl FnOnce(&T)) {
+    match json {
+        true => println!(
+            "{}",
+            serde_json::to_string_pretty(value).unwrap_or_else(|e| fail(e))
+        ),
+        false => table(value),
+    }
+}
+
+fn status_name(status: &PublicationStatus) -> &'static str {
+    match status {
+        PublicationStatus::Ongoing => "Ongoing",
+        PublicationStatus::Completed => "Completed",
+        PublicationStatus::Hiatus => "Hiatus",
+        PublicationStatus::Cancelled => "Cancelled",
+        PublicationStatus::Unknown => "Unknown",
+    }
+}
+
+fn print_chapters(chapters: &[&ChapterListEntry]) {
+    for c in chapters {
+        println!(
+            "{:<8}  {:<6}  {:<36}  {}",
+            c.number,
+            c.volume.as_deref().unwrap_or("-"),
+            c.identifier,
+            c.title
+        );
+    }
+}
+
+/// The filter requests are sent with, in the language asked for if any
+fn filter(config: &Config, language: Option<&String>) -> Filter {
+    let filter = config.filter();
+    match language {
+        Some(language) => Filter {
+            language: language.clone(),
+            ..filter
+        },
+        None => filter,
+    }
+}
+
+/// Every chapter of the manga, oldest first
+fn fetch_chapters(
+    address: &str,
+    identifier: &str,
+    filter: Filter,
+) -> Result<Vec<ChapterListEntry>, String> {
+    let filter = Filter {
+        sort: Sort::new(Order::Ascending),
+        ..filter
+    };
+
+    let mut chapters = Vec::new();
+    let mut page = 1;
+    loop {
+        let command = Command::FetchChapterList {
+            identifier: identifier.to_string(),
+            page,
+            filter: filter.clone(),
+        };
+        let (_, mut list): (String, ChapterList) = try_request(address, command)?;
+        chapters.append(&mut list.data);
+
+        if list.page >= list.total_page {
+            return Ok(chapters);
+        }
+        page = list.page + 1;
+    }
+}
+
+pub(crate) fn search_handler(
+    config: &Config,
+    query: &Query,
+    keyword: &str,
+    page: u32,
+    language: Option<&String>,
+) {
+    let command = Command::Search {
+        keyword: keyword.to_string(),
+        page,
+        filter: filter(config, language),
+    };
+    let (_, list): (String, MangaList) =
+        with_source(config, query, |address| try_request(address, command));
+
+    print(query.json, &list, |list| {
+        for m in &list.data {
+            println!(
+                "{:<36}  {:<9}  {}",
+                m.identifier,
+                status_name(&m.status),
+                m.title
+            );
+        }
+        eprintln!("page {} of {}", list.page, list.total_page);
+    });
+}
+
+pub(crate) fn info_handler(config: &Config, query: &Query, identifier: &str) {
+    let command = Command::FetchManga {
+        manga_identifier: identifier.to_string(),
+    };
+    let (_, manga): (String, Manga) =
+        with_source(config, query, |address| try_request(address, command));
+
+    print(query.json, &manga, |m| {
+        let authors: Vec<&str> = m.authors.iter().map(|a| a.name.as_str()).collect();
+
+        println!("Identifier: {}", m.identifier);
+        println!("Title: {}", m.title);
+        println!("Status: {}", status_name(&m.status));
+        println!("Authors: {}", authors.join(", "));
+        println!("Language: {}", m.original_language);
+        println!();
+        println!("{}", m.description);
+    });
+}
+
+pub(crate) fn chapters_handler(
+    config: &Config,
+    query: &Query,
+    identifier: &str,
+    language: Option<&String>,
+    range: ChapterRange,
+) {
+    let chapters = with_source(config, query, |address| {
+        fetch_chapters(address, identifier, filter(config, language))
+    });
+    let chapters: Vec<&ChapterListEntry> = chapters
+        .iter()
+        .filter(|c| range.contains(&c.number))
+        .collect();
+
+    print(query.json, &chapters, |chapters| print_chapters(chapters));
+}
+
+/// Downloads the chapters of the manga in the range, oldest first. A chapter that fails
+/// doesn't stop the others, but the program ends with an error code.
+pub(crate) fn download_handler(
+    config: &Config,
+    query: &Query,
+    identifier: &str,
+    language: Option<&String>,
+    range: ChapterRange,
+) {
+    let (chapters, downloaded, failed) = with_source(config, query, |address| {
+        let chapters = fetch_chapters(address, identifier, filter(config, language))?;
+        let chapters: Vec<ChapterListEntry> = chapters
+            .into_iter()
+            .filter(|c| range.contains(&c.number))
+            .collect();
+        if chapters.is_empty() {
+            return Err(format!("No chapter of {identifier} is in {range}"));
+        }
+
+        let mut downloaded = Vec::new();
+        let mut failed = false;
+        for (i, chapter) in chapters.iter().enumerate() {
+            eprintln!(
+                "[{}/{}] #{} - {}",
+                i + 1,
+                chapters.len(),
+                chapter.number,
+                chapter.title
+            );
+
+            match download_chapter(config, address, identifier, chapter) {
+                Ok(chapter) => downloaded.push(chapter),
+                Err(e) => {
+                    eprintln!("#{}: {e}", chapter.number);
+                    failed = true;
+                }
+            }
+        }
+
+        Ok((chapters, downloaded, failed))
+    });
+
+    print(query.json, &downloaded, |downloaded| {
+        println!(
+            "{} of {} chapter(s) downloaded",
+            downloaded.len(),
+            chapters.len()
+        );
+    });
+
+    if failed {
+        process::exit(1);
+    }
+}
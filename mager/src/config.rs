@@ -0,0 +1,44 @@
+use std::env;
+
+use mager_core::config::{Config, KEYS};
+
+use crate::utils::fail;
+use crate::ConfigOperation;
+
+pub(crate) fn config_menu_handler(operation: &ConfigOperation) {
+    match operation {
+        ConfigOperation::Get { key } => {
+            let config = Config::load().unwrap_or_else(|e| fail(format!("{e:#}")));
+            let value = config.get(key).unwrap_or_else(|e| fail(e));
+            println!("{value}");
+        }
+        ConfigOperation::Set { key, value } => {
+            // Only what is written in the file is saved, not the environment overrides
+            let path = Config::path();
+            let mut config = Config::load_file(&path).unwrap_or_else(|e| fail(format!("{e:#}")));
+
+            config.set(key, value).unwrap_or_else(|e| fail(e));
+            config.validate().unwrap_or_else(|e| fail(format!("{e:#}")));
+            config.save(&path).unwrap_or_else(|e| fail(e));
+
+            let var = Config::env_var(key);
+            if env::var_os(&var).is_some() {
+                eprintln!("{key} is saved, but {var} overrides it for now");
+            }
+        }
+        ConfigOperation::List => {
+            let config = Config::load().unwrap_or_else(|e| fail(format!("{e:#}")));
+
+            println!("# {}", Config::path().display());
+            for key in KEYS {
+                let value = config.get(key).unwrap();
+                let var = Config::env_var(key);
+                if env::var_os(&var).is_some() {
+                    println!("{key} = {value}  # from {var}");
+                } else {
+                    println!("{key} = {value}");
+                }
+            }
+        }
+    }
+}
@@ -1,21 +1,21 @@
+mod commands;
 mod config;
-mod mager;
 mod manga;
 mod source;
+mod update;
 mod utils;
 
-use std::io::stdout;
+use std::process;
 
-use mager::*;
+use clap::{Args, Parser, Subcommand};
 
-use clap::{Parser, Subcommand};
-
-use ratatui::prelude::CrosstermBackend;
-use ratatui::Terminal;
-
-use crossterm::terminal;
-use crossterm::terminal::{EnterAlternateScreen, LeaveAlternateScreen};
-use crossterm::ExecutableCommand;
+use commands::{chapters_handler, download_handler, info_handler, search_handler};
+use config::config_menu_handler;
+use mager_core::config::Config;
+use mager_core::range::ChapterRange;
+use manga::manga_menu_handler;
+use update::update_handler;
+use utils::fail;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -24,8 +24,21 @@ struct Arguments {
     menu: Menu,
 }
 
+/// Options shared by the commands that ask a source
+#[derive(Args)]
+struct Query {
+    /// The installed source to ask
+    #[arg(short, long, value_name = "SOURCE", default_value = "mangadex")]
+    source: String,
+
+    /// Print JSON instead of a table
+    #[arg(long)]
+    json: bool,
+}
+
 #[derive(Subcommand)]
 enum Menu {
+    /// Browse the search results and chapters of a source interactively
     Manga {
         #[command(subcommand)]
         operation: MangaOperation,
@@ -35,7 +48,75 @@ enum Menu {
     },
 
     Source,
-    Config,
+
+    /// Search manga by title
+    Search {
+        keyword: String,
+
+        #[arg(short, long, value_name = "PAGE", default_value = "1")]
+        page: u32,
+
+        /// Language of the manga, instead of the configured one
+        #[arg(short, long, value_name = "LANG")]
+        lang: Option<String>,
+
+        #[command(flatten)]
+        query: Query,
+    },
+
+    /// Show the details of a manga
+    Info {
+        identifier: String,
+
+        #[command(flatten)]
+        query: Query,
+    },
+
+    /// List the chapters of a manga, oldest first
+    Chapters {
+        identifier: String,
+
+        /// Language of the chapters, instead of the configured one
+        #[arg(short, long, value_name = "LANG")]
+        lang: Option<String>,
+
+        /// Only the chapters with these numbers, like 10-20, 5, 12- or -3
+        #[arg(short, long, value_name = "RANGE")]
+        range: Option<ChapterRange>,
+
+        #[command(flatten)]
+        query: Query,
+    },
+
+    /// Download the chapters of a manga, oldest first
+    Download {
+        identifier: String,
+
+        /// Language of the chapters, instead of the configured one
+        #[arg(short, long, value_name = "LANG")]
+        lang: Option<String>,
+
+        /// Only the chapters with these numbers, like 1-5, 5, 12- or -3. Every chapter is
+        /// downloaded otherwise.
+        #[arg(short, long, value_name = "RANGE")]
+        chapters: Option<ChapterRange>,
+
+        #[command(flatten)]
+        query: Query,
+    },
+
+    /// Look for new chapters in the manga of the library
+    Update {
+        /// Download the new chapters
+        #[arg(short, long)]
+        download: bool,
+    },
+
+    /// Show or change the settings shared with mager-tui
+    Config {
+        #[command(subcommand)]
+        operation: ConfigOperation,
+    },
 }
 
 #[derive(Subcommand)]
@@ -46,22 +127,73 @@ enum MangaOperation {
         #[arg(short, long, value_name = "PAGE", default_value = "1")]
         page: u32,
     },
-    Download,
+}
+
+#[derive(Subcommand)]
+enum ConfigOperation {
+    /// Print the value of a setting
+    Get { key: String },
+    /// Change a setting in the config file
+    Set { key: String, value: String },
+    /// Print every setting and where the config file is
+    List,
+}
+
+fn load_config() -> Config {
+    Config::load().unwrap_or_else(|e| {
+        eprintln!("{e:#}");
+        process::exit(1);
+    })
 }
 
 fn main() {
     // Parsing the arguments
     let args = Arguments::parse();
 
-    // Boilerplate
-    stdout().execute(EnterAlternateScreen).unwrap();
-    terminal::enable_raw_mode().unwrap();
-    let backend = CrosstermBackend::new(stdout());
-    let mut terminal = Terminal::new(backend).unwrap();
-
-    let mut mager = Mager::init(args);
-    mager.run(&mut terminal);
+    // Downloads run on this runtime, everything else is synchronous
+    let runtime = tokio::runtime::Runtime::new().unwrap_or_else(|e| fail(e));
+    let _runtime = runtime.enter();
 
-    terminal::disable_raw_mode().unwrap();
-    stdout().execute(LeaveAlternateScreen).unwrap();
+    match args.menu {
+        Menu::Manga { operation, source } => {
+            manga_menu_handler(&load_config(), &source, &operation)
+        }
+        Menu::Search {
+            keyword,
+            page,
+            lang,
+            query,
+        } => search_handler(&load_config(), &query, &keyword, page, lang.as_ref()),
+        Menu::Info { identifier, query } => info_handler(&load_config(), &query, &identifier),
+        Menu::Chapters {
+            identifier,
+            lang,
+            range,
+            query,
+        } => chapters_handler(
+            &load_config(),
+            &query,
+            &identifier,
+            lang.as_ref(),
+            range.unwrap_or_default(),
+        ),
+        Menu::Download {
+            identifier,
+            lang,
+            chapters,
+            query,
+        } => download_handler(
+            &load_config(),
+            &query,
+            &identifier,
+            lang.as_ref(),
+            chapters.unwrap_or_default(),
+        ),
+        Menu::Update { download } => update_handler(&load_config(), download),
+        Menu::Config { operation } => config_menu_handler(&operation),
+        Menu::Source => {
+            eprintln!("This menu is not available yet, use mager-tui instead");
+            process::exit(1);
+        }
+    }
 }
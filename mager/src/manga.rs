@@ -1,20 +1,38 @@
-use std::env;
 use std::io::{stdin, stdout, Write};
-use std::path::PathBuf;
-use std::process;
 
-use crate::utils::{connect_to_source, download_resource, read_from_stream, write_to_stream};
+use crate::source::RunningSource;
+use crate::utils::{connect_to_source, fail, read_from_stream, write_to_stream};
 use dto::carriers::{Command, Request, Response, Status};
-use dto::{Chapter, ChapterList, Filter, Manga, MangaList, Order};
+use dto::{Chapter, ChapterList, ChapterListEntry, Manga, MangaList, MangaListEntry};
+use futures::{stream, StreamExt, TryStreamExt};
+use mager_core::config::Config;
+use mager_core::images::download_image;
+use mager_core::template::{DownloadLayout, PathValues};
+use serde::de::DeserializeOwned;
+use tokio::runtime::Handle;
 
 use crate::MangaOperation;
 use termion::{self, terminal_size};
 
+/// How many pages of a chapter are downloaded at the same time
+const MAX_PAGE_DOWNLOADS: usize = 4;
+
+/// Shortens the title to fit in `width` characters
+fn ellipsize(title: &str, width: usize) -> String {
+    if title.chars().count() < width.saturating_sub(3) {
+        return title.to_string();
+    }
+
+    let shortened: String = title.chars().take(width.saturating_sub(3)).collect();
+    format!("{shortened}...")
+}
+
 fn print_chapter_list(cl: &ChapterList) {
-    let (width, _height) = terminal_size().unwrap();
+    // Output that isn't a terminal gets tables of the usual width
+    let width = terminal_size().map_or(80, |(width, _)| width);
     let border = vec!["="; width as usize].join("");
 
-    let table_w = (width - 17) as f32;
+    let table_w = width.saturating_sub(17).max(40) as f32;
 
     let index_w = 5;
     let chapter_w = (0.1 * table_w).floor() as usize;
@@ -24,11 +42,7 @@ fn print_chapter_list(cl: &ChapterList) {
     println!("{border}");
     let mut i = cl.data.len() as i32 - 1;
     for ch in cl.data.iter().rev() {
-        let ch_title = if ch.title.len() >= title_w - 3 {
-            format!("{}...", &ch.title[..title_w - 3])
-        } else {
-            ch.title.clone()
-        };
+        let ch_title = ellipsize(&ch.title, title_w);
         println!(
             "{:^iw$}    {:<cw$}    {:<tw$}    {:<dw$}",
             i,
@@ -63,24 +77,20 @@ fn print_chapter_list(cl: &ChapterList) {
 }
 
 fn print_mangalist(ml: &MangaList) {
-    let (width, _height) = terminal_size().unwrap();
+    // Output that isn't a terminal gets tables of the usual width
+    let width = terminal_size().map_or(80, |(width, _)| width);
     let border = vec!["="; width as usize].join("");
 
-    let table_w = (width - 17) as f32;
+    let table_w = width.saturating_sub(17).max(40) as f32;
 
     let index_w = 5;
-    let title_w = (0.6 * table_w).floor() as usize;
-    let status_w = (0.1 * table_w).floor() as usize;
-    let authors_w = (0.3 * table_w).floor() as usize;
+    let title_w = (0.8 * table_w).floor() as usize;
+    let status_w = (0.2 * table_w).floor() as usize;
 
     println!("{border}");
     let mut i = ml.data.len() as i32 - 1;
     for mg in ml.data.iter().rev() {
-        let mg_title_ellipsized = if mg.title.len() >= title_w - 3 {
-            format!("{}...", &mg.title[..title_w - 3])
-        } else {
-            mg.title.clone()
-        };
+        let mg_title_ellipsized = ellipsize(&mg.title, title_w);
 
         let mg_status = match mg.status {
             dto::PublicationStatus::Ongoing => "Ongoing",
@@ -90,44 +100,27 @@ fn print_mangalist(ml: &MangaList) {
             dto::PublicationStatus::Unknown => "Unknown",
         };
 
-        let mg_authors: String = mg
-            .authors
-            .iter()
-            .map(|a| format!("{} ({})", a.name, a.details))
-            .collect::<Vec<String>>()
-            .join(", ");
-
-        let mg_authors_ellipsized = if mg_authors.len() >= authors_w - 3 {
-            format!("{}...", &mg_authors[..authors_w - 3])
-        } else {
-            mg_authors
-        };
-
         println!(
-            "{:^iw$}    {:<tw$}    {:<sw$}    {:<aw$}",
+            "{:^iw$}    {:<tw$}    {:<sw$}",
             i,
             mg_title_ellipsized,
             mg_status,
-            mg_authors_ellipsized,
             iw = index_w,
             tw = title_w,
             sw = status_w,
-            aw = authors_w
         );
         i -= 1;
     }
 
     println!("{border}");
     println!(
-        "{:^iw$}    {:<tw$}    {:<sw$}    {:<aw$}",
+        "{:^iw$}    {:<tw$}    {:<sw$}",
         "Index",
         "Title",
         "Status",
-        "Author",
         iw = index_w,
         tw = title_w,
         sw = status_w,
-        aw = authors_w
     );
     println!("{border}");
     println!(
@@ -136,193 +129,202 @@ fn print_mangalist(ml: &MangaList) {
     );
 }
 
-fn download_chapter(port: u16, manga_title: &str, chapter: &Chapter) {
-    let home = env::var("HOME").unwrap();
-    let request = Request {
-        command: Command::Pages {
-            identifier: chapter.identifier.clone(),
-        },
-        version: String::from("0.0.0"),
-    };
-
-    let mut connection = connect_to_source(port).unwrap();
-    write_to_stream(&serde_json::to_string(&request).unwrap(), &mut connection).unwrap();
-
-    let response: Response<ChapterPages> =
-        serde_json::from_slice(&read_from_stream(&mut connection).unwrap()).unwrap();
-
-    let base_folder = format!("{home}/Downloads/mager/{}", response.source_name);
-
-    let rt = tokio::runtime::Builder::new_multi_thread()
-        .enable_all()
-        .build()
-        .unwrap();
+/// Sends the command to the source and waits for its response
+pub(crate) fn try_request<T: DeserializeOwned>(
+    address: &str,
+    command: Command,
+) -> Result<(String, T), String> {
+    let request = Request::new(0, command);
+
+    let mut connection = connect_to_source(address).map_err(|e| e.to_string())?;
+    write_to_stream(&serde_json::to_string(&request).unwrap(), &mut connection)
+        .map_err(|e| e.to_string())?;
+
+    let raw_response = read_from_stream(&mut connection).map_err(|e| e.to_string())?;
+    let response: Response<T> = serde_json::from_slice(&raw_response).map_err(|e| e.to_string())?;
+
+    match (response.status, response.content) {
+        (Status::Ok, Some(content)) => Ok((response.source_name, content)),
+        (Status::Ok, None) => Err(format!("{} sent an empty response", response.source_name)),
+        (Status::Error(code), _) => Err(format!(
+            "{} failed ({code}): {}",
+            response.source_name, response.reason
+        )),
+    }
+}
 
-    let mut handles = Vec::new();
-    let mut counter = 1;
-    for url in response.content {
-        let mut path = PathBuf::from(base_folder.clone());
-        path.push(format!(
-            "{manga_title}/{} - {}/{counter}.png",
-            chapter.number, chapter.title
-        ));
+/// Downloads every page of the chapter and returns its details
+pub(crate) fn download_chapter(
+    config: &Config,
+    address: &str,
+    manga_identifier: &str,
+    chapter: &ChapterListEntry,
+) -> Result<Chapter, String> {
+    let layout = DownloadLayout::from_config(config).map_err(|e| format!("{e:#}"))?;
+
+    let command = Command::FetchChapter {
+        chapter_identifier: chapter.identifier.clone(),
+    };
+    let (source_name, chapter): (String, Chapter) = try_request(address, command)?;
 
-        let handle = rt.spawn(download_resource(url.clone(), path.clone()));
+    let command = Command::FetchManga {
+        manga_identifier: manga_identifier.to_string(),
+    };
+    let (_, manga): (String, Manga) = try_request(address, command)?;
+
+    let values = PathValues::new(&source_name, &manga, &chapter);
+    let owner = format!("{source_name}/{}", chapter.identifier);
+    let folder = layout
+        .chapter_folder(&values, &owner)
+        .map_err(|e| format!("{e:#}"))?;
+
+    let page_count = chapter.page_urls.len();
+    let downloads = chapter.page_urls.iter().enumerate().map(|(i, url)| {
+        let path = folder.join(layout.template.page_name(&values, i + 1, page_count));
+        download_image(url, &config.user_agent, path, |_| {})
+    });
+
+    Handle::current()
+        .block_on(
+            stream::iter(downloads)
+                .buffer_unordered(MAX_PAGE_DOWNLOADS)
+                .try_collect::<Vec<_>>(),
+        )
+        .map_err(|e| format!("{e:#}"))?;
+
+    Ok(chapter)
+}
 
-        handles.push(handle);
-        counter += 1;
-    }
+/// Asks for a line of input. Returns `None` once stdin is closed.
+fn prompt(message: &str) -> Option<String> {
+    print!("{message}");
+    stdout().flush().ok()?;
 
-    for h in handles {
-        rt.block_on(h).unwrap().unwrap();
+    let mut input = String::new();
+    match stdin().read_line(&mut input) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(input.trim().to_string()),
     }
-
-    println!("Download successful")
 }
 
-fn browse_chapters(port: u16, manga: &Manga, mut page: u32) {
+fn browse_chapters(
+    config: &Config,
+    address: &str,
+    manga: &MangaListEntry,
+    mut page: u32,
+) -> Result<(), String> {
     loop {
-        let filter = Filter {
-            language: String::from("en"),
-            sort: Order::Descending,
-        };
+        let filter = config.filter();
 
-        let request = Request {
-            command: Command::FetchChapterList {
-                identifier: manga.identifier.clone(),
-                page,
-                filter,
-            },
-            version: String::from("0.0.0"),
+        let command = Command::FetchChapterList {
+            identifier: manga.identifier.clone(),
+            page,
+            filter,
         };
+        let (_, ch_list): (String, ChapterList) = try_request(address, command)?;
 
-        let mut connection = connect_to_source(port).unwrap();
-        write_to_stream(&serde_json::to_string(&request).unwrap(), &mut connection).unwrap();
-
-        let response: Response<ChapterList> =
-            serde_json::from_slice(&read_from_stream(&mut connection).unwrap()).unwrap();
-
-        let ch_list = &response.content;
-
-        print_chapter_list(ch_list);
+        print_chapter_list(&ch_list);
         println!("Choose chapter index to download or perform an action");
-        print!(
+        let message = format!(
             "Enter a command: [0-{}, n: next, p: prev, b: back]: ",
-            ch_list.data.len() - 1
+            ch_list.data.len().saturating_sub(1)
         );
-        stdout().flush().unwrap();
 
-        let mut input = String::new();
-        stdin().read_line(&mut input).unwrap();
+        loop {
+            let Some(input) = prompt(&message) else {
+                return Ok(());
+            };
+
+            if let Ok(i) = input.parse::<usize>() {
+                let Some(chapter) = ch_list.data.get(i) else {
+                    eprintln!("There is no chapter {i} on this page");
+                    continue;
+                };
+
+                download_chapter(config, address, &manga.identifier, chapter)?;
+                println!("Download successful");
+                return Ok(());
+            }
 
-        let input = input.trim();
-        match input.parse::<u32>() {
-            Ok(i) => {
-                let chapter = ch_list.data.get((i) as usize).unwrap();
-                download_chapter(port, &manga.title, chapter);
-                break;
+            match input.as_str() {
+                "n" if page < ch_list.total_page => page += 1,
+                "p" if page > 1 => page -= 1,
+                "n" | "p" => {
+                    eprintln!("There is no page in that direction");
+                    continue;
+                }
+                "b" => return Ok(()),
+                _ => {
+                    eprintln!("{input} is not a command");
+                    continue;
+                }
             }
-            Err(_) => match input {
-                "n" => page += 1,
-                "p" => page -= 1,
-                "b" => break,
-                _ => panic!("Invalid operation"),
-            },
+            break;
         }
     }
 }
 
-fn browse_manga(port: u16, keyword: &str, mut page: u32) {
+fn browse_manga(
+    config: &Config,
+    address: &str,
+    keyword: &str,
+    mut page: u32,
+) -> Result<(), String> {
     loop {
-        let filter = Filter {
-            language: String::from("en"),
-            sort: Order::Descending,
-        };
+        let filter = config.filter();
 
-        let request = Request {
-            command: Command::Search {
-                keyword: keyword.to_string(),
-                page,
-                filter,
-            },
-            version: String::from("0.0.0"),
+        let command = Command::Search {
+            keyword: keyword.to_string(),
+            page,
+            filter,
         };
-
-        let mut connection = connect_to_source(port).unwrap();
-        write_to_stream(&serde_json::to_string(&request).unwrap(), &mut connection).unwrap();
-
-        let response: Response<MangaList> =
-            serde_json::from_slice(read_from_stream(&mut connection).unwrap().as_ref()).unwrap();
-
-        let mn_list = response.content;
+        let (_, mn_list): (String, MangaList) = try_request(address, command)?;
 
         print_mangalist(&mn_list); // Display search result
         println!("Choose manga index to see or perform an action");
-        print!(
+        let message = format!(
             "Enter a command [0-{}, n: next, p: prev, a: abort]: ",
-            mn_list.data.len() - 1
+            mn_list.data.len().saturating_sub(1)
         );
-        stdout().flush().unwrap();
 
-        let mut input = String::new();
-        stdin().read_line(&mut input).unwrap();
+        loop {
+            let Some(input) = prompt(&message) else {
+                return Ok(());
+            };
+
+            if let Ok(i) = input.parse::<usize>() {
+                let Some(manga) = mn_list.data.get(i) else {
+                    eprintln!("There is no manga {i} on this page");
+                    continue;
+                };
 
-        let input = input.trim();
-        match input.parse::<u32>() {
-            Ok(i) => {
-                let manga = mn_list.data.get((i) as usize).unwrap();
-                browse_chapters(port, manga, 1);
-                break;
+                return browse_chapters(config, address, manga, 1);
             }
-            Err(_) => match input {
-                "n" => page += 1,
-                "p" => page -= 1,
-                "a" => break,
-                _ => break,
-            },
+
+            match input.as_str() {
+                "n" if page < mn_list.total_page => page += 1,
+                "p" if page > 1 => page -= 1,
+                "n" | "p" => {
+                    eprintln!("There is no page in that direction");
+                    continue;
+                }
+                _ => return Ok(()),
+            }
+            break;
         }
     }
 }
 
-pub(crate) fn manga_menu_handler(source: &str, operation: &MangaOperation) {
-    let home = env::var("HOME").unwrap();
-    let port = 7878;
-
-    let path = if cfg!(debug_assertions) {
-        format!("{home}/Projects/mager/target/debug/{source}")
-    } else {
-        format!("{home}/.local/mager/sources/{source}")
-    };
-
-    let mut source_proccess = process::Command::new(path)
-        .arg(&port.to_string())
-        .spawn()
-        .unwrap();
-
-    // This code will attempt to connect to the source 10 times
-    let mut connection = connect_to_source(port).unwrap();
-
-    // Attempt to ping the source to ensure the source is connected
-    let request = Request {
-        command: Command::Ping,
-        version: String::from("0.0.0"),
-    };
-
-    let request = serde_json::to_string(&request).unwrap();
-    write_to_stream(request.trim(), &mut connection).unwrap();
+pub(crate) fn manga_menu_handler(config: &Config, source: &str, operation: &MangaOperation) {
+    let source = RunningSource::start(config, source).unwrap_or_else(|e| fail(e));
 
-    let raw_response = read_from_stream(&mut connection).unwrap();
-    let response: Response<()> = serde_json::from_slice(&raw_response).unwrap();
-
-    if let Status::Error = response.status {
-        panic!("Error connecting to source");
-    }
-
-    match operation {
+    let result = match operation {
         MangaOperation::Search { keyword, page } => {
-            browse_manga(port, keyword, *page);
+            browse_manga(config, source.address(), keyword, *page)
         }
-        MangaOperation::Download => todo!(),
-    }
-    source_proccess.kill().unwrap();
+    };
+
+    // The source is stopped before an error ends the program
+    drop(source);
+    result.unwrap_or_else(|e| fail(e));
 }
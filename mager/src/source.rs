@@ -0,0 +1,103 @@
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command as Process, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use dto::carriers::{self, Command, SourceInfo};
+use mager_core::config::Config;
+
+use crate::manga::try_request;
+
+/// How long a source has to say it is ready
+const READY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An installed source started by the CLI, listening on a port it picked itself. The
+/// source is stopped once this is dropped.
+pub(crate) struct RunningSource {
+    process: Child,
+    address: String,
+}
+
+impl RunningSource {
+    pub(crate) fn start(config: &Config, name: &str) -> Result<Self, String> {
+        let path = config.sources_dir.join(name);
+        let mut process = Process::new(&path)
+            // The source's own output would end up in the middle of ours
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("Can't start {}: {e}", path.display()))?;
+
+        let stdout = process.stdout.take().expect("stdout is piped");
+        // Built first, so the source is stopped if it never gets ready
+        let mut source = Self {
+            process,
+            address: String::new(),
+        };
+        source.address = wait_until_ready(stdout)
+            .ok_or_else(|| format!("{} didn't say it was ready", path.display()))?;
+
+        // Attempt to ping the source to ensure the source is connected and speaks our version
+        let (_, info): (String, SourceInfo) = try_request(&source.address, Command::Ping)?;
+        if info.compatible_version().is_none() {
+            return Err(format!(
+                "{} speaks GMTP {}, but mager needs GMTP {}",
+                info.name,
+                info.versions.join(", "),
+                carriers::VERSION
+            ));
+        }
+
+        Ok(source)
+    }
+
+    /// The address the source listens on
+    pub(crate) fn address(&self) -> &str {
+        &self.address
+    }
+}
+
+/// Reads the source's output until it says where it listens. Whatever it prints after that
+/// is discarded, so the source never blocks on a full pipe.
+fn wait_until_ready(stdout: impl std::io::Read + Send + 'static) -> Option<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut lines = BufReader::new(stdout).lines().map_while(Result::ok);
+        if let Some(address) = lines.by_ref().find_map(|l| ready_address(&l)) {
+            let _ = sender.send(address);
+        }
+        lines.for_each(drop);
+    });
+
+    receiver.recv_timeout(READY_TIMEOUT).ok()
+}
+
+/// The address in a line like `GMTP-READY tcp 127.0.0.1:41234`
+fn ready_address(line: &str) -> Option<String> {
+    let rest = line.trim().strip_prefix(carriers::READY)?.trim_start();
+    rest.strip_prefix("tcp ").map(|a| a.trim().to_string())
+}
+
+impl Drop for RunningSource {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ready_address;
+
+    #[test]
+    fn ready_line() {
+        assert_eq!(
+            ready_address("GMTP-READY tcp 127.0.0.1:41234\n").as_deref(),
+            Some("127.0.0.1:41234")
+        );
+        assert_eq!(ready_address("GMTP-READY unix /tmp/mangadex.sock"), None);
+        assert_eq!(ready_address("Listening on 7878"), None);
+    }
+}
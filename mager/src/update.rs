@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+use std::process;
+
+use dto::carriers::Command;
+use dto::ChapterList;
+use mager_core::config::Config;
+use mager_core::library::{Library, LibraryEntry};
+use mager_core::update::ChapterScan;
+
+use crate::manga::{download_chapter, try_request};
+use crate::source::RunningSource;
+use crate::utils::fail;
+
+/// Looks for new chapters in every manga of the library and prints them. Nothing is
+/// printed when there is nothing new, so it can run from cron. Ends with an error code if
+/// anything failed.
+pub(crate) fn update_handler(config: &Config, download: bool) {
+    let mut library = Library::open(&Library::path()).unwrap_or_else(|e| fail(e));
+    let entries = library.entries().unwrap_or_else(|e| fail(e));
+
+    // Every source is started once, for all of its manga
+    let mut by_source: BTreeMap<String, Vec<LibraryEntry>> = BTreeMap::new();
+    for entry in entries {
+        by_source
+            .entry(entry.source.clone())
+            .or_default()
+            .push(entry);
+    }
+
+    let mut failed = false;
+    for (source, entries) in by_source {
+        let running = match RunningSource::start(config, &source) {
+            Ok(running) => running,
+            Err(e) => {
+                eprintln!("{e}");
+                failed = true;
+                continue;
+            }
+        };
+
+        for entry in entries {
+            if let Err(e) = update_manga(config, running.address(), &mut library, &entry, download)
+            {
+                eprintln!("{} ({source}): {e}", entry.manga.title);
+                failed = true;
+            }
+        }
+        drop(running);
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
+
+fn update_manga(
+    config: &Config,
+    address: &str,
+    library: &mut Library,
+    entry: &LibraryEntry,
+    download: bool,
+) -> Result<(), String> {
+    let (source, manga) = (&entry.source, &entry.manga);
+    let known = library
+        .chapters(source, &manga.identifier)
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .map(|c| c.identifier);
+
+    let mut scan = ChapterScan::new(known, entry.checked);
+    let mut page = Some(1);
+    while let Some(p) = page {
+        let command = Command::FetchChapterList {
+            identifier: manga.identifier.clone(),
+            page: p,
+            filter: ChapterScan::filter(config),
+        };
+        let (_, list): (String, ChapterList) = try_request(address, command)?;
+        page = scan.add_page(list);
+    }
+
+    library
+        .add_chapters(source, &manga.identifier, &scan.chapters)
+        .and_then(|_| library.set_checked(source, &manga.identifier))
+        .map_err(|e| e.to_string())?;
+
+    let new_chapters = scan.new_chapters();
+    if new_chapters.is_empty() {
+        return Ok(());
+    }
+
+    println!(
+        "{} ({source}): {} new chapter(s)",
+        manga.title,
+        new_chapters.len()
+    );
+    // Oldest first, in reading order
+    for chapter in new_chapters.iter().rev() {
+        println!("  #{} - {}", chapter.number, chapter.title);
+
+        if download {
+            let downloaded = download_chapter(config, address, &manga.identifier, chapter)?;
+            library
+                .mark_downloaded(source, &downloaded)
+                .map_err(|e| e.to_string())?;
+        }
+    }
+
+    Ok(())
+}
@@ -1,34 +1,11 @@
 use std::io::{self, Read, Write};
 use std::net::TcpStream;
-use std::path::Path;
-use std::thread;
-use std::time::Duration;
+use std::process;
 
-use tokio::fs::{create_dir_all, File};
-
-use reqwest::{self, ClientBuilder};
-use tokio::io::AsyncWriteExt;
-
-pub(crate) async fn download_resource(
-    url: String,
-    file_name: impl AsRef<Path>,
-) -> Result<(), io::Error> {
-    let client = ClientBuilder::new()
-        .user_agent("Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0")
-        .build()
-        .unwrap();
-
-    let response = client.get(url).send().await.unwrap();
-    let bytes = response.bytes().await.unwrap();
-
-    if let Some(parent) = file_name.as_ref().parent() {
-        create_dir_all(parent).await.unwrap()
-    }
-
-    let mut file = File::create(&file_name).await.unwrap();
-    file.write_all(&bytes).await.unwrap();
-
-    Ok(())
+/// Prints the error and ends the program
+pub(crate) fn fail(error: impl std::fmt::Display) -> ! {
+    eprintln!("{error}");
+    process::exit(1);
 }
 
 pub(crate) fn write_to_stream(request: &str, connection: &mut TcpStream) -> Result<(), io::Error> {
@@ -51,21 +28,6 @@ pub(crate) fn read_from_stream(connection: &mut TcpStream) -> Result<Vec<u8>, io
     Ok(buffer)
 }
 
-pub(crate) fn connect_to_source(port: u16) -> Result<TcpStream, io::Error> {
-    let mut counter = 0;
-    let mut error: io::Error;
-
-    loop {
-        let stream = TcpStream::connect(format!("127.0.0.1:{port}"));
-        match stream {
-            Ok(s) => return Ok(s),
-            Err(e) => error = e,
-        }
-
-        if counter > 20 {
-            return Err(error);
-        }
-        counter += 1;
-        thread::sleep(Duration::from_millis(50));
-    }
+pub(crate) fn connect_to_source(address: &str) -> Result<TcpStream, io::Error> {
+    TcpStream::connect(address)
 }
@@ -1,166 +1,240 @@
 use color_eyre::eyre::{eyre, Report};
 use color_eyre::Result;
 use futures::future;
-use tokio::task::JoinHandle;
+use tokio::sync::{OnceCell, Semaphore};
+use tokio::task;
 
-use std::env;
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use dto::carriers::{self, Request, Response, Status};
+use dto::carriers::ErrorCode;
 use dto::*;
+use mager_source_sdk::{Source, SourceError};
 
-use mangadex::enums::RelationshipType;
-use mangadex::query::{chapter::ChapterQuery, manga::SearchQuery};
+use mangadex::enums::{RelationshipType, TagGroup as MDTagGroup};
+use mangadex::query::chapter::ChapterQuery;
+use mangadex::query::manga::{self, SearchQuery, SortingOrder};
 use mangadex::schema::{self, Manga as MDManga};
 use mangadex::Mangadex;
 
 mod mangadex;
 
+const USER_AGENT: &str = "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0";
+
 #[tokio::main]
 pub async fn main() -> Result<()> {
     color_eyre::install()?;
-    let args: Vec<String> = env::args().collect();
-    let port = args.get(1).unwrap();
+    // `--max-in-flight` caps the calls to the MangaDex API, whatever the number of requests
+    mager_source_sdk::serve_with(|options| MangaDexSource::new(USER_AGENT, options.max_in_flight))
+        .await
+}
 
-    let listener = TcpListener::bind(format!("127.0.0.1:{port}"))?;
+struct MangaDexSource {
+    client: Arc<Mangadex>,
+    limiter: Semaphore,
+    /// Every tag of MangaDex. They are only fetched once, the first time they are needed.
+    tags: OnceCell<Vec<schema::Tag>>,
+}
 
-    for stream in listener.incoming() {
-        let mut stream = stream?;
+impl MangaDexSource {
+    fn new(user_agent: &str, max_api_calls: usize) -> Self {
+        Self {
+            client: Arc::new(Mangadex::new(user_agent)),
+            limiter: Semaphore::new(max_api_calls),
+            tags: OnceCell::new(),
+        }
+    }
 
-        let mut length: [u8; 4] = [0; 4];
-        stream.read_exact(&mut length)?;
+    async fn all_tags(&self) -> Result<&Vec<schema::Tag>> {
+        self.tags
+            .get_or_try_init(|| async {
+                let tags = self.call(|client| client.tags()).await?;
+                Ok::<_, Report>(tags.data)
+            })
+            .await
+    }
 
-        let mut request = vec![0; u32::from_ne_bytes(length) as usize];
-        stream.read_exact(&mut request)?;
+    /// Looks up the identifiers of tags named in a filter
+    async fn tag_ids(&self, names: &[String]) -> Result<Vec<String>> {
+        if names.is_empty() {
+            return Ok(Vec::new());
+        }
 
-        let request: Request = serde_json::from_slice(&request)?;
-        handle_request(request, stream).await?;
+        let tags = self.all_tags().await?;
+        names
+            .iter()
+            .map(|name| {
+                tags.iter()
+                    .find(|t| {
+                        t.attributes
+                            .name
+                            .get("en")
+                            .is_some_and(|en| en.eq_ignore_ascii_case(name))
+                    })
+                    .map(|t| t.id.clone())
+                    .ok_or_else(|| {
+                        SourceError::new(ErrorCode::BadRequest, format!("There is no {name} tag"))
+                            .into()
+                    })
+            })
+            .collect()
     }
-    Ok(())
-}
 
-async fn handle_request(request: Request, mut stream: TcpStream) -> Result<()> {
-    let client_name = String::from("MangaDex");
-    let user_agent = "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0";
-    let response = match request.command {
-        carriers::Command::Ping => {
-            let content = Response {
-                status: Status::Ok,
-                reason: String::from("Pong, this source is active"),
-                source_name: client_name.clone(),
-                content: Some(()),
-            };
-            serde_json::to_string(&content)?
-        }
-        carriers::Command::Search {
-            keyword,
-            page,
-            filter,
-        } => {
-            let content = search(&keyword, page, filter, user_agent);
-            serde_json::to_string(&content)?
-        }
-        carriers::Command::FetchManga { manga_identifier } => {
-            let content = fetch_manga(&manga_identifier, user_agent).await;
-            serde_json::to_string(&content)?
+    /// Runs a manga search narrowed down with the filter. The order of the filter is
+    /// replaced by `order`, if any.
+    async fn search_manga(
+        &self,
+        query: SearchQuery,
+        page: u32,
+        filter: Filter,
+        order: Option<SortingOrder>,
+    ) -> Result<MangaList> {
+        let limit = 20;
+        let offset = page_offset(page, limit)?;
+        let included_tags = self.tag_ids(&filter.included_tags).await?;
+        let excluded_tags = self.tag_ids(&filter.excluded_tags).await?;
+        let mut query = query.set_limit(limit).set_offset(offset).set_filter(
+            &filter,
+            included_tags,
+            excluded_tags,
+        );
+        if let Some(order) = order {
+            query = query.set_order(order);
         }
-        carriers::Command::FetchChapterList {
-            identifier,
+
+        let mglist_cont = self.call(move |client| client.search(&query)).await?;
+
+        let total_page = mglist_cont.total.div_ceil(limit);
+        let data = mglist_cont
+            .data
+            .into_iter()
+            .map(|mg| {
+                let title = extract_title("en", &mg).unwrap_or(String::from("Unknown Title"));
+                let identifier = mg.id;
+                let attr = mg.attributes;
+                let status = attr.status.to_dto();
+
+                MangaListEntry {
+                    identifier,
+                    title,
+                    status,
+                }
+            })
+            .collect::<Vec<MangaListEntry>>();
+
+        Ok(MangaList {
             page,
-            filter,
-        } => {
-            let content = fetch_chapter_list(&identifier, page, filter, user_agent);
-            serde_json::to_string(&content)?
-        }
-        carriers::Command::FetchChapter { chapter_identifier } => {
-            let content = fetch_chapter(&chapter_identifier, user_agent);
-            serde_json::to_string(&content)?
-        }
-    };
+            total_page,
+            data,
+        })
+    }
 
-    write_to_stream(&mut stream, &response)
+    /// Waits for a free slot, then runs the call. The MangaDex client is blocking, so every
+    /// call to the API is run on tokio's blocking thread pool.
+    async fn call<T, F>(&self, call: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Mangadex) -> Result<T> + Send + 'static,
+    {
+        let _permit = self.limiter.acquire().await?;
+        let client = self.client.clone();
+
+        task::spawn_blocking(move || call(&client))
+            .await?
+            .map_err(explain_error)
+    }
 }
 
-fn search(keyword: &str, page: u32, filter: Filter, user_agent: &str) -> Response<MangaList> {
-    let client = Arc::new(Mangadex::new(user_agent));
-    let source_name = "MangaDex".to_string();
-    let limit = 20;
-    let query = &SearchQuery::new(keyword)
-        .set_limit(limit)
-        .set_offset((page - 1) * limit)
-        .set_order(filter.sort.clone().into());
-
-    let mglist_cont = match client.search(query) {
-        Ok(mglist_cont) => mglist_cont,
-        Err(report) => return create_error_response(report, &source_name),
-    };
+impl Source for MangaDexSource {
+    fn name(&self) -> &str {
+        "MangaDex"
+    }
 
-    let total_page = (mglist_cont.total + limit - 1) / limit;
-    let data = mglist_cont
-        .data
-        .into_iter()
-        .map(|mg| {
-            let title = extract_title("en", &mg).unwrap_or(String::from("Unknown Title"));
-            let identifier = mg.id;
-            let attr = mg.attributes;
-            let status = attr.status.to_dto();
-
-            MangaListEntry {
-                identifier,
-                title,
-                status,
-            }
-        })
-        .collect::<Vec<MangaListEntry>>();
+    fn filters(&self) -> Vec<String> {
+        [
+            "language",
+            "sort",
+            "included_tags",
+            "tag_mode",
+            "excluded_tags",
+            "statuses",
+            "content_ratings",
+            "demographics",
+            "year",
+            "original_languages",
+            "authors",
+            "fields",
+        ]
+        .map(String::from)
+        .to_vec()
+    }
 
-    Response {
-        status: Status::Ok,
-        reason: "All good".to_string(),
-        source_name,
-        content: Some(MangaList {
-            page,
-            total_page,
-            data,
-        }),
+    fn filter_fields(&self) -> Vec<FilterField> {
+        let option = |id: &str, label: &str| FieldOption {
+            id: id.to_string(),
+            label: label.to_string(),
+        };
+
+        vec![
+            FilterField {
+                id: manga::AVAILABLE_CHAPTERS.to_string(),
+                label: String::from("Chapters"),
+                kind: FieldKind::Select {
+                    options: vec![option("with", "In the language"), option("any", "Any")],
+                    default: Some(String::from("with")),
+                },
+            },
+            FilterField {
+                id: manga::EXCLUDED_ORIGINAL_LANGUAGES.to_string(),
+                label: String::from("Excluded original language"),
+                kind: FieldKind::Text,
+            },
+            FilterField {
+                id: manga::AUTHOR_OR_ARTIST.to_string(),
+                label: String::from("Author or artist id"),
+                kind: FieldKind::Text,
+            },
+        ]
     }
-}
 
-/// This function will fetch manga details for a specified manga id
-async fn fetch_manga(id: &str, user_agent: &str) -> Response<Manga> {
-    let client = Arc::new(Mangadex::new(user_agent));
-    let source_name = "MangaDex".to_string();
+    fn sort_keys(&self) -> Vec<SortKey> {
+        SortKey::ALL.to_vec()
+    }
 
-    let manga = match client.manga(id) {
-        Ok(mg_cont) => mg_cont.data,
-        Err(report) => return create_error_response(report, &source_name),
-    };
+    fn browse_modes(&self) -> Vec<BrowseMode> {
+        BrowseMode::ALL.to_vec()
+    }
 
-    let authors = match extract_author(client.clone(), &manga).await {
-        Ok(authors) => authors,
-        Err(report) => return create_error_response(report, &source_name),
-    };
+    async fn search(&self, keyword: String, page: u32, filter: Filter) -> Result<MangaList> {
+        self.search_manga(SearchQuery::new(&keyword), page, filter, None)
+            .await
+    }
+
+    async fn browse(&self, mode: BrowseMode, page: u32, filter: Filter) -> Result<MangaList> {
+        self.search_manga(SearchQuery::default(), page, filter, Some(mode.into()))
+            .await
+    }
 
-    let title = extract_title("en", &manga).unwrap_or("Unknown Title".to_string());
-    let identifier = manga.id;
-    let attr = manga.attributes;
-    let description = attr
-        .description
-        .as_ref()
-        .and_then(|desc| desc.get("en"))
-        .cloned()
-        .unwrap_or(String::from("No description"));
-    let original_language = attr.original_language;
-    let status = attr.status.to_dto();
-    // For now, it only supports english language
-    let language = String::from("en");
-
-    Response {
-        status: Status::Ok,
-        reason: "All good!".to_string(),
-        source_name: source_name.clone(),
-        content: Some(Manga {
+    /// This function will fetch manga details for a specified manga id
+    async fn manga(&self, id: String) -> Result<Manga> {
+        let manga = self.call(move |client| client.manga(&id)).await?.data;
+        let authors = extract_author(self, &manga).await?;
+
+        let title = extract_title("en", &manga).unwrap_or("Unknown Title".to_string());
+        let identifier = manga.id;
+        let attr = manga.attributes;
+        let description = attr
+            .description
+            .as_ref()
+            .and_then(|desc| desc.get("en"))
+            .cloned()
+            .unwrap_or(String::from("No description"));
+        let original_language = attr.original_language;
+        let status = attr.status.to_dto();
+        // For now, it only supports english language
+        let language = String::from("en");
+
+        Ok(Manga {
             identifier,
             title,
             authors,
@@ -168,130 +242,178 @@ async fn fetch_manga(id: &str, user_agent: &str) -> Response<Manga> {
             language,
             description,
             status,
-        }),
-    }
-}
-
-fn fetch_chapter_list(
-    id: &str,
-    page: u32,
-    filter: Filter,
-    user_agent: &str,
-) -> Response<ChapterList> {
-    let client = Arc::new(Mangadex::new(user_agent));
-    let client_name = String::from("MangaDex");
-    let limit = 40;
-    let offset = (page - 1) * 50;
-    let query = ChapterQuery::new(limit, offset).set_order(filter.sort.into());
-
-    let chlist_cont = match client.chapters(id, &query) {
-        Err(report) => return create_error_response(report, &client_name),
-        Ok(chlist_cont) => chlist_cont,
-    };
-
-    let total_page = (chlist_cont.total + limit - 1) / limit;
-    let data = chlist_cont
-        .data
-        .into_iter()
-        .map(|ch| {
-            let identifier = ch.id;
-            let title = ch.attributes.title.unwrap_or("No title".to_string());
-            let number = ch.attributes.chapter.unwrap_or("No number".to_string());
-
-            ChapterListEntry {
-                identifier,
-                title,
-                number,
-            }
         })
-        .collect();
+    }
 
-    Response {
-        status: Status::Ok,
-        reason: String::from("All good"),
-        source_name: client_name,
-        content: Some(ChapterList {
+    async fn chapter_list(&self, id: String, page: u32, filter: Filter) -> Result<ChapterList> {
+        let limit = 40;
+        let offset = page_offset(page, limit)?;
+        let query = ChapterQuery::new(limit, offset)
+            .set_order(filter.sort.order.into())
+            .set_language(&filter.language);
+
+        let chlist_cont = self
+            .call(move |client| client.chapters(&id, &query))
+            .await?;
+
+        let total_page = chlist_cont.total.div_ceil(limit);
+        let data = chlist_cont
+            .data
+            .into_iter()
+            .map(|ch| {
+                let group = extract_group(&ch);
+                let identifier = ch.id;
+                let title = ch.attributes.title.unwrap_or("No title".to_string());
+                let number = ch.attributes.chapter.unwrap_or("No number".to_string());
+                let volume = ch.attributes.volume;
+
+                ChapterListEntry {
+                    identifier,
+                    title,
+                    number,
+                    volume,
+                    group,
+                }
+            })
+            .collect();
+
+        Ok(ChapterList {
             page,
             total_page,
             data,
-        }),
+        })
     }
-}
-
-fn fetch_chapter(id: &str, user_agent: &str) -> Response<Chapter> {
-    let client = Arc::new(Mangadex::new(user_agent));
-    let source_name = "MangaDex".to_string();
-
-    let ch_container = match client.chapter(id) {
-        Ok(ch_cont) => ch_cont,
-        Err(report) => return create_error_response(report, &source_name),
-    };
 
-    let page_urls = match get_chapter_pages(id, user_agent) {
-        Ok(pages) => pages,
-        Err(report) => return create_error_response(report, &source_name),
-    };
-
-    let chapter = ch_container.data;
-    let identifier = chapter.id;
-    let attr = &chapter.attributes;
-
-    // This code will find the chapter's origin manga
-    let mut manga_identifier = String::from("");
-    for rel in chapter.relationships.unwrap() {
-        if let RelationshipType::Manga = rel.rel_type {
-            manga_identifier = rel.id.clone();
+    async fn chapter(&self, id: String) -> Result<Chapter> {
+        let ch_id = id.clone();
+        let ch_container = self.call(move |client| client.chapter(&ch_id)).await?;
+        let page_urls = self
+            .call(move |client| get_chapter_pages(client, &id))
+            .await?;
+
+        let chapter = ch_container.data;
+        let group = extract_group(&chapter);
+        let identifier = chapter.id;
+        let attr = &chapter.attributes;
+
+        // This code will find the chapter's origin manga
+        let mut manga_identifier = String::from("");
+        for rel in chapter.relationships.unwrap_or_default() {
+            if let RelationshipType::Manga = rel.rel_type {
+                manga_identifier = rel.id.clone();
+            }
         }
-    }
 
-    let title = attr.title.clone().unwrap_or(String::from("No Title"));
-    let number = attr.chapter.clone().unwrap_or(String::from("No Number"));
-    let language = attr.translated_language.clone();
+        let title = attr.title.clone().unwrap_or(String::from("No Title"));
+        let number = attr.chapter.clone().unwrap_or(String::from("No Number"));
+        let volume = attr.volume.clone();
+        let language = attr.translated_language.clone();
 
-    Response {
-        status: Status::Ok,
-        reason: "All good".to_string(),
-        source_name,
-        content: Some(Chapter {
+        Ok(Chapter {
             identifier,
             manga_identifier,
             title,
             number,
+            volume,
+            group,
             language,
             page_urls,
-        }),
+        })
+    }
+
+    async fn tags(&self) -> Result<Vec<dto::TagGroup>> {
+        let tags = self.all_tags().await?;
+
+        let groups = MDTagGroup::ALL
+            .into_iter()
+            .map(|group| {
+                let mut tags: Vec<Tag> = tags
+                    .iter()
+                    .filter(|t| t.attributes.group == group)
+                    .filter_map(|t| {
+                        Some(Tag {
+                            name: t.attributes.name.get("en")?.clone(),
+                            localized_names: t.attributes.name.clone(),
+                        })
+                    })
+                    .collect();
+                tags.sort_by(|a, b| a.name.cmp(&b.name));
+
+                dto::TagGroup {
+                    name: group.name().to_string(),
+                    tags,
+                }
+            })
+            .collect();
+
+        Ok(groups)
     }
 }
 
-fn create_error_response<T>(report: Report, source_name: &str) -> Response<T> {
-    let err_msg = report
-        .downcast::<ureq::Error>()
-        .ok()
-        .and_then(|err| match err {
-            ureq::Error::Status(code, response) => response
+/// How many results come before the page. Pages start at 1.
+fn page_offset(page: u32, limit: u32) -> Result<u32> {
+    let Some(before) = page.checked_sub(1) else {
+        return Err(SourceError::new(ErrorCode::BadRequest, "Pages start at 1").into());
+    };
+    before.checked_mul(limit).ok_or_else(|| {
+        SourceError::new(ErrorCode::BadRequest, format!("Page {page} is too far")).into()
+    })
+}
+
+/// Turns errors from the MangaDex API into something readable for the client, with the
+/// matching GMTP error code
+fn explain_error(report: Report) -> Report {
+    let report = match report.downcast::<ureq::Error>() {
+        Ok(err) => err,
+        Err(report) => return report,
+    };
+
+    let error = match report {
+        ureq::Error::Status(code, response) => {
+            let error_code = match code {
+                400 => ErrorCode::BadRequest,
+                404 => ErrorCode::NotFound,
+                429 => ErrorCode::RateLimited(retry_after(&response)),
+                _ => ErrorCode::Upstream(code),
+            };
+
+            let err_msg = response
                 .into_json::<schema::ErrorResponse>()
-                .map(|resp| {
+                .ok()
+                .and_then(|resp| {
                     resp.errors
                         .first()
                         .map(|e| format!("{}: {}", code, e.title))
-                })
-                .unwrap_or(Some("Unknown error".to_string())),
+                });
 
-            ureq::Error::Transport(transport) => transport.message().map(|m| m.to_string()),
-        })
-        .unwrap_or("Unknown error".to_string());
+            SourceError::new(
+                error_code,
+                err_msg.unwrap_or(format!("{code}: Unknown error")),
+            )
+        }
+        ureq::Error::Transport(transport) => SourceError::new(
+            ErrorCode::Network,
+            transport.message().unwrap_or("Unknown error"),
+        ),
+    };
 
-    Response {
-        status: Status::Error,
-        reason: err_msg,
-        source_name: source_name.to_string(),
-        content: None,
+    error.into()
+}
+
+/// Reads how many seconds MangaDex wants us to wait before trying again
+fn retry_after(response: &ureq::Response) -> Option<u64> {
+    if let Some(secs) = response.header("Retry-After") {
+        return secs.parse().ok();
     }
+
+    // This one is a unix timestamp instead of a duration
+    let retry_at: u64 = response.header("X-RateLimit-Retry-After")?.parse().ok()?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    Some(retry_at.saturating_sub(now))
 }
 
-fn get_chapter_pages(id: &str, user_agent: &str) -> Result<Vec<String>> {
-    let client = Arc::new(Mangadex::new(user_agent));
-    let result = client.page_hash(id).unwrap();
+fn get_chapter_pages(client: &Mangadex, id: &str) -> Result<Vec<String>> {
+    let result = client.page_hash(id)?;
 
     let mut urls = Vec::new();
     for h in result.chapter.data {
@@ -317,9 +439,20 @@ fn extract_title(preferred_lang: &str, manga: &MDManga) -> Option<String> {
     })
 }
 
+/// Finds the name of the scanlation group that translated the chapter, if it was included
+/// in the response
+fn extract_group(chapter: &schema::Chapter) -> Option<String> {
+    chapter
+        .relationships
+        .as_ref()?
+        .iter()
+        .filter(|rel| matches!(rel.rel_type, RelationshipType::ScanlationGroup))
+        .find_map(|rel| rel.attributes.as_ref()?.name.clone())
+}
+
 /// This function will extract the author and artist from the manga's relation list
-async fn extract_author(client: Arc<Mangadex>, md_manga: &MDManga) -> Result<Vec<Author>> {
-    let mut handles = Vec::new();
+async fn extract_author(source: &MangaDexSource, md_manga: &MDManga) -> Result<Vec<Author>> {
+    let mut lookups = Vec::new();
 
     let Some(relationships) = &md_manga.relationships else {
         return Err(eyre!("There's no relationships".to_string()));
@@ -334,10 +467,9 @@ async fn extract_author(client: Arc<Mangadex>, md_manga: &MDManga) -> Result<Vec
                 _ => unreachable!(),
             };
 
-            let cl = client.clone();
             let rl = rel.clone();
-            let author: JoinHandle<Result<Author>> = tokio::spawn(async move {
-                let name: String = cl
+            let author = source.call(move |client| {
+                let name: String = client
                     .author(&rl.id)?
                     .attributes
                     .name
@@ -350,24 +482,56 @@ async fn extract_author(client: Arc<Mangadex>, md_manga: &MDManga) -> Result<Vec
                     details,
                 })
             });
-            handles.push(author);
+            lookups.push(author);
         }
     }
 
     let mut authors: Vec<Author> = Vec::new();
 
-    for handle in future::join_all(handles).await {
-        authors.push(handle??);
+    for author in future::join_all(lookups).await {
+        authors.push(author?);
     }
 
     Ok(authors)
 }
 
-fn write_to_stream(stream: &mut TcpStream, payload: &str) -> Result<()> {
-    let size = payload.len() as u32;
-    stream.write_all(&size.to_ne_bytes())?;
-    stream.write_all(payload.as_bytes())?;
-    stream.flush()?;
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn pages_follow_each_other() {
+        assert_eq!(page_offset(1, 40).unwrap(), 0);
+        assert_eq!(page_offset(2, 40).unwrap(), 40);
+        assert_eq!(page_offset(3, 20).unwrap(), 40);
+        assert!(page_offset(0, 40).is_err());
+        assert!(page_offset(u32::MAX, 40).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn api_calls_over_the_limit_wait() {
+        let source = MangaDexSource::new(USER_AGENT, 2);
+        let running = Arc::new(AtomicUsize::new(0));
+        let most_running = Arc::new(AtomicUsize::new(0));
+
+        let calls = (0..6).map(|_| {
+            let (running, most_running) = (running.clone(), most_running.clone());
+            source.call(move |_| {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                most_running.fetch_max(now, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(50));
+                running.fetch_sub(1, Ordering::SeqCst);
+                Ok(())
+            })
+        });
+        for result in future::join_all(calls).await {
+            result.unwrap();
+        }
 
-    Ok(())
+        assert_eq!(most_running.load(Ordering::SeqCst), 2);
+    }
 }
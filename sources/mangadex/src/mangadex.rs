@@ -45,6 +45,11 @@ impl Mangadex {
         self.get::<schema::MangaListContainer>(&url)
     }
 
+    /// Function for fetching every tag manga can be searched by
+    pub(crate) fn tags(&self) -> Result<schema::TagListContainer> {
+        self.get::<schema::TagListContainer>(&format!("{}/manga/tag", self.base_url))
+    }
+
     /// Function for fetching a manga's chapter list from MangaDex API
     pub(crate) fn chapters(
         &self,
@@ -64,7 +69,10 @@ impl Mangadex {
 
     /// Function for fetching a chapter details
     pub(crate) fn chapter(&self, id: &str) -> Result<schema::ChapterContainer> {
-        self.get::<schema::ChapterContainer>(&format!("{}/chapter/{}", self.base_url, id))
+        self.get::<schema::ChapterContainer>(&format!(
+            "{}/chapter/{}?includes[]=scanlation_group",
+            self.base_url, id
+        ))
     }
 
     /// Function for fetching a chapter's page hash from MangaDex API
@@ -11,7 +11,19 @@ pub enum Demographic {
     Null,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl From<dto::Demographic> for Demographic {
+    fn from(value: dto::Demographic) -> Self {
+        match value {
+            dto::Demographic::Shounen => Demographic::Shounen,
+            dto::Demographic::Shoujo => Demographic::Shoujo,
+            dto::Demographic::Seinen => Demographic::Seinen,
+            dto::Demographic::Josei => Demographic::Josei,
+            dto::Demographic::None => Demographic::None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 #[serde(rename_all = "lowercase")]
 pub enum PublicationStatus {
     Ongoing,
@@ -29,6 +41,17 @@ impl PublicationStatus {
             PublicationStatus::Cancelled => dto::PublicationStatus::Cancelled,
         }
     }
+
+    /// MangaDex doesn't have manga of unknown status
+    pub fn from_dto(status: dto::PublicationStatus) -> Option<Self> {
+        match status {
+            dto::PublicationStatus::Ongoing => Some(PublicationStatus::Ongoing),
+            dto::PublicationStatus::Completed => Some(PublicationStatus::Completed),
+            dto::PublicationStatus::Hiatus => Some(PublicationStatus::Hiatus),
+            dto::PublicationStatus::Cancelled => Some(PublicationStatus::Cancelled),
+            dto::PublicationStatus::Unknown => None,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -40,6 +63,17 @@ pub enum ContentRating {
     Pornographic,
 }
 
+impl From<dto::ContentRating> for ContentRating {
+    fn from(value: dto::ContentRating) -> Self {
+        match value {
+            dto::ContentRating::Safe => ContentRating::Safe,
+            dto::ContentRating::Suggestive => ContentRating::Suggestive,
+            dto::ContentRating::Erotica => ContentRating::Erotica,
+            dto::ContentRating::Pornographic => ContentRating::Pornographic,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum RelationshipType {
@@ -85,7 +119,7 @@ pub enum MangaRelationshipType {
     Serialization,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum TagGroup {
     Content,
@@ -93,3 +127,17 @@ pub enum TagGroup {
     Genre,
     Theme,
 }
+
+impl TagGroup {
+    /// The groups in the order MangaDex shows them
+    pub const ALL: [Self; 4] = [Self::Genre, Self::Theme, Self::Format, Self::Content];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TagGroup::Content => "Content",
+            TagGroup::Format => "Format",
+            TagGroup::Genre => "Genre",
+            TagGroup::Theme => "Theme",
+        }
+    }
+}
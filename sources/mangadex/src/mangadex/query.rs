@@ -12,6 +12,15 @@ pub enum Order {
     Descending,
 }
 
+impl From<dto::Order> for Order {
+    fn from(value: dto::Order) -> Self {
+        match value {
+            dto::Order::Ascending => Order::Ascending,
+            dto::Order::Descending => Order::Descending,
+        }
+    }
+}
+
 #[derive(Debug, Serialize)]
 #[serde(rename_all(serialize = "UPPERCASE"))]
 pub enum LogicMode {
@@ -22,52 +31,89 @@ pub enum LogicMode {
 pub mod manga {
     use super::*;
 
-    #[derive(Debug, Serialize)]
+    // The ids of the filter fields only MangaDex has
+    pub const AVAILABLE_CHAPTERS: &str = "available_chapters";
+    pub const EXCLUDED_ORIGINAL_LANGUAGES: &str = "excluded_original_languages";
+    pub const AUTHOR_OR_ARTIST: &str = "author_or_artist";
+
+    // Fields that are left out aren't sorted by
+    #[derive(Debug, Serialize, Default)]
     #[serde(rename_all(serialize = "camelCase"))]
     pub struct SortingOrder {
-        title: Order,
-        year: Order,
-        created_at: Order,
-        updated_at: Order,
-        latest_uploaded_chapter: Order,
-        followed_count: Order,
-        relevance: Order,
-        rating: Order,
+        title: Option<Order>,
+        year: Option<Order>,
+        created_at: Option<Order>,
+        updated_at: Option<Order>,
+        latest_uploaded_chapter: Option<Order>,
+        followed_count: Option<Order>,
+        relevance: Option<Order>,
+        rating: Option<Order>,
     }
 
-    impl SortingOrder {
-        pub fn ascending() -> Self {
-            SortingOrder {
-                title: Order::Ascending,
-                year: Order::Ascending,
-                created_at: Order::Ascending,
-                updated_at: Order::Ascending,
-                latest_uploaded_chapter: Order::Ascending,
-                followed_count: Order::Ascending,
-                relevance: Order::Ascending,
-                rating: Order::Ascending,
-            }
-        }
-
-        pub fn descending() -> Self {
-            SortingOrder {
-                title: Order::Descending,
-                year: Order::Descending,
-                created_at: Order::Descending,
-                updated_at: Order::Descending,
-                latest_uploaded_chapter: Order::Descending,
-                followed_count: Order::Descending,
-                relevance: Order::Descending,
-                rating: Order::Descending,
+    impl From<dto::Sort> for SortingOrder {
+        fn from(value: dto::Sort) -> Self {
+            let order = Some(value.order.into());
+            let Some(key) = value.key else {
+                return Self::default();
+            };
+
+            match key {
+                dto::SortKey::Relevance => Self {
+                    relevance: order,
+                    ..Default::default()
+                },
+                dto::SortKey::Title => Self {
+                    title: order,
+                    ..Default::default()
+                },
+                dto::SortKey::Year => Self {
+                    year: order,
+                    ..Default::default()
+                },
+                dto::SortKey::CreatedAt => Self {
+                    created_at: order,
+                    ..Default::default()
+                },
+                dto::SortKey::UpdatedAt => Self {
+                    updated_at: order,
+                    ..Default::default()
+                },
+                dto::SortKey::LatestChapter => Self {
+                    latest_uploaded_chapter: order,
+                    ..Default::default()
+                },
+                dto::SortKey::Follows => Self {
+                    followed_count: order,
+                    ..Default::default()
+                },
+                dto::SortKey::Rating => Self {
+                    rating: order,
+                    ..Default::default()
+                },
             }
         }
     }
 
-    impl From<dto::Order> for SortingOrder {
-        fn from(value: dto::Order) -> Self {
+    impl From<dto::BrowseMode> for SortingOrder {
+        fn from(value: dto::BrowseMode) -> Self {
+            let order = Some(Order::Descending);
             match value {
-                dto::Order::Ascending => Self::ascending(),
-                dto::Order::Descending => Self::descending(),
+                dto::BrowseMode::Latest => Self {
+                    latest_uploaded_chapter: order,
+                    ..Default::default()
+                },
+                dto::BrowseMode::Popular => Self {
+                    followed_count: order,
+                    ..Default::default()
+                },
+                dto::BrowseMode::TopRated => Self {
+                    rating: order,
+                    ..Default::default()
+                },
+                dto::BrowseMode::RecentlyAdded => Self {
+                    created_at: order,
+                    ..Default::default()
+                },
             }
         }
     }
@@ -124,6 +170,79 @@ pub mod manga {
             self.limit = limit;
             self
         }
+
+        /// Narrows the search down with the filter. Tags are named in filters, so their
+        /// identifiers have to be looked up beforehand.
+        pub fn set_filter(
+            mut self,
+            filter: &dto::Filter,
+            included_tags: Vec<Uuid>,
+            excluded_tags: Vec<Uuid>,
+        ) -> Self {
+            self.order = filter.sort.into();
+            self.available_translated_language = Some(vec![filter.language.clone()]);
+
+            if !included_tags.is_empty() {
+                self.included_tags = Some(included_tags);
+            }
+            self.included_tags_mode = match filter.tag_mode {
+                dto::TagMode::All => LogicMode::And,
+                dto::TagMode::Any => LogicMode::Or,
+            };
+            if !excluded_tags.is_empty() {
+                self.excluded_tags = Some(excluded_tags);
+            }
+
+            // Empty lists keep the defaults, MangaDex would search everything otherwise
+            let statuses: Vec<PublicationStatus> = filter
+                .statuses
+                .iter()
+                .filter_map(|s| PublicationStatus::from_dto(*s))
+                .collect();
+            if !statuses.is_empty() {
+                self.status = statuses;
+            }
+            if !filter.content_ratings.is_empty() {
+                self.content_rating = filter.content_ratings.iter().map(|&r| r.into()).collect();
+            }
+            if !filter.demographics.is_empty() {
+                self.publication_demographic =
+                    filter.demographics.iter().map(|&d| d.into()).collect();
+            }
+
+            self.year = filter.year;
+            if !filter.original_languages.is_empty() {
+                self.original_language = Some(filter.original_languages.clone());
+            }
+            if !filter.authors.is_empty() {
+                self.authors = Some(filter.authors.clone());
+            }
+
+            use dto::FieldValue;
+            if let Some(FieldValue::Select(option)) = filter.fields.get(AVAILABLE_CHAPTERS) {
+                self.has_available_chapters = option != "any";
+            }
+            if let Some(FieldValue::Text(languages)) =
+                filter.fields.get(EXCLUDED_ORIGINAL_LANGUAGES)
+            {
+                let languages: Vec<String> = languages
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|l| !l.is_empty())
+                    .map(String::from)
+                    .collect();
+                if !languages.is_empty() {
+                    self.excluded_original_language = Some(languages);
+                }
+            }
+            if let Some(FieldValue::Text(id)) = filter.fields.get(AUTHOR_OR_ARTIST) {
+                if !id.trim().is_empty() {
+                    self.author_or_artist = Some(id.trim().to_string());
+                }
+            }
+
+            self
+        }
     }
 
     impl Default for SearchQuery {
@@ -164,7 +283,8 @@ pub mod manga {
                 ],
                 created_at_since: None,
                 updated_at_since: None,
-                order: SortingOrder::descending(),
+                // MangaDex picks its own order
+                order: SortingOrder::default(),
                 includes: None,
                 has_available_chapters: true,
                 group: None,
@@ -255,11 +375,13 @@ pub mod chapter {
             }
         }
 
+        #[allow(dead_code)]
         pub fn set_limit(mut self, limit: u32) -> Self {
             self.limit = limit;
             self
         }
 
+        #[allow(dead_code)]
         pub fn set_offset(mut self, offset: u32) -> Self {
             self.offset = offset;
             self
@@ -269,6 +391,11 @@ pub mod chapter {
             self.order = order;
             self
         }
+
+        pub fn set_language(mut self, language: &str) -> Self {
+            self.translated_language = Some(vec![language.to_string()]);
+            self
+        }
     }
 
     impl Default for ChapterQuery {
@@ -314,7 +441,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::chapter::ChapterQuery;
-    use super::manga::SearchQuery;
+    use super::manga::*;
 
     #[test]
     fn manga_query() {
@@ -340,20 +467,93 @@ mod tests {
             &contentRating[0]=safe\
             &contentRating[1]=suggestive\
             &contentRating[2]=erotica\
-            &order[title]=desc\
-            &order[year]=desc\
-            &order[createdAt]=desc\
-            &order[updatedAt]=desc\
-            &order[latestUploadedChapter]=desc\
-            &order[followedCount]=desc\
-            &order[relevance]=desc\
-            &order[rating]=desc\
             &hasAvailableChapters=1",
         );
 
         assert_eq!(result, correct_result)
     }
 
+    #[test]
+    fn filtered_manga_query() {
+        let filter = dto::Filter {
+            tag_mode: dto::TagMode::Any,
+            statuses: vec![dto::PublicationStatus::Completed],
+            content_ratings: vec![dto::ContentRating::Safe],
+            year: Some(2014),
+            ..Default::default()
+        };
+        let q = SearchQuery::new("Hanayome").set_filter(
+            &filter,
+            vec![String::from("romance-id")],
+            Vec::new(),
+        );
+        let result = serde_qs::to_string(&q).unwrap();
+
+        for part in [
+            "year=2014",
+            "includedTags[0]=romance-id",
+            "includedTagsMode=OR",
+            "&status[0]=completed&availableTranslatedLanguage",
+            "&contentRating[0]=safe&hasAvailableChapters",
+        ] {
+            assert!(result.contains(part), "{part} is missing from {result}");
+        }
+        assert!(!result.contains("excludedTags["));
+    }
+
+    #[test]
+    fn manga_query_fields() {
+        let fields = [
+            (
+                AVAILABLE_CHAPTERS,
+                dto::FieldValue::Select(String::from("any")),
+            ),
+            (
+                EXCLUDED_ORIGINAL_LANGUAGES,
+                dto::FieldValue::Text(String::from("ko, zh")),
+            ),
+        ];
+        let filter = dto::Filter {
+            fields: fields
+                .into_iter()
+                .map(|(id, value)| (id.to_string(), value))
+                .collect(),
+            ..Default::default()
+        };
+        let q = SearchQuery::new("Hanayome").set_filter(&filter, Vec::new(), Vec::new());
+        let result = serde_qs::to_string(&q).unwrap();
+
+        assert!(result.contains("&excludedOriginalLanguage[0]=ko&excludedOriginalLanguage[1]=zh&"));
+        assert!(result.ends_with("hasAvailableChapters=0"));
+    }
+
+    #[test]
+    fn sorted_manga_query() {
+        let filter = dto::Filter {
+            sort: dto::Sort {
+                key: Some(dto::SortKey::Relevance),
+                order: dto::Order::Descending,
+            },
+            ..Default::default()
+        };
+        let q = SearchQuery::new("Hanayome").set_filter(&filter, Vec::new(), Vec::new());
+        let result = serde_qs::to_string(&q).unwrap();
+
+        assert!(
+            result.contains("&contentRating[2]=erotica&order[relevance]=desc&hasAvailableChapters")
+        );
+    }
+
+    #[test]
+    fn browse_query() {
+        let q = SearchQuery::default().set_order(dto::BrowseMode::Popular.into());
+        let result = serde_qs::to_string(&q).unwrap();
+
+        assert!(!result.contains("title="));
+        assert!(result
+            .contains("&contentRating[2]=erotica&order[followedCount]=desc&hasAvailableChapters"));
+    }
+
     #[test]
     fn chapter_query() {
         let q = ChapterQuery::default();
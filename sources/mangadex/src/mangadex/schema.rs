@@ -1,3 +1,6 @@
+// These structs mirror the MangaDex API responses, not every field is used by the source
+#![allow(dead_code)]
+
 use serde::Deserialize;
 use std::collections::HashMap;
 
@@ -44,6 +47,15 @@ pub struct MangaListContainer {
     pub total: u32,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct TagListContainer {
+    pub response: String,
+    pub data: Vec<Tag>,
+    pub limit: u32,
+    pub offset: u32,
+    pub total: u32,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct MangaFeedContainer {
     pub response: String,
@@ -82,6 +94,13 @@ pub struct Relationship {
     #[serde(rename = "type")]
     pub rel_type: RelationshipType,
     pub related: Option<MangaRelationshipType>,
+    /// Only there when the relationship is included in the request
+    pub attributes: Option<RelationshipAttributes>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RelationshipAttributes {
+    pub name: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]